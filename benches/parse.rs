@@ -0,0 +1,31 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use env_sync::parse::{EnvFile, ParseOptions};
+use std::hint::black_box;
+
+/// Builds a file with `count` documented variables, some with inline comments.
+fn large_env_file(count: usize) -> String {
+  (0..count)
+    .map(|i| {
+      if i % 3 == 0 {
+        format!("# Setting {i}\nKEY_{i}=value_{i} # note {i}\n")
+      } else {
+        format!("KEY_{i}=value_{i}\n")
+      }
+    })
+    .collect()
+}
+
+fn bench_parse(c: &mut Criterion) {
+  let input = large_env_file(10_000);
+  let options = ParseOptions::default();
+
+  c.bench_function("parse strict", |b| {
+    b.iter(|| EnvFile::try_from(black_box(input.as_str())).unwrap())
+  });
+  c.bench_function("parse lossy", |b| {
+    b.iter(|| EnvFile::parse_lossy(black_box(input.as_str()), &options))
+  });
+}
+
+criterion_group!(benches, bench_parse);
+criterion_main!(benches);
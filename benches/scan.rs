@@ -0,0 +1,38 @@
+//! Compares the line scans used by the parser with and without the `fast-parse` feature.
+//!
+//! Run `cargo bench --bench parse` with and without `--features fast-parse` to compare
+//! the effect on whole-file parsing.
+
+use criterion::{Criterion, criterion_group, criterion_main};
+use std::hint::black_box;
+
+fn large_env_file(count: usize) -> String {
+  (0..count)
+    .map(|i| format!("SOME_LONGER_KEY_NAME_{i}=a_reasonably_long_value_{i} # note {i}\n"))
+    .collect()
+}
+
+fn bench_scan(c: &mut Criterion) {
+  let input = large_env_file(10_000);
+
+  c.bench_function("scan str::find", |b| {
+    b.iter(|| {
+      black_box(input.as_str()).lines().for_each(|line| {
+        black_box((line.find('='), line.find('#')));
+      })
+    })
+  });
+  c.bench_function("scan memchr", |b| {
+    b.iter(|| {
+      black_box(input.as_str()).lines().for_each(|line| {
+        black_box((
+          memchr::memchr(b'=', line.as_bytes()),
+          memchr::memchr(b'#', line.as_bytes()),
+        ));
+      })
+    })
+  });
+}
+
+criterion_group!(benches, bench_scan);
+criterion_main!(benches);
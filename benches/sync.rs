@@ -0,0 +1,39 @@
+use criterion::{Criterion, criterion_group, criterion_main};
+use env_sync::parse::EnvFile;
+use env_sync::sync::EnvSync;
+use std::hint::black_box;
+
+/// Builds a file with `count` documented variables, leaving every other value empty.
+fn large_env_file(count: usize, empty_values: bool) -> String {
+  (0..count)
+    .map(|i| {
+      let value = if empty_values && i % 2 == 0 {
+        String::new()
+      } else {
+        format!("value_{i}")
+      };
+      if i % 3 == 0 {
+        format!("# Setting {i}\nKEY_{i}={value} # note {i}\n")
+      } else {
+        format!("KEY_{i}={value}\n")
+      }
+    })
+    .collect()
+}
+
+fn bench_sync(c: &mut Criterion) {
+  let local_input = large_env_file(10_000, false);
+  let template_input = large_env_file(10_000, true);
+  let local = EnvFile::try_from(local_input.as_str()).unwrap();
+  let template = EnvFile::try_from(template_input.as_str()).unwrap();
+
+  c.bench_function("sync owned", |b| {
+    b.iter(|| EnvSync::sync_files(black_box(&local), template.clone()).unwrap())
+  });
+  c.bench_function("sync plan", |b| {
+    b.iter(|| EnvSync::plan(black_box(&local), black_box(&template)))
+  });
+}
+
+criterion_group!(benches, bench_sync);
+criterion_main!(benches);
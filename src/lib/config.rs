@@ -0,0 +1,247 @@
+//! Project configuration file.
+//!
+//! A `.env-sync.toml` in the working directory provides default options, so a project
+//! doesn't need to repeat the same flags on every run. Command-line flags take precedence
+//! over the config file.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use env_sync::config::Config;
+//! use env_sync::sync::EnvSyncOptions;
+//!
+//! let config = Config::from_toml(
+//!   r#"
+//! template = ".env.example"
+//! tidy = true
+//!
+//! [set]
+//! DEBUG = "false"
+//! "#,
+//! )
+//! .unwrap();
+//!
+//! let mut options = EnvSyncOptions::default();
+//! config.apply(&mut options);
+//! assert!(options.tidy);
+//! ```
+
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::sync::{EnvSyncOptions, TemplateSource};
+
+/// Name of the config file looked up by [`Config::discover`].
+pub const CONFIG_FILENAME: &str = ".env-sync.toml";
+
+/// Default options read from a `.env-sync.toml` file.
+///
+/// Every field is optional, unset fields leave the corresponding option untouched.
+#[derive(Debug, Clone, PartialEq, Default, Deserialize)]
+#[serde(default, deny_unknown_fields, rename_all = "kebab-case")]
+pub struct Config {
+  /// See [`EnvSyncOptions::local_file`]
+  pub local: Option<PathBuf>,
+  /// See [`EnvSyncOptions::local_files`]. With `local` set too, or `--local` on the command
+  /// line, these are only read as layers below the written file.
+  pub local_files: Vec<PathBuf>,
+  /// Template file, see [`EnvSyncOptions::template_source`]
+  pub template: Option<PathBuf>,
+  /// See [`EnvSyncOptions::header`]
+  pub header: Option<String>,
+  /// See [`EnvSyncOptions::allow_empty_template`]
+  pub allow_empty_template: Option<bool>,
+  /// See [`EnvSyncOptions::allow_duplicate_template_keys`]
+  pub allow_duplicate_template_keys: Option<bool>,
+  /// See [`EnvSyncOptions::consolidate_comments`]
+  pub consolidate_comments: Option<bool>,
+  /// See [`EnvSyncOptions::nonsecret_allowlist`]
+  pub nonsecret_allowlist: Option<PathBuf>,
+  /// See [`EnvSyncOptions::follow_symlinks`]
+  pub follow_symlinks: Option<bool>,
+  /// See [`EnvSyncOptions::tidy`]
+  pub tidy: Option<bool>,
+  /// See [`EnvSyncOptions::defaults_file`]
+  pub defaults_file: Option<PathBuf>,
+  /// See [`EnvSyncOptions::minimal_rewrite`]
+  pub minimal_rewrite: Option<bool>,
+  /// See [`EnvSyncOptions::group_by_prefix`]
+  pub group_by_prefix: Option<bool>,
+  /// See [`EnvSyncOptions::group_headers`]
+  pub group_headers: Option<bool>,
+  /// See [`EnvSyncOptions::fill_from_env`]
+  pub fill_from_env: Option<bool>,
+  /// See [`EnvSyncOptions::keep_local_header`]
+  pub keep_local_header: Option<bool>,
+  /// See [`EnvSyncOptions::prune`]
+  pub prune: Option<bool>,
+  /// See [`EnvSyncOptions::backup`]
+  pub backup: Option<bool>,
+  /// See [`EnvSyncOptions::prefer_local`]
+  pub prefer_local: Option<bool>,
+  /// See [`EnvSyncOptions::keys_only`]
+  pub keys_only: Option<bool>,
+  /// See [`EnvSyncOptions::region`]
+  pub region: Option<String>,
+  /// See [`EnvSyncOptions::file_mode`], e.g. `file-mode = 0o600`
+  pub file_mode: Option<u32>,
+  /// Values forced onto keys, see [`EnvSyncOptions::overrides`]
+  pub set: BTreeMap<String, String>,
+  /// Inline comments set on keys, see [`EnvSyncOptions::comments`]
+  pub comments: BTreeMap<String, String>,
+  /// Keys removed from the output, see [`EnvSyncOptions::deletions`]
+  pub delete: Vec<String>,
+}
+
+/// Errors that can occur while loading a config file.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+  /// Error reading the config file
+  #[error("Config file IO error: {0}")]
+  Io(std::io::Error),
+  /// The config file isn't valid TOML or contains unknown options
+  #[error("Config file parse error: {0}")]
+  Parse(toml::de::Error),
+}
+
+impl Config {
+  /// Parses a config from TOML content.
+  pub fn from_toml(s: &str) -> Result<Self, ConfigError> {
+    toml::from_str(s).map_err(ConfigError::Parse)
+  }
+
+  /// Reads and parses the config file at `path`.
+  pub fn load(path: &Path) -> Result<Self, ConfigError> {
+    let content = std::fs::read_to_string(path).map_err(ConfigError::Io)?;
+    Self::from_toml(&content)
+  }
+
+  /// Loads the [`CONFIG_FILENAME`] file in `dir`, or returns `None` if there is none.
+  pub fn discover(dir: &Path) -> Result<Option<Self>, ConfigError> {
+    let path = dir.join(CONFIG_FILENAME);
+    if !path.is_file() {
+      return Ok(None);
+    }
+    Self::load(&path).map(Some)
+  }
+
+  /// Sets the options configured in the file, leaving the others as they are.
+  ///
+  /// Keyed options (`set`, `comments`, `delete`) are added before the existing entries, so
+  /// entries given on the command line afterwards win.
+  pub fn apply(self, options: &mut EnvSyncOptions) {
+    if let Some(local) = self.local {
+      options.local_file = Some(local);
+    }
+    if !self.local_files.is_empty() {
+      options.local_files = self.local_files;
+    }
+    if let Some(template) = self.template {
+      options.template_source = TemplateSource::File(template);
+    }
+    if let Some(header) = self.header {
+      options.header = Some(header);
+    }
+    if let Some(path) = self.nonsecret_allowlist {
+      options.nonsecret_allowlist = Some(path);
+    }
+    if let Some(path) = self.defaults_file {
+      options.defaults_file = Some(path);
+    }
+    if let Some(region) = self.region {
+      options.region = Some(region);
+    }
+    if let Some(mode) = self.file_mode {
+      options.file_mode = Some(mode);
+    }
+
+    let flags = [
+      (self.allow_empty_template, &mut options.allow_empty_template),
+      (
+        self.allow_duplicate_template_keys,
+        &mut options.allow_duplicate_template_keys,
+      ),
+      (self.consolidate_comments, &mut options.consolidate_comments),
+      (self.follow_symlinks, &mut options.follow_symlinks),
+      (self.tidy, &mut options.tidy),
+      (self.minimal_rewrite, &mut options.minimal_rewrite),
+      (self.group_by_prefix, &mut options.group_by_prefix),
+      (self.group_headers, &mut options.group_headers),
+      (self.fill_from_env, &mut options.fill_from_env),
+      (self.keep_local_header, &mut options.keep_local_header),
+      (self.prune, &mut options.prune),
+      (self.backup, &mut options.backup),
+      (self.prefer_local, &mut options.prefer_local),
+      (self.keys_only, &mut options.keys_only),
+    ];
+    for (value, option) in flags {
+      if let Some(value) = value {
+        *option = value;
+      }
+    }
+
+    options.overrides.splice(0..0, self.set);
+    options.comments.splice(0..0, self.comments);
+    options.deletions.splice(0..0, self.delete);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use tempfile::TempDir;
+
+  #[test]
+  fn test_discover_and_apply() {
+    let temp_dir = TempDir::new().unwrap();
+    assert!(Config::discover(temp_dir.path()).unwrap().is_none());
+
+    std::fs::write(
+      temp_dir.path().join(CONFIG_FILENAME),
+      r#"
+template = "config/.env.example"
+tidy = true
+follow-symlinks = false
+delete = ["LEGACY_URL"]
+
+[set]
+DEBUG = "false"
+"#,
+    )
+    .unwrap();
+
+    let config = Config::discover(temp_dir.path()).unwrap().unwrap();
+    let mut options = EnvSyncOptions {
+      overrides: vec![("DEBUG".to_string(), "true".to_string())],
+      ..Default::default()
+    };
+    config.apply(&mut options);
+
+    assert_eq!(
+      options.template_source,
+      TemplateSource::File(PathBuf::from("config/.env.example"))
+    );
+    assert!(options.tidy);
+    assert!(!options.follow_symlinks);
+    assert!(options.allow_empty_template);
+    assert!(options.local_file.is_none());
+    assert_eq!(options.deletions, vec!["LEGACY_URL".to_string()]);
+    assert_eq!(
+      options.overrides,
+      vec![
+        ("DEBUG".to_string(), "false".to_string()),
+        ("DEBUG".to_string(), "true".to_string()),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_unknown_option() {
+    assert!(matches!(
+      Config::from_toml("sort = true"),
+      Err(ConfigError::Parse(_))
+    ));
+  }
+}
@@ -0,0 +1,643 @@
+//! Key-level comparison of environment files.
+//!
+//! This module computes which variables were added, removed, or had their value
+//! changed between two parsed files. Comments and ordering are not considered,
+//! so only differences that affect the effective configuration are reported.
+//! For line-level updates that keep untouched lines intact, see [`EnvFile::edit_script`].
+//!
+//! # Examples
+//!
+//! ```rust
+//! use env_sync::diff::{Change, DiffOptions, EnvDiff};
+//! use env_sync::parse::EnvFile;
+//!
+//! let old = EnvFile::try_from("FLAG=true\nPORT=80").unwrap();
+//! let new = EnvFile::try_from("FLAG=TRUE\nPORT=8080").unwrap();
+//!
+//! let options = DiffOptions {
+//!     ignore_case_values: true,
+//! };
+//! let diff = EnvDiff::with_options(&old, &new, &options);
+//!
+//! assert_eq!(
+//!     diff.changes,
+//!     vec![Change::Modified {
+//!         key: "PORT".to_string(),
+//!         old: "80".to_string(),
+//!         new: "8080".to_string(),
+//!     }]
+//! );
+//! ```
+
+use std::borrow::Cow;
+use std::collections::HashSet;
+use std::fmt;
+
+use crate::parse::{EnvEntry, EnvFile};
+
+/// Options controlling how two files are compared.
+#[derive(Debug, Clone, Default)]
+pub struct DiffOptions {
+  /// Treat values that differ only by letter case (e.g. `true` vs `TRUE`) as unchanged.
+  pub ignore_case_values: bool,
+}
+
+impl DiffOptions {
+  /// Returns whether two values are considered equal under these options.
+  pub fn values_equal(&self, a: &str, b: &str) -> bool {
+    if self.ignore_case_values {
+      a.chars()
+        .flat_map(char::to_lowercase)
+        .eq(b.chars().flat_map(char::to_lowercase))
+    } else {
+      a == b
+    }
+  }
+}
+
+/// A single key-level difference between two environment files.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Change {
+  /// The key only exists in the new file
+  Added { key: String, value: String },
+  /// The key only exists in the old file
+  Removed { key: String, value: String },
+  /// The key exists in both files with different values
+  Modified {
+    key: String,
+    old: String,
+    new: String,
+  },
+}
+
+/// Formats the change as one line, e.g. `+ KEY=value`, `- KEY=value` or `~ KEY: old -> new`.
+impl fmt::Display for Change {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Change::Added { key, value } => write!(f, "+ {}={}", key, value),
+      Change::Removed { key, value } => write!(f, "- {}={}", key, value),
+      Change::Modified { key, old, new } => write!(f, "~ {}: {} -> {}", key, old, new),
+    }
+  }
+}
+
+impl Change {
+  /// Returns the key the change applies to.
+  pub fn key(&self) -> &str {
+    match self {
+      Change::Added { key, .. } | Change::Removed { key, .. } | Change::Modified { key, .. } => key,
+    }
+  }
+
+  /// Renders a character-level diff of a modified value, e.g. `543[-2-]{+3+}`.
+  ///
+  /// Removed segments are wrapped in `[-...-]` and inserted segments in `{+...+}`.
+  /// Returns `None` for additions and removals.
+  #[cfg(feature = "diff")]
+  pub fn inline_diff(&self) -> Option<String> {
+    match self {
+      Change::Modified { old, new, .. } => Some(inline_value_diff(old, new)),
+      _ => None,
+    }
+  }
+}
+
+/// Renders a character-level diff between two values.
+#[cfg(feature = "diff")]
+fn inline_value_diff(old: &str, new: &str) -> String {
+  use similar::{ChangeTag, TextDiff};
+
+  fn markers(tag: ChangeTag) -> (&'static str, &'static str) {
+    match tag {
+      ChangeTag::Equal => ("", ""),
+      ChangeTag::Delete => ("[-", "-]"),
+      ChangeTag::Insert => ("{+", "+}"),
+    }
+  }
+
+  let diff = TextDiff::from_chars(old, new);
+  let mut rendered = String::new();
+  let mut current = None;
+
+  for change in diff.iter_all_changes() {
+    if current != Some(change.tag()) {
+      if let Some(tag) = current {
+        rendered.push_str(markers(tag).1);
+      }
+      rendered.push_str(markers(change.tag()).0);
+      current = Some(change.tag());
+    }
+    rendered.push_str(change.value());
+  }
+
+  if let Some(tag) = current {
+    rendered.push_str(markers(tag).1);
+  }
+
+  rendered
+}
+
+/// The set of key-level changes between two environment files.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct EnvDiff {
+  /// Added and modified keys in the order of the new file, followed by removed keys
+  /// in the order of the old file.
+  pub changes: Vec<Change>,
+}
+
+impl EnvDiff {
+  /// Compares two files using the default options.
+  pub fn new(old: &EnvFile, new: &EnvFile) -> Self {
+    Self::with_options(old, new, &DiffOptions::default())
+  }
+
+  /// Compares two files using the provided options.
+  ///
+  /// When a key is defined more than once, only its first definition is compared,
+  /// matching [`EnvFile::get`].
+  pub fn with_options(old: &EnvFile, new: &EnvFile, options: &DiffOptions) -> Self {
+    let mut changes = Vec::new();
+    let mut seen = HashSet::new();
+
+    for var in new.variables() {
+      if !seen.insert(var.name()) {
+        continue;
+      }
+
+      match old.get(var.name()) {
+        None => changes.push(Change::Added {
+          key: var.name().to_string(),
+          value: var.value.to_string(),
+        }),
+        Some(old_var) if !options.values_equal(&old_var.value, &var.value) => {
+          changes.push(Change::Modified {
+            key: var.name().to_string(),
+            old: old_var.value.to_string(),
+            new: var.value.to_string(),
+          })
+        }
+        Some(_) => {}
+      }
+    }
+
+    let mut removed = HashSet::new();
+    for var in old.variables() {
+      if !seen.contains(var.name()) && removed.insert(var.name()) {
+        changes.push(Change::Removed {
+          key: var.name().to_string(),
+          value: var.value.to_string(),
+        });
+      }
+    }
+
+    Self { changes }
+  }
+
+  /// Returns `true` if the files are equivalent.
+  pub fn is_empty(&self) -> bool {
+    self.changes.is_empty()
+  }
+}
+
+/// Compares two streams of `(key, value)` pairs sorted by key, calling `on_change` for
+/// every difference.
+///
+/// Unlike [`EnvDiff`], no structure is built: the streams are walked side by side, so
+/// memory stays constant regardless of the input size. Changes are reported in key order.
+/// When a key repeats, only its first pair is compared, matching [`EnvDiff`] for a stable
+/// sort of the same files.
+///
+/// Both streams must be sorted by key (byte-wise, as `str` orders), otherwise changes are
+/// misreported. Files that aren't sorted have to be sorted first, which gives up the
+/// memory bound.
+pub fn diff_streaming<O, N, K, V, F>(old: O, new: N, options: &DiffOptions, mut on_change: F)
+where
+  O: IntoIterator<Item = (K, V)>,
+  N: IntoIterator<Item = (K, V)>,
+  K: AsRef<str>,
+  V: AsRef<str>,
+  F: FnMut(Change),
+{
+  let mut old = FirstPerKey(old.into_iter().peekable());
+  let mut new = FirstPerKey(new.into_iter().peekable());
+  let mut old_next = old.next();
+  let mut new_next = new.next();
+
+  loop {
+    match (old_next.take(), new_next.take()) {
+      (None, None) => break,
+      (Some((key, value)), None) => {
+        on_change(Change::Removed {
+          key: key.as_ref().to_string(),
+          value: value.as_ref().to_string(),
+        });
+        old_next = old.next();
+      }
+      (None, Some((key, value))) => {
+        on_change(Change::Added {
+          key: key.as_ref().to_string(),
+          value: value.as_ref().to_string(),
+        });
+        new_next = new.next();
+      }
+      (Some((old_key, old_value)), Some((new_key, new_value))) => {
+        match old_key.as_ref().cmp(new_key.as_ref()) {
+          std::cmp::Ordering::Less => {
+            on_change(Change::Removed {
+              key: old_key.as_ref().to_string(),
+              value: old_value.as_ref().to_string(),
+            });
+            old_next = old.next();
+            new_next = Some((new_key, new_value));
+          }
+          std::cmp::Ordering::Greater => {
+            on_change(Change::Added {
+              key: new_key.as_ref().to_string(),
+              value: new_value.as_ref().to_string(),
+            });
+            old_next = Some((old_key, old_value));
+            new_next = new.next();
+          }
+          std::cmp::Ordering::Equal => {
+            if !options.values_equal(old_value.as_ref(), new_value.as_ref()) {
+              on_change(Change::Modified {
+                key: new_key.as_ref().to_string(),
+                old: old_value.as_ref().to_string(),
+                new: new_value.as_ref().to_string(),
+              });
+            }
+            old_next = old.next();
+            new_next = new.next();
+          }
+        }
+      }
+    }
+  }
+}
+
+/// Yields the first pair of every run of equal keys in a sorted stream.
+struct FirstPerKey<I: Iterator>(std::iter::Peekable<I>);
+
+impl<I, K, V> Iterator for FirstPerKey<I>
+where
+  I: Iterator<Item = (K, V)>,
+  K: AsRef<str>,
+{
+  type Item = (K, V);
+
+  fn next(&mut self) -> Option<Self::Item> {
+    let first = self.0.next()?;
+    while self
+      .0
+      .next_if(|(key, _)| key.as_ref() == first.0.as_ref())
+      .is_some()
+    {}
+    Some(first)
+  }
+}
+
+/// A single line-level edit produced by [`EnvFile::edit_script`].
+///
+/// Indices refer to entries of the file as it is after applying all previous operations.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EditOp<'a> {
+  /// Insert an entry before the entry at `index`
+  Insert { index: usize, entry: EnvEntry<'a> },
+  /// Remove the entry at `index`
+  Delete { index: usize },
+  /// Replace the value of the variable at `index`, keeping its comments
+  SetValue { index: usize, value: Cow<'a, str> },
+}
+
+impl<'a> EnvFile<'a> {
+  /// Computes the ordered operations that transform this file into `target`.
+  ///
+  /// Entries are aligned with a longest common subsequence, matching variables by key and
+  /// other entries by content, so untouched lines produce no operations. A variable whose
+  /// value changed becomes a [`EditOp::SetValue`]; if its comments changed too, it is
+  /// deleted and re-inserted.
+  pub fn edit_script(&self, target: &EnvFile<'a>) -> Vec<EditOp<'a>> {
+    fn matchable(a: &EnvEntry, b: &EnvEntry) -> bool {
+      match (a, b) {
+        (EnvEntry::Variable(a), EnvEntry::Variable(b)) => a.key == b.key,
+        _ => a == b,
+      }
+    }
+
+    let (old, new) = (&self.entries, &target.entries);
+    let (n, m) = (old.len(), new.len());
+
+    // lcs[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lcs = vec![vec![0usize; m + 1]; n + 1];
+    for i in (0..n).rev() {
+      for j in (0..m).rev() {
+        lcs[i][j] = if matchable(&old[i], &new[j]) {
+          lcs[i + 1][j + 1] + 1
+        } else {
+          lcs[i + 1][j].max(lcs[i][j + 1])
+        };
+      }
+    }
+
+    let mut ops = Vec::new();
+    let (mut i, mut j, mut index) = (0, 0, 0);
+    while i < n || j < m {
+      if i < n && j < m && matchable(&old[i], &new[j]) && lcs[i][j] == lcs[i + 1][j + 1] + 1 {
+        if let (EnvEntry::Variable(old_var), EnvEntry::Variable(new_var)) = (&old[i], &new[j])
+          && old_var != new_var
+        {
+          let mut updated = old_var.clone();
+          updated.value = new_var.value.clone();
+          if updated == *new_var {
+            ops.push(EditOp::SetValue {
+              index,
+              value: new_var.value.clone(),
+            });
+          } else {
+            ops.push(EditOp::Delete { index });
+            ops.push(EditOp::Insert {
+              index,
+              entry: new[j].clone(),
+            });
+          }
+        }
+        i += 1;
+        j += 1;
+        index += 1;
+      } else if j < m && (i == n || lcs[i][j + 1] >= lcs[i + 1][j]) {
+        ops.push(EditOp::Insert {
+          index,
+          entry: new[j].clone(),
+        });
+        j += 1;
+        index += 1;
+      } else {
+        ops.push(EditOp::Delete { index });
+        i += 1;
+      }
+    }
+
+    ops
+  }
+
+  /// Applies operations produced by [`EnvFile::edit_script`] in order.
+  ///
+  /// # Panics
+  ///
+  /// Panics if an operation refers to an index outside the file, or if a
+  /// [`EditOp::SetValue`] targets an entry that isn't a variable.
+  pub fn apply_edits(&mut self, ops: impl IntoIterator<Item = EditOp<'a>>) {
+    for op in ops {
+      match op {
+        EditOp::Insert { index, entry } => self.entries.insert(index, entry),
+        EditOp::Delete { index } => {
+          self.entries.remove(index);
+        }
+        EditOp::SetValue { index, value } => match &mut self.entries[index] {
+          EnvEntry::Variable(var) => var.value = value,
+          _ => panic!("SetValue targets entry {} which is not a variable", index),
+        },
+      }
+    }
+  }
+
+  /// Returns the key-level changes between a previous parse of the same file and this one.
+  ///
+  /// Produces the same result as [`EnvDiff::new`], but when the variable keys are unchanged
+  /// and in the same order (the usual single-edit case) it only compares values in place.
+  pub fn changes_since(&self, previous: &EnvFile) -> Vec<Change> {
+    let same_keys = self.variables().count() == previous.variables().count()
+      && self
+        .variables()
+        .zip(previous.variables())
+        .all(|(current, old)| current.name() == old.name());
+
+    if !same_keys {
+      return EnvDiff::new(previous, self).changes;
+    }
+
+    let mut seen = HashSet::new();
+    self
+      .variables()
+      .zip(previous.variables())
+      .filter(|(current, _)| seen.insert(current.name()))
+      .filter(|(current, old)| current.value != old.value)
+      .map(|(current, old)| Change::Modified {
+        key: current.name().to_string(),
+        old: old.value.to_string(),
+        new: current.value.to_string(),
+      })
+      .collect()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_diff_added_removed_modified() {
+    let old: EnvFile = "KEEP=1\nCHANGE=old\nGONE=x".try_into().unwrap();
+    let new: EnvFile = "# comment\nKEEP=1\nCHANGE=new\nNEW=y".try_into().unwrap();
+
+    let diff = EnvDiff::new(&old, &new);
+
+    assert_eq!(
+      diff.changes,
+      vec![
+        Change::Modified {
+          key: "CHANGE".to_string(),
+          old: "old".to_string(),
+          new: "new".to_string(),
+        },
+        Change::Added {
+          key: "NEW".to_string(),
+          value: "y".to_string(),
+        },
+        Change::Removed {
+          key: "GONE".to_string(),
+          value: "x".to_string(),
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_change_display() {
+    let old: EnvFile = "CHANGE=old\nGONE=x".try_into().unwrap();
+    let new: EnvFile = "CHANGE=new\nADDED=1".try_into().unwrap();
+
+    let lines: Vec<String> = EnvDiff::new(&old, &new)
+      .changes
+      .iter()
+      .map(ToString::to_string)
+      .collect();
+    assert_eq!(lines, vec!["~ CHANGE: old -> new", "+ ADDED=1", "- GONE=x"]);
+    assert_eq!(EnvDiff::new(&old, &new).changes[2].key(), "GONE");
+  }
+
+  #[test]
+  fn test_diff_streaming_matches_env_diff() {
+    let generate = |count: usize, modulo: usize, offset: usize| {
+      let mut content = String::new();
+      for i in offset..offset + count {
+        content.push_str(&format!("KEY_{:06}=value_{}\n", i, i % modulo));
+      }
+      // A repeated key, where only the first definition counts
+      content.push_str(&format!("KEY_{:06}=duplicate\n", offset));
+      content
+    };
+    let old_content = generate(10_000, 7, 0);
+    let new_content = generate(10_000, 5, 2_500);
+    let old: EnvFile = old_content.as_str().try_into().unwrap();
+    let new: EnvFile = new_content.as_str().try_into().unwrap();
+
+    let sorted_pairs = |file: &EnvFile| {
+      let mut pairs: Vec<(String, String)> = file
+        .variables()
+        .map(|var| (var.key.to_string(), var.value.to_string()))
+        .collect();
+      pairs.sort_by(|a, b| a.0.cmp(&b.0));
+      pairs
+    };
+    let mut streamed = Vec::new();
+    diff_streaming(
+      sorted_pairs(&old),
+      sorted_pairs(&new),
+      &DiffOptions::default(),
+      |change| streamed.push(change),
+    );
+
+    let key = |change: &Change| match change {
+      Change::Added { key, .. } | Change::Removed { key, .. } | Change::Modified { key, .. } => {
+        key.clone()
+      }
+    };
+    let mut expected = EnvDiff::new(&old, &new).changes;
+    expected.sort_by_key(key);
+
+    assert!(streamed.len() > 5_000);
+    assert!(streamed.is_sorted_by_key(key));
+    assert_eq!(streamed, expected);
+  }
+
+  #[test]
+  fn test_diff_ignore_case_values() {
+    let old: EnvFile = "FLAG=true".try_into().unwrap();
+    let new: EnvFile = "FLAG=TRUE".try_into().unwrap();
+
+    assert!(!EnvDiff::new(&old, &new).is_empty());
+
+    let options = DiffOptions {
+      ignore_case_values: true,
+    };
+    assert!(EnvDiff::with_options(&old, &new, &options).is_empty());
+  }
+
+  #[test]
+  fn test_changes_since_value_edit() {
+    let previous: EnvFile = "# Docs\nHOST=localhost\nPORT=5432".try_into().unwrap();
+    let current: EnvFile = "# Docs\nHOST=localhost\nPORT=5433".try_into().unwrap();
+
+    assert_eq!(
+      current.changes_since(&previous),
+      vec![Change::Modified {
+        key: "PORT".to_string(),
+        old: "5432".to_string(),
+        new: "5433".to_string(),
+      }]
+    );
+    assert!(current.changes_since(&current).is_empty());
+  }
+
+  #[test]
+  fn test_changes_since_addition() {
+    let previous: EnvFile = "HOST=localhost".try_into().unwrap();
+    let current: EnvFile = "HOST=localhost\nPORT=5432".try_into().unwrap();
+
+    assert_eq!(
+      current.changes_since(&previous),
+      vec![Change::Added {
+        key: "PORT".to_string(),
+        value: "5432".to_string(),
+      }]
+    );
+  }
+
+  #[test]
+  fn test_changes_since_deletion() {
+    let previous: EnvFile = "HOST=localhost\nPORT=5432".try_into().unwrap();
+    let current: EnvFile = "PORT=5432".try_into().unwrap();
+
+    assert_eq!(
+      current.changes_since(&previous),
+      vec![Change::Removed {
+        key: "HOST".to_string(),
+        value: "localhost".to_string(),
+      }]
+    );
+  }
+
+  #[test]
+  fn test_edit_script_value_change() {
+    let local: EnvFile = "# Docs\nHOST=localhost # primary\n\nPORT=5432"
+      .try_into()
+      .unwrap();
+    let target: EnvFile = "# Docs\nHOST=db.internal # primary\n\nPORT=5432"
+      .try_into()
+      .unwrap();
+
+    let ops = local.edit_script(&target);
+    assert_eq!(
+      ops,
+      vec![EditOp::SetValue {
+        index: 0,
+        value: "db.internal".into(),
+      }]
+    );
+
+    let mut edited = local.clone();
+    edited.apply_edits(ops);
+    assert_eq!(edited, target);
+  }
+
+  #[test]
+  fn test_edit_script_insertion() {
+    let local: EnvFile = "HOST=localhost\nPORT=5432".try_into().unwrap();
+    let target: EnvFile = "HOST=localhost\nUSER=app\nPORT=5432".try_into().unwrap();
+
+    let ops = local.edit_script(&target);
+    assert_eq!(
+      ops,
+      vec![EditOp::Insert {
+        index: 1,
+        entry: target.entries[1].clone(),
+      }]
+    );
+
+    let mut edited = local.clone();
+    edited.apply_edits(ops);
+    assert_eq!(edited, target);
+    assert!(target.edit_script(&target).is_empty());
+  }
+
+  #[cfg(feature = "diff")]
+  #[test]
+  fn test_inline_diff_highlights_changed_segment() {
+    let old: EnvFile = "DB_URL=postgres://localhost:5432/app".try_into().unwrap();
+    let new: EnvFile = "DB_URL=postgres://localhost:5433/app".try_into().unwrap();
+
+    let diff = EnvDiff::new(&old, &new);
+
+    assert_eq!(
+      diff.changes[0].inline_diff().unwrap(),
+      "postgres://localhost:543[-2-]{+3+}/app"
+    );
+
+    let added = Change::Added {
+      key: "NEW".to_string(),
+      value: "1".to_string(),
+    };
+    assert!(added.inline_diff().is_none());
+  }
+}
@@ -0,0 +1,150 @@
+//! General-purpose merging of two environment files.
+//!
+//! Unlike [`crate::sync`], which reshapes a local file to follow a template, a merge keeps
+//! the structure of the base file and layers an overlay on top of it. Keys that only exist
+//! in the overlay are appended, together with their comments.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use env_sync::{merge_files, MergePolicy};
+//! use std::path::Path;
+//!
+//! let report = merge_files(
+//!     Path::new(".env"),
+//!     Path::new(".env.ci"),
+//!     Path::new(".env.merged"),
+//!     MergePolicy::TakeTheirs,
+//! )
+//! .unwrap();
+//!
+//! println!("{} keys changed", report.changes.len());
+//! ```
+
+use std::path::Path;
+
+#[cfg(feature = "tracing")]
+use tracing::{debug, trace};
+
+use crate::diff::EnvDiff;
+use crate::parse::{EnvEntry, EnvFile};
+use crate::secret::SecretPolicy;
+use crate::sync::{EnvSync, EnvSyncError, SyncReport};
+
+/// Which value wins when a key is defined in both files.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum MergePolicy {
+  /// Keep the value from the base file
+  #[default]
+  TakeOurs,
+  /// Use the value from the overlay file
+  TakeTheirs,
+}
+
+/// Merges `overlay` into `base` and writes the result to `out`.
+///
+/// The returned report describes the change from the base file to the merged output, with
+/// [`SyncReport::local_path`] set to `out`.
+pub fn merge_files(
+  base: &Path,
+  overlay: &Path,
+  out: &Path,
+  policy: MergePolicy,
+) -> Result<SyncReport, EnvSyncError> {
+  let base_str = EnvSync::read_env_file(base, EnvSyncError::LocalIo)?;
+  let overlay_str = EnvSync::read_env_file(overlay, EnvSyncError::OverlayIo)?;
+
+  let base_content: EnvFile = base_str
+    .as_str()
+    .try_into()
+    .map_err(EnvSyncError::LocalParse)?;
+  let overlay_content: EnvFile = overlay_str
+    .as_str()
+    .try_into()
+    .map_err(EnvSyncError::OverlayParse)?;
+
+  #[cfg(feature = "tracing")]
+  debug!("Merging {:?} into {:?} with {:?}", overlay, base, policy);
+
+  let merged = merge(&base_content, &overlay_content, policy);
+  let changes = EnvDiff::new(&base_content, &merged).changes;
+  let merged = merged.to_string();
+
+  std::fs::write(out, &merged).map_err(EnvSyncError::Write)?;
+
+  Ok(SyncReport {
+    local_path: out.to_path_buf(),
+    original: base_str,
+    synced: merged,
+    written: true,
+    changes,
+    deprecated: Vec::new(),
+    secrets: SecretPolicy::default(),
+  })
+}
+
+/// Layers the overlay variables on top of the base file.
+pub(crate) fn merge<'a>(
+  base: &EnvFile<'a>,
+  overlay: &EnvFile<'a>,
+  policy: MergePolicy,
+) -> EnvFile<'a> {
+  let mut merged = base.clone();
+
+  for var in overlay.variables() {
+    match merged.get_mut(var.name()) {
+      Some(existing) => {
+        if policy == MergePolicy::TakeTheirs {
+          #[cfg(feature = "tracing")]
+          trace!("Taking overlay value for {}", var.key);
+          existing.value = var.value.clone();
+          existing.heredoc = var.heredoc.clone();
+        }
+      }
+      None => {
+        #[cfg(feature = "tracing")]
+        trace!("Appending overlay variable {}", var.key);
+        merged.entries.push(EnvEntry::Variable(var.clone()));
+      }
+    }
+  }
+
+  merged
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_merge_policies() {
+    let base: EnvFile = "# Base docs\nHOST=localhost\nPORT=80".try_into().unwrap();
+    let overlay: EnvFile = "PORT=8080\n# Only in overlay\nDEBUG=1".try_into().unwrap();
+
+    let ours = merge(&base, &overlay, MergePolicy::TakeOurs);
+    assert_eq!(
+      ours.to_string(),
+      "# Base docs\nHOST=localhost\nPORT=80\n# Only in overlay\nDEBUG=1\n"
+    );
+
+    let theirs = merge(&base, &overlay, MergePolicy::TakeTheirs);
+    assert_eq!(
+      theirs.to_string(),
+      "# Base docs\nHOST=localhost\nPORT=8080\n# Only in overlay\nDEBUG=1\n"
+    );
+  }
+
+  #[test]
+  fn test_merge_heredoc_values() {
+    let base: EnvFile = "CERT=old\nKEY<<EOF\nline1\nline2\nEOF\n"
+      .try_into()
+      .unwrap();
+    let overlay: EnvFile = "CERT<<END\na\nb\nEND\nKEY=single\n".try_into().unwrap();
+
+    let theirs = merge(&base, &overlay, MergePolicy::TakeTheirs);
+    assert_eq!(theirs.to_string(), "CERT<<END\na\nb\nEND\nKEY=single\n");
+
+    let ours = merge(&base, &overlay, MergePolicy::TakeOurs);
+    assert_eq!(ours, base);
+  }
+}
@@ -9,21 +9,45 @@
 //! - **Zero-copy parsing**: Uses `Cow<str>` for efficient string handling
 //! - **Comment preservation**: Maintains both preceding and inline comments
 //! - **Flexible synchronization**: Merges template structure with local values
+//! - **Project config**: Default options from a `.env-sync.toml` file with the `config`
+//!   feature (enabled by default)
+//! - **JSON reports**: Machine-readable sync summaries with the `json` feature (enabled by
+//!   default)
 //! - **Optional tracing**: Detailed logging when the `tracing` feature is enabled
+//! - **`no_std` parsing**: Without the default `std` feature only the [`parse`] module is
+//!   built, requiring just `alloc`. The `env-sync` binary and its dependencies are behind
+//!   the default `cli` feature, so use `default-features = false` for library-only builds
 //!
 //! # Example
 //!
 //! ```rust,no_run
-//! use env_sync::sync::{EnvSync, EnvSyncOptions};
+//! use env_sync::sync::{EnvSync, EnvSyncOptions, TemplateSource};
 //! use std::path::PathBuf;
 //!
 //! let options = EnvSyncOptions {
 //!     local_file: None, // defaults to .env
-//!     template_file: PathBuf::from(".env.template"),
+//!     template_source: TemplateSource::File(PathBuf::from(".env.template")),
+//!     ..Default::default()
 //! };
 //!
 //! EnvSync::sync_with_options(options).unwrap();
 //! ```
 
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+#[cfg(feature = "config")]
+pub mod config;
+#[cfg(feature = "std")]
+pub mod diff;
+#[cfg(feature = "std")]
+pub mod merge;
 pub mod parse;
+#[cfg(feature = "std")]
+pub mod secret;
+#[cfg(feature = "std")]
 pub mod sync;
+
+#[cfg(feature = "std")]
+pub use merge::{MergePolicy, merge_files};
@@ -20,13 +20,40 @@
 //! println!("{}", env_file);
 //! ```
 
-use std::{borrow::Cow, convert::TryFrom, fmt};
+use alloc::{
+  borrow::Cow,
+  collections::BTreeMap,
+  format,
+  string::{String, ToString},
+  vec,
+  vec::Vec,
+};
+use core::{convert::TryFrom, fmt};
+#[cfg(feature = "std")]
+use std::collections::{HashMap, HashSet};
+
+#[cfg(feature = "std")]
+use crate::secret::SecretPolicy;
 
 #[cfg(feature = "tracing")]
-use tracing::{debug, trace};
+use tracing::{debug, trace, warn};
 
 const COMMENT_PREFIX: &str = "#";
 const ASSIGNMENT_OPERATOR: &str = "=";
+const DOCUMENT_SEPARATOR: &str = "---";
+const ANNOTATION_PREFIX: &str = "@";
+const BARE_KEY_VALUE: &str = "true";
+const HEREDOC_OPERATOR: &str = "<<";
+const REGION_START: &str = "region";
+const REGION_END: &str = "endregion";
+const MIN_DIVIDER_LEN: usize = 3;
+const TRUE_SPELLINGS: [&str; 5] = ["true", "yes", "y", "on", "1"];
+const FALSE_SPELLINGS: [&str; 5] = ["false", "no", "n", "off", "0"];
+/// Shell prefixes that don't change which variable a line defines, e.g. `export FOO=1`.
+const KEY_PREFIXES: [&str; 2] = [EXPORT_PREFIX, "set "];
+
+/// Prefix marking a variable as exported, see [`EnvVariable::exported`].
+const EXPORT_PREFIX: &str = "export ";
 
 /// Represents a parsed environment file with preserved comments.
 ///
@@ -37,12 +64,30 @@ pub struct EnvFile<'a> {
   pub entries: Vec<EnvEntry<'a>>,
 }
 
+/// Formats the file, padding every key to the formatter's width (e.g. `{:24}`) so values
+/// line up.
 impl<'a> fmt::Display for EnvFile<'a> {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    for entry in &self.entries {
-      write!(f, "{}", entry)?;
-    }
-    Ok(())
+    let key_column = KeyColumn::from_formatter(f);
+    self.write_with(f, &RenderOptions::default(), key_column.as_ref())
+  }
+}
+
+impl<'a> IntoIterator for EnvFile<'a> {
+  type Item = EnvEntry<'a>;
+  type IntoIter = vec::IntoIter<EnvEntry<'a>>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.entries.into_iter()
+  }
+}
+
+impl<'e, 'a> IntoIterator for &'e EnvFile<'a> {
+  type Item = &'e EnvEntry<'a>;
+  type IntoIter = core::slice::Iter<'e, EnvEntry<'a>>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.entries.iter()
   }
 }
 
@@ -50,17 +95,159 @@ impl<'a> TryFrom<&'a str> for EnvFile<'a> {
   type Error = ParseError;
 
   fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+    Self::parse_with_options(s, &ParseOptions::default())
+  }
+}
+
+impl<'a> EnvFile<'a> {
+  /// Renders the file using the provided output options.
+  ///
+  /// `env.to_string()` is equivalent to `env.render(&RenderOptions::default())`.
+  pub fn render(&self, options: &RenderOptions) -> String {
+    let mut out = String::new();
+    // Writing to a `String` cannot fail
+    let _ = self.write_with(&mut out, options, None);
+    out
+  }
+
+  fn write_with<W: fmt::Write>(
+    &self,
+    f: &mut W,
+    options: &RenderOptions,
+    key_column: Option<&KeyColumn>,
+  ) -> fmt::Result {
+    for entry in &self.entries {
+      entry.write_with(f, options, key_column)?;
+    }
+    Ok(())
+  }
+
+  /// Parses an env file using the provided options.
+  pub fn parse_with_options(s: &'a str, options: &ParseOptions) -> Result<Self, ParseError> {
+    Self::parse_entries(s, options, None)
+  }
+
+  /// Parses an env file, skipping lines that cannot be parsed.
+  ///
+  /// Returns the salvaged file along with an error for every skipped line. Comments
+  /// preceding a skipped line attach to the next variable.
+  pub fn parse_lossy(s: &'a str, options: &ParseOptions) -> (Self, Vec<ParseError>) {
+    let mut errors = Vec::new();
+    let file = Self::parse_entries(s, options, Some(&mut errors))
+      .expect("lossy parsing collects errors instead of failing");
+    (file, errors)
+  }
+
+  /// Checks raw env file content for likely mistakes that parsing silently accepts.
+  ///
+  /// Lines that cannot be parsed are ignored here, see [`EnvFile::lint`] for those.
+  pub fn validate(s: &str, options: &ParseOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Vec::new();
+    // Trimmed key -> (raw key, line number) of its first definition
+    let mut seen: BTreeMap<&str, (&str, usize)> = BTreeMap::new();
+
+    for (line_number, raw_key) in assignment_lines(s, options) {
+      let Some(raw_key) = raw_key else {
+        continue;
+      };
+
+      let key = raw_key.trim();
+      if !is_identifier(strip_key_prefix(key)) {
+        diagnostics.push(Diagnostic::InvalidKey {
+          key: key.to_string(),
+          line: line_number,
+        });
+      }
+
+      match seen.get(key) {
+        Some((first_raw, first_line)) if *first_raw != raw_key => {
+          diagnostics.push(Diagnostic::WhitespaceDuplicateKey {
+            key: key.to_string(),
+            first_line: *first_line,
+            line: line_number,
+          });
+        }
+        Some(_) => {}
+        None => {
+          seen.insert(key, (raw_key, line_number));
+        }
+      }
+    }
+
+    diagnostics
+  }
+
+  /// Reports every problem [`EnvFile::validate`] finds, plus lines that cannot be parsed
+  /// and keys defined more than once, ordered by line.
+  pub fn lint(s: &str, options: &ParseOptions) -> Vec<Diagnostic> {
+    let mut diagnostics = Self::validate(s, options);
+    // Trimmed key -> (raw key, line number) of its first definition
+    let mut seen: BTreeMap<&str, (&str, usize)> = BTreeMap::new();
+
+    for (line_number, raw_key) in assignment_lines(s, options) {
+      let Some(raw_key) = raw_key else {
+        diagnostics.push(Diagnostic::InvalidLine { line: line_number });
+        continue;
+      };
+
+      let key = raw_key.trim();
+      match seen.get(key) {
+        // Different whitespace is already reported by `validate`
+        Some((first_raw, first_line)) if *first_raw == raw_key => {
+          diagnostics.push(Diagnostic::DuplicateKey {
+            key: key.to_string(),
+            first_line: *first_line,
+            line: line_number,
+          });
+        }
+        Some(_) => {}
+        None => {
+          seen.insert(key, (raw_key, line_number));
+        }
+      }
+    }
+
+    diagnostics.sort_by_key(Diagnostic::line);
+    diagnostics
+  }
+
+  /// Parses all lines, either failing on the first invalid line or collecting the errors.
+  fn parse_entries(
+    s: &'a str,
+    options: &ParseOptions,
+    mut errors: Option<&mut Vec<ParseError>>,
+  ) -> Result<Self, ParseError> {
     #[cfg(feature = "tracing")]
     debug!("Parsing env file with {} lines", s.lines().count());
 
     let mut entries = Vec::new();
     let mut pending_comments = Vec::new();
+    let mut skipped_blank_lines = 0;
 
-    for line in s.lines() {
+    let mut lines = s.lines();
+    while let Some(line) = lines.next() {
       #[cfg(feature = "tracing")]
       trace!("Parsing line: {:?}", line);
 
-      let mut entry: EnvEntry = line.try_into()?;
+      let parsed = if let Some(opener) = EnvVariable::heredoc_opener(line) {
+        EnvVariable::parse_heredoc(opener, &mut lines).map(EnvEntry::Variable)
+      } else if let Some(var) = EnvVariable::parse_multiline_quoted(s, line, &mut lines) {
+        Ok(EnvEntry::Variable(var))
+      } else {
+        EnvEntry::parse_with_options(line, options)
+      };
+      let parsed = parsed.and_then(|entry| check_annotation(entry, s, line, options));
+
+      let mut entry = match (parsed, errors.as_mut()) {
+        (Ok(entry), _) => entry,
+        (Err(error), Some(errors)) => {
+          #[cfg(feature = "tracing")]
+          debug!("Skipping invalid line: {:?}", line);
+          errors.push(error);
+          continue;
+        }
+        (Err(error), None) => return Err(error),
+      };
 
       if let EnvEntry::Variable(ref mut var) = entry {
         #[cfg(feature = "tracing")]
@@ -70,13 +257,23 @@ impl<'a> TryFrom<&'a str> for EnvFile<'a> {
           pending_comments.len()
         );
 
-        var.preceding_comments = std::mem::take(&mut pending_comments);
+        var.preceding_comments = core::mem::take(&mut pending_comments);
+        skipped_blank_lines = 0;
       } else if let EnvEntry::OrphanComment(comment) = entry {
         #[cfg(feature = "tracing")]
         trace!("Found comment, adding to pending");
 
         pending_comments.push(comment);
         continue;
+      } else if matches!(entry, EnvEntry::EmptyLine)
+        && !pending_comments.is_empty()
+        && options.attach_across_blank
+      {
+        #[cfg(feature = "tracing")]
+        trace!("Empty line with pending comments, keeping them for the next variable");
+
+        skipped_blank_lines += 1;
+        continue;
       } else if matches!(entry, EnvEntry::EmptyLine) && !pending_comments.is_empty() {
         #[cfg(feature = "tracing")]
         trace!(
@@ -87,6 +284,23 @@ impl<'a> TryFrom<&'a str> for EnvFile<'a> {
         for comment in pending_comments.drain(..) {
           entries.push(EnvEntry::OrphanComment(comment));
         }
+      } else if matches!(
+        entry,
+        EnvEntry::RegionStart { .. } | EnvEntry::RegionEnd { .. }
+      ) {
+        #[cfg(feature = "tracing")]
+        trace!(
+          "Region marker, flushing {} pending comments",
+          pending_comments.len()
+        );
+
+        for comment in pending_comments.drain(..) {
+          entries.push(EnvEntry::OrphanComment(comment));
+        }
+        entries.extend(core::iter::repeat_n(
+          EnvEntry::EmptyLine,
+          core::mem::take(&mut skipped_blank_lines),
+        ));
       }
 
       entries.push(entry);
@@ -95,189 +309,2249 @@ impl<'a> TryFrom<&'a str> for EnvFile<'a> {
     for comment in pending_comments {
       entries.push(EnvEntry::OrphanComment(comment));
     }
+    entries.extend(core::iter::repeat_n(
+      EnvEntry::EmptyLine,
+      skipped_blank_lines,
+    ));
 
     #[cfg(feature = "tracing")]
     debug!("Parsed {} entries", entries.len());
 
     Ok(Self { entries })
   }
-}
 
-impl<'a> EnvFile<'a> {
+  /// Parses a bundle of env documents separated by `---` lines.
+  ///
+  /// Each document is parsed independently. A leading separator is optional, so
+  /// `---\nA=1\n---\nB=2` and `A=1\n---\nB=2` both yield two documents.
+  pub fn parse_multi(s: &'a str) -> Result<Vec<EnvFile<'a>>, ParseError> {
+    let mut documents = Vec::new();
+    let mut start = 0;
+    let mut offset = 0;
+
+    for line in s.split_inclusive('\n') {
+      if line.trim() == DOCUMENT_SEPARATOR {
+        documents.push(&s[start..offset]);
+        start = offset + line.len();
+      }
+      offset += line.len();
+    }
+    documents.push(&s[start..]);
+
+    if documents.len() > 1 && documents[0].trim().is_empty() {
+      documents.remove(0);
+    }
+
+    #[cfg(feature = "tracing")]
+    debug!("Parsing {} documents", documents.len());
+
+    documents.into_iter().map(EnvFile::try_from).collect()
+  }
+
   /// Finds an environment variable by its key.
   ///
   /// Returns the first variable with the matching key, or `None` if not found.
   pub fn get(&self, key: &str) -> Option<&EnvVariable<'a>> {
+    let name = strip_key_prefix(key);
     self.entries.iter().find_map(|entry| {
       if let EnvEntry::Variable(var) = entry {
-        if var.key == key { Some(var) } else { None }
+        if var.name() == name { Some(var) } else { None }
       } else {
         None
       }
     })
   }
-}
-
-/// Represents a single entry in an environment file.
-#[derive(Debug, Clone, PartialEq)]
-pub enum EnvEntry<'a> {
-  /// A variable assignment with optional comments
-  Variable(EnvVariable<'a>),
-  /// A comment not associated with a variable
-  OrphanComment(EnvComment<'a>),
-  /// An empty line
-  EmptyLine,
-}
 
-impl<'a> fmt::Display for EnvEntry<'a> {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    match self {
-      EnvEntry::Variable(var) => {
-        write!(f, "{}", var)?;
-        writeln!(f)
-      }
-      EnvEntry::OrphanComment(comment) => {
-        writeln!(f, "{}", comment)
-      }
-      EnvEntry::EmptyLine => {
-        writeln!(f)
+  /// Finds an environment variable by its key for modification.
+  ///
+  /// Returns the first variable with the matching key, or `None` if not found.
+  pub fn get_mut(&mut self, key: &str) -> Option<&mut EnvVariable<'a>> {
+    let name = strip_key_prefix(key);
+    self.entries.iter_mut().find_map(|entry| {
+      if let EnvEntry::Variable(var) = entry {
+        if var.name() == name { Some(var) } else { None }
+      } else {
+        None
       }
-    }
+    })
   }
-}
-
-impl<'a> TryFrom<&'a str> for EnvEntry<'a> {
-  type Error = ParseError;
 
-  fn try_from(s: &'a str) -> Result<Self, Self::Error> {
-    let trimmed = s.trim();
+  /// Sets the value of a variable.
+  ///
+  /// Updates the first variable with the matching key, or appends a new variable if none exists.
+  pub fn set(&mut self, key: impl Into<Cow<'a, str>>, value: impl Into<Cow<'a, str>>) {
+    let key = key.into();
+    let value = value.into();
 
-    if trimmed.is_empty() {
-      Ok(EnvEntry::EmptyLine)
-    } else if trimmed.starts_with(COMMENT_PREFIX) {
-      Ok(EnvEntry::OrphanComment(trimmed.try_into()?))
+    if let Some(var) = self.get_mut(&key) {
+      var.value = value;
     } else {
-      Ok(EnvEntry::Variable(trimmed.try_into()?))
+      self.push(EnvVariable::new(key, value));
     }
   }
-}
 
-/// Represents an environment variable with its value and associated comments.
-#[derive(Debug, Clone, PartialEq)]
-pub struct EnvVariable<'a> {
-  /// The variable name
-  pub key: Cow<'a, str>,
-  /// The variable value
-  pub value: Cow<'a, str>,
-  /// Comments that appear before this variable
-  pub preceding_comments: Vec<EnvComment<'a>>,
-  /// Comment that appears on the same line as the variable
-  pub inline_comment: Option<EnvComment<'a>>,
-}
+  /// Removes the first variable named `key` and returns it, or `None` if there is none.
+  ///
+  /// The comments preceding the variable are removed with it and stay attached to the
+  /// returned variable. Use [`EnvFile::remove_keep_comments`] to leave them in the file.
+  pub fn remove(&mut self, key: &str) -> Option<EnvVariable<'a>> {
+    self.remove_with(key, CommentOnRemove::Delete)
+  }
 
-impl<'a> fmt::Display for EnvVariable<'a> {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    for comment in &self.preceding_comments {
-      writeln!(f, "{}", comment)?;
-    }
-    write!(f, "{}{}{}", self.key, ASSIGNMENT_OPERATOR, self.value)?;
-    if let Some(comment) = &self.inline_comment {
-      write!(f, " {}", comment)?;
+  /// Removes the first variable named `key` like [`EnvFile::remove`], but keeps its
+  /// preceding comments in its place as orphan comments.
+  ///
+  /// The returned variable has no preceding comments.
+  pub fn remove_keep_comments(&mut self, key: &str) -> Option<EnvVariable<'a>> {
+    self.remove_with(key, CommentOnRemove::Orphan)
+  }
+
+  /// Removes the first variable named `key`, handling its preceding comments as `comments`
+  /// says, and returns it.
+  pub fn remove_with(&mut self, key: &str, comments: CommentOnRemove) -> Option<EnvVariable<'a>> {
+    let index = self.variable_index(key)?;
+    let EnvEntry::Variable(mut var) = self.entries.remove(index) else {
+      unreachable!("variable_index only returns variables");
+    };
+    if comments == CommentOnRemove::Orphan {
+      let preceding = core::mem::take(&mut var.preceding_comments);
+      self.entries.splice(
+        index..index,
+        preceding.into_iter().map(EnvEntry::OrphanComment),
+      );
     }
-    Ok(())
+    Some(var)
   }
-}
 
-impl<'a> TryFrom<&'a str> for EnvVariable<'a> {
-  type Error = ParseError;
+  /// Returns the index in `entries` of the first variable named `key`.
+  fn variable_index(&self, key: &str) -> Option<usize> {
+    let name = strip_key_prefix(key);
+    self
+      .entries
+      .iter()
+      .position(|entry| matches!(entry, EnvEntry::Variable(var) if var.name() == name))
+  }
 
-  fn try_from(s: &'a str) -> Result<Self, Self::Error> {
-    #[cfg(feature = "tracing")]
-    trace!("Parsing variable from: {:?}", s);
+  /// Appends an entry, such as a variable or an orphan comment, to the end of the file.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use env_sync::parse::{EnvComment, EnvEntry, EnvFile, EnvVariable};
+  ///
+  /// let mut env = EnvFile::default();
+  /// env.push(EnvComment::new(" Database"));
+  /// env.push(EnvVariable::new("DB_HOST", "localhost"));
+  /// env.push(EnvEntry::EmptyLine);
+  /// env.push(EnvVariable::new("DEBUG", "false"));
+  ///
+  /// assert_eq!(env.to_string(), "# Database\nDB_HOST=localhost\n\nDEBUG=false\n");
+  /// ```
+  pub fn push(&mut self, entry: impl Into<EnvEntry<'a>>) {
+    self.entries.push(entry.into());
+  }
 
-    if let Some((key, value_part)) = s.split_once(ASSIGNMENT_OPERATOR) {
-      let key = key.trim();
+  /// Returns the value of `key` as a program loading the file sees it.
+  ///
+  /// Surrounding quotes are removed. In double-quoted values the escapes `\n`, `\t`, `\r`
+  /// are decoded and any other escaped character (e.g. `\"` or `\$`) is taken literally.
+  /// `${NAME}` references in double-quoted and unquoted values are replaced with the
+  /// effective value of `NAME` in this file, or nothing if it isn't defined. Single-quoted
+  /// and heredoc values are taken as is. Returns `None` if `key` isn't defined.
+  ///
+  /// `${NAME:-default}` uses `default` when `NAME` is undefined or empty, and
+  /// `${NAME:=default}` also assigns it, so later references to `NAME` while resolving
+  /// `key` see it. Defaults may contain references themselves.
+  pub fn effective(&self, key: &str) -> Option<String> {
+    Resolver::new(self, &ResolveOptions::default(), false).value(key)
+  }
 
-      let (value, inline_comment) =
-        if let Some((value, comment)) = value_part.split_once(COMMENT_PREFIX) {
-          (value.trim(), Some(EnvComment(Cow::Borrowed(comment))))
-        } else {
-          (value_part.trim(), None)
-        };
+  /// Resolves every variable of the file, keyed by name.
+  ///
+  /// Values are read as in [`EnvFile::effective`], and `$NAME` references are expanded
+  /// too. See [`ResolveOptions`] for references to keys the file doesn't define. Keys set by
+  /// `${NAME:=default}` are included. Fails if references form a cycle, e.g. `A=${B}` and
+  /// `B=${A}`.
+  pub fn resolve(&self) -> Result<BTreeMap<String, String>, ResolveError> {
+    self.resolve_with_options(&ResolveOptions::default())
+  }
 
-      #[cfg(feature = "tracing")]
-      trace!(
-        "Parsed variable: key={}, value={}, has_inline_comment={}",
-        key,
-        value,
-        inline_comment.is_some()
-      );
+  /// Resolves every variable of the file using the provided options, see
+  /// [`EnvFile::resolve`].
+  pub fn resolve_with_options(
+    &self,
+    options: &ResolveOptions,
+  ) -> Result<BTreeMap<String, String>, ResolveError> {
+    let mut resolver = Resolver::new(self, options, true);
+    let mut resolved = BTreeMap::new();
+    for var in self.variables() {
+      if resolved.contains_key(var.name()) {
+        continue;
+      }
+      let value = resolver.value(var.name()).unwrap_or_default();
+      if let Some(chain) = resolver.cycle.take() {
+        return Err(ResolveError::Cycle { chain });
+      }
+      resolved.insert(var.name().to_string(), value);
+    }
+    for (key, value) in resolver.assigned {
+      resolved.entry(key).or_insert(value);
+    }
+    Ok(resolved)
+  }
 
-      Ok(EnvVariable {
-        key: Cow::Borrowed(key),
-        value: Cow::Borrowed(value),
-        preceding_comments: Vec::new(),
-        inline_comment,
+  /// Returns the chains of `${NAME}` references that lead back to where they started.
+  ///
+  /// Each chain starts and ends with the same key, e.g. `["A", "B", "A"]` for `A=${B}` and
+  /// `B=${A}`, and every cycle is reported once, starting from its key defined first. Values
+  /// are read as in [`EnvFile::effective`] but nothing is substituted.
+  pub fn detect_cycles(&self) -> Vec<Vec<String>> {
+    let mut names: Vec<&str> = Vec::new();
+    for var in self.variables() {
+      if !names.contains(&var.name()) {
+        names.push(var.name());
+      }
+    }
+    let edges: Vec<Vec<usize>> = names
+      .iter()
+      .map(|name| {
+        let mut targets = Vec::new();
+        for reference in self.get(name).map(references).unwrap_or_default() {
+          if let Some(target) = names.iter().position(|name| *name == reference)
+            && !targets.contains(&target)
+          {
+            targets.push(target);
+          }
+        }
+        targets
       })
-    } else {
-      Err(ParseError::InvalidLine(s.to_string()))
+      .collect();
+
+    let mut cycles = Vec::new();
+    for start in 0..names.len() {
+      Self::find_cycles(&edges, &mut vec![start], &mut |path| {
+        let mut chain: Vec<String> = path.iter().map(|&i| names[i].to_string()).collect();
+        chain.push(names[start].to_string());
+        cycles.push(chain);
+      });
     }
+    cycles
   }
-}
 
-/// Represents a comment in an environment file.
-///
-/// The comment content excludes the leading `#` character.
-#[derive(Debug, Clone, PartialEq)]
-pub struct EnvComment<'a>(Cow<'a, str>);
+  /// Walks from the last key in `path` and reports every path returning to its first key.
+  ///
+  /// Only keys after the first one are visited, so each cycle is found from a single start.
+  fn find_cycles(edges: &[Vec<usize>], path: &mut Vec<usize>, found: &mut impl FnMut(&[usize])) {
+    let start = path[0];
+    let node = path[path.len() - 1];
+    for &next in &edges[node] {
+      if next == start {
+        found(path);
+      } else if next > start && !path.contains(&next) {
+        path.push(next);
+        Self::find_cycles(edges, path, found);
+        path.pop();
+      }
+    }
+  }
 
-impl<'a> fmt::Display for EnvComment<'a> {
-  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-    write!(f, "{}{}", COMMENT_PREFIX, self.0)
+  /// Rewrites the boolean values of the given keys to `true_val` or `false_val`.
+  ///
+  /// Recognized spellings are `true/yes/y/on/1` and `false/no/n/off/0`, ignoring case.
+  /// Other values are left alone.
+  pub fn canonicalize_booleans(&mut self, keys: &[&str], true_val: &str, false_val: &str) {
+    for entry in &mut self.entries {
+      let EnvEntry::Variable(var) = entry else {
+        continue;
+      };
+      if !keys.iter().any(|key| strip_key_prefix(key) == var.name()) {
+        continue;
+      }
+
+      let is = |spellings: &[&str]| {
+        spellings
+          .iter()
+          .any(|spelling| var.value.eq_ignore_ascii_case(spelling))
+      };
+      let canonical = if is(&TRUE_SPELLINGS) {
+        true_val
+      } else if is(&FALSE_SPELLINGS) {
+        false_val
+      } else {
+        continue;
+      };
+      if var.value != canonical {
+        var.value = Cow::Owned(canonical.to_string());
+      }
+    }
   }
-}
 
-impl<'a> TryFrom<&'a str> for EnvComment<'a> {
-  type Error = ParseError;
+  /// Applies `f` to every variable's `(key, value)`, replacing the value when it returns
+  /// `Some`.
+  pub fn rewrite_values<F: FnMut(&str, &str) -> Option<String>>(&mut self, mut f: F) {
+    for entry in &mut self.entries {
+      if let EnvEntry::Variable(var) = entry
+        && let Some(value) = f(var.name(), &var.value)
+      {
+        var.value = Cow::Owned(value);
+      }
+    }
+  }
 
-  fn try_from(s: &'a str) -> Result<Self, Self::Error> {
-    #[cfg(feature = "tracing")]
-    trace!("Parsing comment from: {:?}", s);
+  /// Returns a file containing only the variable with the given key, along with its
+  /// preceding and inline comments.
+  pub fn extract(&self, key: &str) -> Option<EnvFile<'a>> {
+    let var = self.get(key)?;
+    Some(EnvFile {
+      entries: vec![EnvEntry::Variable(var.clone())],
+    })
+  }
 
-    let trimmed = s.trim();
-    if let Some(content) = trimmed.strip_prefix(COMMENT_PREFIX) {
-      #[cfg(feature = "tracing")]
-      trace!("Parsed comment content: {:?}", content);
+  /// Rewrites every comment, including inline and preceding comments, to use `marker`.
+  pub fn canonicalize_comment_marker(&mut self, marker: &str) {
+    let marker: Cow<'a, str> = Cow::Owned(marker.to_string());
 
-      Ok(EnvComment(Cow::Borrowed(content)))
-    } else {
-      Err(ParseError::InvalidLine(s.to_string()))
+    for entry in &mut self.entries {
+      match entry {
+        EnvEntry::OrphanComment(comment) => comment.marker = marker.clone(),
+        EnvEntry::Variable(var) => {
+          for comment in var
+            .preceding_comments
+            .iter_mut()
+            .chain(var.inline_comment.as_mut())
+          {
+            comment.marker = marker.clone();
+          }
+        }
+        EnvEntry::RegionStart { comment, .. } | EnvEntry::RegionEnd { comment } => {
+          comment.marker = marker.clone()
+        }
+        EnvEntry::EmptyLine => {}
+      }
     }
   }
-}
 
-/// Errors that can occur during parsing.
-#[derive(Debug, thiserror::Error)]
-pub enum ParseError {
-  /// A line that cannot be parsed as a variable, comment, or empty line
-  #[error("Invalid line: {0}")]
-  InvalidLine(String),
-}
+  /// Returns the entries inside the first region named `name`, excluding its markers.
+  ///
+  /// Nested regions are included. A region that is never closed extends to the end of the
+  /// file.
+  pub fn region(&self, name: &str) -> Option<&[EnvEntry<'a>]> {
+    self.region_range(name).map(|range| &self.entries[range])
+  }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+  /// Returns the index range of the entries inside the first region named `name`.
+  pub(crate) fn region_range(&self, name: &str) -> Option<core::ops::Range<usize>> {
+    let start = self.entries.iter().position(
+      |entry| matches!(entry, EnvEntry::RegionStart { name: region, .. } if region == name),
+    )? + 1;
 
-  #[test]
-  fn test_parse_simple() {
-    let input = "KEY=value\nANOTHER=test";
-    let env: EnvFile = input.try_into().unwrap();
+    let mut depth = 0;
+    let end = self.entries[start..]
+      .iter()
+      .position(|entry| match entry {
+        EnvEntry::RegionStart { .. } => {
+          depth += 1;
+          false
+        }
+        EnvEntry::RegionEnd { .. } if depth == 0 => true,
+        EnvEntry::RegionEnd { .. } => {
+          depth -= 1;
+          false
+        }
+        _ => false,
+      })
+      .map_or(self.entries.len(), |offset| start + offset);
+    Some(start..end)
+  }
 
-    assert_eq!(env.entries.len(), 2);
-    match &env.entries[0] {
-      EnvEntry::Variable(var) => {
-        assert_eq!(var.key, "KEY");
-        assert_eq!(var.value, "value");
+  /// Returns the set of keys defined in the file.
+  #[cfg(feature = "std")]
+  pub fn keys_set(&self) -> HashSet<&str> {
+    self.variables().map(EnvVariable::name).collect()
+  }
+
+  /// Groups the variables by the category `f` derives from each key, keeping file order
+  /// within a group.
+  ///
+  /// # Examples
+  ///
+  /// ```rust
+  /// use env_sync::parse::EnvFile;
+  ///
+  /// let env = EnvFile::try_from("DB_HOST=localhost\nDB_PORT=5432\nDEBUG=true").unwrap();
+  /// let groups = env.group_by(|key| key.split('_').next().unwrap_or(key).to_string());
+  ///
+  /// assert_eq!(groups["DB"].len(), 2);
+  /// assert_eq!(groups["DEBUG"][0].value, "true");
+  /// ```
+  #[cfg(feature = "std")]
+  pub fn group_by<K, F>(&self, mut f: F) -> HashMap<K, Vec<&EnvVariable<'a>>>
+  where
+    K: Eq + core::hash::Hash,
+    F: FnMut(&str) -> K,
+  {
+    let mut groups: HashMap<K, Vec<&EnvVariable<'a>>> = HashMap::new();
+    for var in self.variables() {
+      groups.entry(f(var.name())).or_default().push(var);
+    }
+    groups
+  }
+
+  /// Returns the keys defined in `template` but not in this file, in template order.
+  ///
+  /// Keys are compared by name, so `export FOO=1` in the local file provides `FOO`.
+  pub fn missing_keys_from<'t>(&self, template: &'t EnvFile) -> Vec<&'t str> {
+    let mut missing: Vec<&str> = Vec::new();
+    for var in template.variables() {
+      if self.get(var.name()).is_none() && !missing.contains(&var.name()) {
+        missing.push(var.name());
+      }
+    }
+    missing
+  }
+
+  /// Iterates over the variables in the file, skipping comments and empty lines.
+  pub fn variables(&self) -> impl Iterator<Item = &EnvVariable<'a>> {
+    self.entries.iter().filter_map(|entry| {
+      if let EnvEntry::Variable(var) = entry {
+        Some(var)
+      } else {
+        None
+      }
+    })
+  }
+
+  /// Iterates mutably over the variables in the file, skipping comments and empty lines.
+  pub fn variables_mut(&mut self) -> impl Iterator<Item = &mut EnvVariable<'a>> {
+    self.entries.iter_mut().filter_map(|entry| {
+      if let EnvEntry::Variable(var) = entry {
+        Some(var)
+      } else {
+        None
+      }
+    })
+  }
+
+  /// Iterates over the variable names in file order, skipping comments and empty lines.
+  ///
+  /// A key defined more than once is yielded once per definition.
+  pub fn keys(&self) -> impl Iterator<Item = &str> {
+    self.variables().map(EnvVariable::name)
+  }
+
+  /// Iterates over the raw variable values in file order, skipping comments and empty lines.
+  pub fn values(&self) -> impl Iterator<Item = &str> {
+    self.variables().map(|var| var.value.as_ref())
+  }
+
+  /// Collects the variables into a map of names to raw values.
+  ///
+  /// When a key is defined more than once the first definition wins, matching
+  /// [`EnvFile::get`].
+  pub fn to_btreemap(&self) -> BTreeMap<Cow<'_, str>, Cow<'_, str>> {
+    let mut map = BTreeMap::new();
+    for var in self.variables() {
+      map
+        .entry(Cow::Borrowed(var.name()))
+        .or_insert(Cow::Borrowed(var.value.as_ref()));
+    }
+    map
+  }
+
+  /// Collects the variables into a map of names to raw values, see [`EnvFile::to_btreemap`].
+  #[cfg(feature = "std")]
+  pub fn to_hashmap(&self) -> HashMap<Cow<'_, str>, Cow<'_, str>> {
+    let mut map = HashMap::new();
+    for var in self.variables() {
+      map
+        .entry(Cow::Borrowed(var.name()))
+        .or_insert(Cow::Borrowed(var.value.as_ref()));
+    }
+    map
+  }
+
+  /// Flattens all variables into `(key, value, inline_comment)` tuples, in file order.
+  ///
+  /// The inline comment text excludes the leading `#` and surrounding whitespace.
+  pub fn to_triples(&self) -> Vec<(String, String, Option<String>)> {
+    self
+      .variables()
+      .map(|var| {
+        (
+          var.name().to_string(),
+          var.value.to_string(),
+          var
+            .inline_comment
+            .as_ref()
+            .map(|comment| comment.content.trim().to_string()),
+        )
+      })
+      .collect()
+  }
+
+  /// Renames variables according to an explicit `old -> new` key mapping.
+  ///
+  /// Keys in the map that don't appear in the file are skipped. If a rename would produce a
+  /// key that another variable already has (or will have), nothing is renamed and an error
+  /// is returned.
+  #[cfg(feature = "std")]
+  pub fn rekey_with_map(&mut self, map: &HashMap<&str, &str>) -> Result<(), RekeyError> {
+    let final_key = |key: &'_ str| map.get(key).copied().unwrap_or(key).to_string();
+
+    let mut counts: HashMap<String, usize> = HashMap::new();
+    for var in self.variables() {
+      *counts.entry(final_key(var.name())).or_default() += 1;
+    }
+
+    for var in self.variables() {
+      if let Some(to) = map.get(var.name())
+        && counts.get(*to).is_some_and(|count| *count > 1)
+      {
+        return Err(RekeyError::Collision {
+          from: var.name().to_string(),
+          to: to.to_string(),
+        });
+      }
+    }
+
+    for entry in &mut self.entries {
+      if let EnvEntry::Variable(var) = entry
+        && let Some(to) = map.get(var.name())
+      {
+        #[cfg(feature = "tracing")]
+        trace!("Renaming {} to {}", var.key, to);
+        var.key = Cow::Owned(to.to_string());
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Derives a template from this file.
+  ///
+  /// Keys, comments, and layout are kept. Values of keys that look secret (see
+  /// [`SecretPolicy::Heuristic`]) are blanked, other values are kept as defaults.
+  #[cfg(feature = "std")]
+  pub fn to_template(&self) -> EnvFile<'a> {
+    let secrets = SecretPolicy::Heuristic;
+    let mut template = self.clone();
+
+    for entry in &mut template.entries {
+      if let EnvEntry::Variable(var) = entry
+        && secrets.is_secret(var.name())
+      {
+        var.value = Cow::Borrowed("");
+        var.bare = false;
+      }
+    }
+
+    template
+  }
+
+  /// Removes the inline comment of every variable, keeping preceding and orphan comments.
+  pub fn strip_inline_comments(&mut self) {
+    for entry in &mut self.entries {
+      if let EnvEntry::Variable(var) = entry {
+        var.inline_comment = None;
+      }
+    }
+  }
+
+  /// Rejoins values that parsing cut at a `#` inside quotes, e.g. `URL="https://host/#top"`
+  /// read as the value `"https://host/` with the inline comment `top"`.
+  ///
+  /// This is a best-effort heuristic: a value is only repaired when it opens a quote it
+  /// doesn't close and its inline comment contains the closing quote, followed by nothing
+  /// or by a real `#` comment. Whitespace directly before the `#` was trimmed while parsing
+  /// and isn't restored. Returns the number of repaired values.
+  pub fn repair_split_values(&mut self) -> usize {
+    let mut repaired = 0;
+    for entry in &mut self.entries {
+      if let EnvEntry::Variable(var) = entry
+        && var.heredoc.is_none()
+        && let Some((value, inline_comment)) = rejoin_split_value(var)
+      {
+        var.value = Cow::Owned(value);
+        var.inline_comment = inline_comment;
+        repaired += 1;
+      }
+    }
+    repaired
+  }
+
+  /// Merges each variable's preceding comment lines into a single comment line.
+  ///
+  /// The trimmed comment texts are joined with `separator`. This discards the original
+  /// multi-line layout, so it is only applied when explicitly requested.
+  pub fn collapse_preceding_comments(&mut self, separator: &str) {
+    for entry in &mut self.entries {
+      if let EnvEntry::Variable(var) = entry
+        && var.preceding_comments.len() > 1
+      {
+        let joined = var
+          .preceding_comments
+          .iter()
+          .map(|comment| comment.content.trim())
+          .collect::<Vec<_>>()
+          .join(separator);
+        var.preceding_comments = vec![EnvComment::new(format!(" {}", joined))];
+      }
+    }
+  }
+
+  /// Returns every orphan comment in the file, in order of appearance.
+  ///
+  /// Comments attached to a variable (preceding or inline) are not included.
+  pub fn orphan_comments(&self) -> Vec<&EnvComment<'a>> {
+    self
+      .entries
+      .iter()
+      .filter_map(|entry| {
+        if let EnvEntry::OrphanComment(comment) = entry {
+          Some(comment)
+        } else {
+          None
+        }
+      })
+      .collect()
+  }
+
+  /// Returns a hash of the effective `key -> value` content, for change detection.
+  ///
+  /// Comments, empty lines, and variable order don't affect the hash. Like [`EnvFile::get`],
+  /// only the first definition of a duplicated key counts. The hash is 64-bit FNV-1a, so it
+  /// is stable across runs and platforms but not suitable for security purposes.
+  pub fn content_hash(&self) -> u64 {
+    const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+    const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+    let mut pairs: Vec<(&str, &str)> = self
+      .variables()
+      .map(|var| (var.name(), var.value.as_ref()))
+      .collect();
+    // The sort is stable, so the first definition of each key is the one kept
+    pairs.sort_by(|a, b| a.0.cmp(b.0));
+    pairs.dedup_by(|later, first| later.0 == first.0);
+
+    let mut hash = FNV_OFFSET_BASIS;
+    let mut feed = |bytes: &[u8]| {
+      for byte in bytes {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(FNV_PRIME);
+      }
+    };
+    for (key, value) in pairs {
+      // Length prefixes keep `A=BC` and `AB=C` apart
+      feed(&(key.len() as u64).to_le_bytes());
+      feed(key.as_bytes());
+      feed(&(value.len() as u64).to_le_bytes());
+      feed(value.as_bytes());
+    }
+    hash
+  }
+
+  /// Collapses runs of consecutive empty lines into a single one.
+  ///
+  /// Returns `true` if any empty line was removed.
+  pub fn squeeze_blank_lines(&mut self) -> bool {
+    let before = self.entries.len();
+    self
+      .entries
+      .dedup_by(|next, previous| *next == EnvEntry::EmptyLine && *previous == EnvEntry::EmptyLine);
+    self.entries.len() != before
+  }
+
+  /// Tidies the layout by collapsing runs of empty lines and removing decorative orphan
+  /// comments: empty `#` lines, and [`CommentStyle::Divider`] lines unless `keep_dividers` is
+  /// set. Comments attached to a variable are left alone.
+  ///
+  /// Returns `true` if anything was removed.
+  pub fn compact(&mut self, keep_dividers: bool) -> bool {
+    let before = self.entries.len();
+    self.entries.retain(|entry| match entry {
+      EnvEntry::OrphanComment(comment) => match comment.style() {
+        CommentStyle::Blank => false,
+        CommentStyle::Divider => keep_dividers,
+        _ => true,
+      },
+      _ => true,
+    });
+    self.squeeze_blank_lines();
+    self.entries.len() != before
+  }
+
+  /// Reorders the variables to follow the key order of `template`, without changing values.
+  ///
+  /// Variables the template doesn't define come last, in their current order. Each variable
+  /// moves with its comments, while orphan comments and empty lines stay where they are.
+  pub fn reorder_like(&mut self, template: &EnvFile) {
+    let mut ranks: BTreeMap<&str, usize> = BTreeMap::new();
+    for (rank, var) in template.variables().enumerate() {
+      ranks.entry(var.name()).or_insert(rank);
+    }
+
+    let slots: Vec<usize> = self
+      .entries
+      .iter()
+      .enumerate()
+      .filter(|(_, entry)| matches!(entry, EnvEntry::Variable(_)))
+      .map(|(index, _)| index)
+      .collect();
+    let mut variables: Vec<EnvEntry<'a>> = slots
+      .iter()
+      .map(|&index| core::mem::replace(&mut self.entries[index], EnvEntry::EmptyLine))
+      .collect();
+    variables.sort_by_key(|entry| match entry {
+      EnvEntry::Variable(var) => ranks.get(var.name()).copied().unwrap_or(usize::MAX),
+      _ => usize::MAX,
+    });
+
+    for (index, entry) in slots.into_iter().zip(variables) {
+      self.entries[index] = entry;
+    }
+  }
+
+  /// Returns a copy with variables sorted by key and grouped by their prefix (everything
+  /// before the first `_`), with an empty line between groups.
+  ///
+  /// Keys without an underscore form a default group that comes first. With `headers`, every
+  /// other group starts with a `# PREFIX` comment. Orphan comments are kept at the top and
+  /// empty lines are dropped.
+  pub fn grouped_by_prefix(&self, headers: bool) -> EnvFile<'a> {
+    let prefix = |var: &EnvVariable| -> Option<String> {
+      var
+        .name()
+        .split_once('_')
+        .map(|(prefix, _)| prefix.to_string())
+    };
+
+    let mut variables: Vec<&EnvVariable<'a>> = self.variables().collect();
+    // `None` sorts first, so keys without an underscore lead
+    variables.sort_by(|a, b| (prefix(a), a.name()).cmp(&(prefix(b), b.name())));
+
+    let mut entries: Vec<EnvEntry<'a>> = self
+      .entries
+      .iter()
+      .filter(|entry| matches!(entry, EnvEntry::OrphanComment(_)))
+      .cloned()
+      .collect();
+    let mut current_group = None;
+
+    for var in variables {
+      let group = prefix(var);
+      if entries.is_empty() || current_group.as_ref() != Some(&group) {
+        if !entries.is_empty() {
+          entries.push(EnvEntry::EmptyLine);
+        }
+        if headers && let Some(name) = &group {
+          entries.push(EnvEntry::OrphanComment(EnvComment::new(format!(
+            " {}",
+            name
+          ))));
+        }
+        current_group = Some(group);
+      }
+      entries.push(EnvEntry::Variable(var.clone()));
+    }
+
+    EnvFile { entries }
+  }
+
+  /// Removes duplicate definitions of keys, keeping one variable per key.
+  ///
+  /// `keep` decides which definition survives and where it ends up. Comments preceding a
+  /// removed definition are removed with it. Returns the number of removed variables.
+  pub fn dedup_keys(&mut self, keep: DedupKeep) -> usize {
+    let mut positions: BTreeMap<&str, Vec<usize>> = BTreeMap::new();
+    for (index, entry) in self.entries.iter().enumerate() {
+      if let EnvEntry::Variable(var) = entry {
+        positions.entry(var.name()).or_default().push(index);
+      }
+    }
+
+    let mut swaps = Vec::new();
+    let mut removed: Vec<usize> = Vec::new();
+    for indices in positions.values().filter(|indices| indices.len() > 1) {
+      let (first, last) = (indices[0], indices[indices.len() - 1]);
+      match keep {
+        DedupKeep::FirstKeepFirstPos => removed.extend(&indices[1..]),
+        DedupKeep::LastKeepLastPos => removed.extend(&indices[..indices.len() - 1]),
+        DedupKeep::LastKeepFirstPos => {
+          swaps.push((first, last));
+          removed.extend(&indices[1..]);
+        }
+      }
+    }
+
+    for (first, last) in swaps {
+      self.entries.swap(first, last);
+    }
+    removed.sort_unstable();
+    let mut removed_iter = removed.iter().peekable();
+    let mut index = 0;
+    self.entries.retain(|_| {
+      let remove = removed_iter.next_if_eq(&&index).is_some();
+      index += 1;
+      !remove
+    });
+
+    #[cfg(feature = "tracing")]
+    debug!("Removed {} duplicate variables", removed.len());
+
+    removed.len()
+  }
+
+  /// Returns the index where the footer starts: the trailing block of orphan comments and
+  /// empty lines after the last variable.
+  ///
+  /// Returns the number of entries if the file has no variables or doesn't end with a
+  /// comment.
+  pub fn footer_start(&self) -> usize {
+    let after_last_variable = self
+      .entries
+      .iter()
+      .rposition(|entry| matches!(entry, EnvEntry::Variable(_)))
+      .map(|index| index + 1);
+
+    match after_last_variable {
+      Some(index)
+        if self.entries[index..]
+          .iter()
+          .any(|entry| matches!(entry, EnvEntry::OrphanComment(_))) =>
+      {
+        index
+      }
+      _ => self.entries.len(),
+    }
+  }
+
+  /// Iterates over the entries along with the 1-based line each one starts on.
+  ///
+  /// A variable starts at its first preceding comment. Lines are counted in the rendered
+  /// file, which matches the source for files that round-trip unchanged.
+  pub fn entries_with_lines(&self) -> impl Iterator<Item = (usize, &EnvEntry<'a>)> {
+    self.entries.iter().scan(1, |line, entry| {
+      let start = *line;
+      *line += entry.line_count();
+      Some((start, entry))
+    })
+  }
+}
+
+/// Represents a single entry in an environment file.
+#[derive(Debug, Clone, PartialEq)]
+pub enum EnvEntry<'a> {
+  /// A variable assignment with optional comments
+  Variable(EnvVariable<'a>),
+  /// A comment not associated with a variable
+  OrphanComment(EnvComment<'a>),
+  /// An empty line
+  EmptyLine,
+  /// A `# region Name` folding marker opening a named section
+  RegionStart {
+    name: Cow<'a, str>,
+    comment: EnvComment<'a>,
+  },
+  /// A `# endregion` folding marker closing the innermost open section
+  RegionEnd { comment: EnvComment<'a> },
+}
+
+impl<'a> fmt::Display for EnvEntry<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let key_column = KeyColumn::from_formatter(f);
+    self.write_with(f, &RenderOptions::default(), key_column.as_ref())
+  }
+}
+
+impl<'a> EnvEntry<'a> {
+  fn write_with<W: fmt::Write>(
+    &self,
+    f: &mut W,
+    options: &RenderOptions,
+    key_column: Option<&KeyColumn>,
+  ) -> fmt::Result {
+    match self {
+      EnvEntry::Variable(var) => {
+        var.write_with(f, options, key_column)?;
+        writeln!(f)
+      }
+      EnvEntry::OrphanComment(comment) => {
+        writeln!(f, "{}", comment)
+      }
+      EnvEntry::EmptyLine => {
+        writeln!(f)
+      }
+      EnvEntry::RegionStart { comment, .. } | EnvEntry::RegionEnd { comment } => {
+        writeln!(f, "{}", comment)
+      }
+    }
+  }
+}
+
+impl<'a> From<EnvVariable<'a>> for EnvEntry<'a> {
+  fn from(var: EnvVariable<'a>) -> Self {
+    EnvEntry::Variable(var)
+  }
+}
+
+impl<'a> From<EnvComment<'a>> for EnvEntry<'a> {
+  fn from(comment: EnvComment<'a>) -> Self {
+    EnvEntry::OrphanComment(comment)
+  }
+}
+
+impl<'a> TryFrom<&'a str> for EnvEntry<'a> {
+  type Error = ParseError;
+
+  fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+    Self::parse_with_options(s, &ParseOptions::default())
+  }
+}
+
+impl<'a> EnvEntry<'a> {
+  /// Parses a single line using the provided options.
+  pub fn parse_with_options(s: &'a str, options: &ParseOptions) -> Result<Self, ParseError> {
+    let trimmed = s.trim();
+
+    if trimmed.is_empty() {
+      Ok(EnvEntry::EmptyLine)
+    } else if options.regions
+      && let Some(entry) = Self::parse_region_marker(trimmed)
+    {
+      Ok(entry)
+    } else if trimmed.starts_with(COMMENT_PREFIX) {
+      Ok(EnvEntry::OrphanComment(trimmed.try_into()?))
+    } else if let Some(prefix) = options
+      .comment_prefixes
+      .iter()
+      .find(|prefix| !prefix.is_empty() && trimmed.starts_with(prefix.as_str()))
+    {
+      let (marker, content) = trimmed.split_at(prefix.len());
+      Ok(EnvEntry::OrphanComment(EnvComment::with_marker(
+        content, marker,
+      )))
+    } else {
+      let line = if options.preserve_whitespace {
+        s.trim_start()
+      } else {
+        trimmed
+      };
+      Ok(EnvEntry::Variable(EnvVariable::parse_with_options(
+        line, options,
+      )?))
+    }
+  }
+
+  /// Parses a `# region Name` or `# endregion` line.
+  fn parse_region_marker(trimmed: &'a str) -> Option<Self> {
+    let content = trimmed.strip_prefix(COMMENT_PREFIX)?;
+    let body = content.trim_start();
+    let comment = EnvComment::new(content);
+
+    if body.trim_end() == REGION_END {
+      return Some(EnvEntry::RegionEnd { comment });
+    }
+    let name = body.strip_prefix(REGION_START)?;
+    if !name.is_empty() && !name.starts_with(char::is_whitespace) {
+      return None;
+    }
+    Some(EnvEntry::RegionStart {
+      name: Cow::Borrowed(name.trim()),
+      comment,
+    })
+  }
+
+  /// Returns the number of lines the entry takes up when rendered.
+  fn line_count(&self) -> usize {
+    match self {
+      EnvEntry::Variable(var) => {
+        let value_lines = var.value.matches('\n').count();
+        let heredoc_lines = match &var.heredoc {
+          Some(_) if var.value.is_empty() => 1,
+          Some(_) => 2,
+          None => 0,
+        };
+        var.preceding_comments.len() + 1 + value_lines + heredoc_lines
+      }
+      EnvEntry::OrphanComment(_)
+      | EnvEntry::EmptyLine
+      | EnvEntry::RegionStart { .. }
+      | EnvEntry::RegionEnd { .. } => 1,
+    }
+  }
+}
+
+/// Represents an environment variable with its value and associated comments.
+#[derive(Debug, Clone)]
+pub struct EnvVariable<'a> {
+  /// The variable name
+  pub key: Cow<'a, str>,
+  /// The variable value
+  pub value: Cow<'a, str>,
+  /// Comments that appear before this variable
+  pub preceding_comments: Vec<EnvComment<'a>>,
+  /// Comment that appears on the same line as the variable
+  pub inline_comment: Option<EnvComment<'a>>,
+  /// Whether the variable was written as a bare `KEY` line without `=`
+  pub bare: bool,
+  /// Terminator of the `KEY<<TERMINATOR` block the value was read from, if any.
+  ///
+  /// When set, the variable is written back as a heredoc with the value on its own lines.
+  pub heredoc: Option<Cow<'a, str>>,
+  /// Whether the line starts with `export `, which is written back in front of the key
+  pub exported: bool,
+  /// Where the value came from, recorded when the variable is produced by a sync.
+  ///
+  /// Not compared by `==`, so a synced file equals the same content parsed again.
+  pub source: Option<Source>,
+}
+
+impl PartialEq for EnvVariable<'_> {
+  fn eq(&self, other: &Self) -> bool {
+    self.key == other.key
+      && self.value == other.value
+      && self.preceding_comments == other.preceding_comments
+      && self.inline_comment == other.inline_comment
+      && self.bare == other.bare
+      && self.heredoc == other.heredoc
+      && self.exported == other.exported
+  }
+}
+
+/// Formats the variable, padding the key to the formatter's width and honoring its fill and
+/// alignment, e.g. `format!("{:>12}", var)`.
+impl<'a> fmt::Display for EnvVariable<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let key_column = KeyColumn::from_formatter(f);
+    self.write_with(f, &RenderOptions::default(), key_column.as_ref())
+  }
+}
+
+impl<'a> EnvVariable<'a> {
+  fn write_with<W: fmt::Write>(
+    &self,
+    f: &mut W,
+    options: &RenderOptions,
+    key_column: Option<&KeyColumn>,
+  ) -> fmt::Result {
+    for comment in &self.preceding_comments {
+      writeln!(f, "{}", comment)?;
+    }
+    if self.exported {
+      f.write_str(EXPORT_PREFIX)?;
+    }
+    match key_column {
+      Some(column) => column.write_key(f, &self.key)?,
+      None => f.write_str(&self.key)?,
+    }
+    if let Some(terminator) = &self.heredoc {
+      write!(f, "{}{}", HEREDOC_OPERATOR, terminator)?;
+    } else if !(self.bare && options.bare_keys && self.value == BARE_KEY_VALUE) {
+      write!(f, "{}{}", ASSIGNMENT_OPERATOR, self.value)?;
+    }
+    if let Some(comment) = &self.inline_comment {
+      if self.value.is_empty() && self.heredoc.is_none() && !options.space_before_comment_on_empty {
+        write!(f, "{}", comment)?;
+      } else {
+        write!(f, " {}", comment)?;
+      }
+    }
+    if let Some(terminator) = &self.heredoc {
+      if !self.value.is_empty() {
+        write!(f, "\n{}", self.value)?;
+      }
+      write!(f, "\n{}", terminator)?;
+    }
+    Ok(())
+  }
+}
+
+impl<'a> TryFrom<&'a str> for EnvVariable<'a> {
+  type Error = ParseError;
+
+  fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+    Self::parse_with_options(s, &ParseOptions::default())
+  }
+}
+
+impl<'a> EnvVariable<'a> {
+  /// Creates a `KEY=value` variable without comments.
+  pub fn new(key: impl Into<Cow<'a, str>>, value: impl Into<Cow<'a, str>>) -> Self {
+    EnvVariable {
+      key: key.into(),
+      value: value.into(),
+      preceding_comments: Vec::new(),
+      inline_comment: None,
+      bare: false,
+      heredoc: None,
+      exported: false,
+      source: None,
+    }
+  }
+
+  /// Returns the key without a shell prefix such as `export ` or `set `.
+  ///
+  /// A single `export ` is already split off into [`EnvVariable::exported`] when parsing,
+  /// lookups like [`EnvFile::get`] compare names so other spellings are found too.
+  pub fn name(&self) -> &str {
+    strip_key_prefix(&self.key)
+  }
+
+  /// Returns `true` if both variables have the same key and value, ignoring comments.
+  pub fn value_eq(&self, other: &EnvVariable) -> bool {
+    self.name() == other.name() && self.value == other.value
+  }
+
+  /// Classifies the value as empty, whitespace-only, or set.
+  ///
+  /// Whitespace-only values are only observable when parsing with
+  /// [`ParseOptions::preserve_whitespace`]; otherwise they are trimmed to empty. A missing
+  /// key is represented by [`EnvFile::get`] returning `None`.
+  pub fn value_state(&self) -> ValueState {
+    if self.value.is_empty() {
+      ValueState::Empty
+    } else if self.value.trim().is_empty() {
+      ValueState::Whitespace
+    } else {
+      ValueState::Set
+    }
+  }
+
+  /// Returns where the value came from if the variable was produced by a sync.
+  ///
+  /// Always `None` for parsed variables.
+  pub fn source(&self) -> Option<Source> {
+    self.source
+  }
+
+  /// Iterates over the annotations in the variable's preceding comments.
+  pub fn annotations(&self) -> impl Iterator<Item = Annotation<'_>> {
+    self
+      .preceding_comments
+      .iter()
+      .filter_map(EnvComment::annotation)
+  }
+
+  /// Returns the `@deprecated` message if the variable is marked as deprecated.
+  ///
+  /// The message is empty when the annotation has no text.
+  pub fn deprecation(&self) -> Option<&str> {
+    self.annotations().find_map(|annotation| match annotation {
+      Annotation::Deprecated(message) => Some(message),
+      _ => None,
+    })
+  }
+
+  /// Splits the value into a list of items separated by `sep`, e.g. `HOSTS=a, b, c,`.
+  ///
+  /// Items are trimmed, and empty items such as the one after a trailing separator are
+  /// skipped.
+  pub fn as_list(&self, sep: char) -> Vec<String> {
+    self
+      .value
+      .split(sep)
+      .map(str::trim)
+      .filter(|item| !item.is_empty())
+      .map(str::to_string)
+      .collect()
+  }
+
+  /// Sets the value to `items` joined by `sep`, without spaces or a trailing separator.
+  pub fn set_list(&mut self, items: &[&str], sep: char) {
+    let mut value = String::new();
+    for (index, item) in items.iter().enumerate() {
+      if index > 0 {
+        value.push(sep);
+      }
+      value.push_str(item.trim());
+    }
+    self.value = Cow::Owned(value);
+  }
+
+  /// Renders the variable as a shell statement that exports it in the given dialect.
+  ///
+  /// The value is always single-quoted so no expansion happens in the target shell:
+  ///
+  /// - POSIX: `export KEY='value'`, with `'` written as `'\''`
+  /// - fish: `set -x KEY 'value'`, with `\` and `'` backslash-escaped
+  /// - PowerShell: `$env:KEY='value'`, with `'` doubled
+  pub fn quote_for(&self, dialect: ShellDialect) -> String {
+    match dialect {
+      ShellDialect::Posix => format!(
+        "export {}='{}'",
+        self.name(),
+        self.value.replace('\'', "'\\''")
+      ),
+      ShellDialect::Fish => format!(
+        "set -x {} '{}'",
+        self.name(),
+        self.value.replace('\\', "\\\\").replace('\'', "\\'")
+      ),
+      ShellDialect::PowerShell => {
+        format!("$env:{}='{}'", self.name(), self.value.replace('\'', "''"))
+      }
+    }
+  }
+
+  /// Parses a variable line using the provided options.
+  pub fn parse_with_options(s: &'a str, options: &ParseOptions) -> Result<Self, ParseError> {
+    #[cfg(feature = "tracing")]
+    trace!("Parsing variable from: {:?}", s);
+
+    if let Some((key, value, inline_comment)) = Self::split(s, options.split_on) {
+      let (key, exported) = split_export(key.trim());
+      let (value, inline_comment) = match custom_inline_comment(s, value, &options.comment_prefixes)
+      {
+        Some((value, comment)) => (value, Some(comment)),
+        None => (value, inline_comment.map(EnvComment::new)),
+      };
+      let value = match (options.preserve_whitespace, &inline_comment) {
+        (false, _) => value.trim(),
+        (true, Some(_)) => value.trim_end(),
+        (true, None) => value,
+      };
+
+      #[cfg(feature = "tracing")]
+      trace!(
+        "Parsed variable: key={}, value={}, has_inline_comment={}",
+        key,
+        value,
+        inline_comment.is_some()
+      );
+
+      Ok(EnvVariable {
+        key: Cow::Borrowed(key),
+        value: Cow::Borrowed(value),
+        preceding_comments: Vec::new(),
+        inline_comment,
+        bare: false,
+        heredoc: None,
+        exported,
+        source: None,
+      })
+    } else if options.bare_keys_as_true {
+      Self::parse_bare(s).ok_or_else(|| ParseError::InvalidLine(s.to_string()))
+    } else {
+      Err(ParseError::InvalidLine(s.to_string()))
+    }
+  }
+
+  /// Parses a bare `KEY` line (optionally followed by an inline comment) as `KEY=true`.
+  fn parse_bare(s: &'a str) -> Option<Self> {
+    let (key, inline_comment) = match split_once(s, COMMENT_PREFIX) {
+      Some((key, comment)) => (key.trim(), Some(comment)),
+      None => (s.trim(), None),
+    };
+    let (key, exported) = split_export(key);
+
+    if !is_identifier(key) {
+      return None;
+    }
+
+    #[cfg(feature = "tracing")]
+    trace!("Parsed bare key: {}", key);
+
+    Some(EnvVariable {
+      key: Cow::Borrowed(key),
+      value: Cow::Borrowed(BARE_KEY_VALUE),
+      preceding_comments: Vec::new(),
+      inline_comment: inline_comment.map(EnvComment::new),
+      bare: true,
+      heredoc: None,
+      exported,
+      source: None,
+    })
+  }
+
+  /// Recognizes a `KEY<<TERMINATOR` line (optionally followed by an inline comment) that
+  /// opens a multi-line value.
+  fn heredoc_opener(s: &'a str) -> Option<HeredocOpener<'a>> {
+    let (opener, inline_comment) = match split_once(s, COMMENT_PREFIX) {
+      Some((opener, comment)) => (opener, Some(comment)),
+      None => (s, None),
+    };
+    let (key, terminator) = opener.split_once(HEREDOC_OPERATOR)?;
+    let (key, exported) = split_export(key.trim());
+    let terminator = terminator.trim();
+
+    if !is_identifier(key) || terminator.is_empty() || terminator.contains(char::is_whitespace) {
+      return None;
+    }
+
+    Some(HeredocOpener {
+      key,
+      exported,
+      terminator,
+      inline_comment,
+    })
+  }
+
+  /// Reads the body of a heredoc up to its terminator line.
+  ///
+  /// Body lines are joined with `\n`. The lines are only consumed if the terminator is found.
+  fn parse_heredoc(
+    opener: HeredocOpener<'a>,
+    lines: &mut core::str::Lines<'a>,
+  ) -> Result<Self, ParseError> {
+    let mut remaining = lines.clone();
+    let mut body = Vec::new();
+
+    loop {
+      match remaining.next() {
+        Some(line) if line.trim() == opener.terminator => break,
+        Some(line) => body.push(line),
+        None => {
+          return Err(ParseError::UnterminatedHeredoc {
+            key: opener.key.to_string(),
+            terminator: opener.terminator.to_string(),
+          });
+        }
+      }
+    }
+    *lines = remaining;
+
+    #[cfg(feature = "tracing")]
+    trace!(
+      "Parsed heredoc {} with {} lines until {}",
+      opener.key,
+      body.len(),
+      opener.terminator
+    );
+
+    let value = match body.as_slice() {
+      [] => Cow::Borrowed(""),
+      [line] => Cow::Borrowed(*line),
+      lines => Cow::Owned(lines.join("\n")),
+    };
+
+    Ok(EnvVariable {
+      key: Cow::Borrowed(opener.key),
+      value,
+      preceding_comments: Vec::new(),
+      inline_comment: opener.inline_comment.map(EnvComment::new),
+      bare: false,
+      heredoc: Some(Cow::Borrowed(opener.terminator)),
+      exported: opener.exported,
+      source: None,
+    })
+  }
+
+  /// Recognizes a `KEY="...` line whose double-quoted value isn't closed on the same line.
+  ///
+  /// Returns the key, whether it is exported, and the value starting at its opening quote.
+  fn multiline_quoted_opener(line: &'a str) -> Option<(&'a str, bool, &'a str)> {
+    let (key, value) = line.split_once(ASSIGNMENT_OPERATOR)?;
+    let (key, exported) = split_export(key.trim());
+    let value = value.trim_start();
+    let body = value.strip_prefix('"')?;
+
+    if !is_identifier(key) || closing_quote(body).is_some() {
+      return None;
+    }
+    Some((key, exported, value))
+  }
+
+  /// Reads a double-quoted value spanning several lines of `source`, starting at `line`.
+  ///
+  /// The value is kept as written, including its quotes and line breaks. Returns `None`,
+  /// consuming nothing, if `line` doesn't open such a value or the quote is never closed
+  /// at the end of a line (optionally followed by an inline comment).
+  fn parse_multiline_quoted(
+    source: &'a str,
+    line: &'a str,
+    lines: &mut core::str::Lines<'a>,
+  ) -> Option<Self> {
+    let (key, exported, value_start) = Self::multiline_quoted_opener(line)?;
+    let (position, closing_line, quote) = multiline_quoted_close(lines.clone())?;
+    lines.nth(position);
+
+    // Both lines are slices of `source`, so the value is the span between them
+    let offset = |s: &str| s.as_ptr() as usize - source.as_ptr() as usize;
+    let value_end = offset(closing_line) + quote + 1;
+    let value = &source[offset(value_start)..value_end];
+
+    let inline_comment = split_once(&closing_line[quote + 1..], COMMENT_PREFIX)
+      .map(|(_, comment)| EnvComment::new(comment));
+
+    #[cfg(feature = "tracing")]
+    trace!("Parsed multi-line quoted value for key: {}", key);
+
+    Some(EnvVariable {
+      key: Cow::Borrowed(key),
+      value: Cow::Borrowed(value),
+      preceding_comments: Vec::new(),
+      inline_comment,
+      bare: false,
+      heredoc: None,
+      exported,
+      source: None,
+    })
+  }
+
+  /// Splits a line into its raw key, value, and inline comment parts.
+  fn split(s: &'a str, split_on: SplitOn) -> Option<(&'a str, &'a str, Option<&'a str>)> {
+    match split_on {
+      SplitOn::First => {
+        let (key, value_part) = split_once(s, ASSIGNMENT_OPERATOR)?;
+        match split_once(value_part, COMMENT_PREFIX) {
+          Some((value, comment)) => Some((key, value, Some(comment))),
+          None => Some((key, value_part, None)),
+        }
+      }
+      SplitOn::Last => {
+        let (assignment, comment) = match split_once(s, COMMENT_PREFIX) {
+          Some((assignment, comment)) => (assignment, Some(comment)),
+          None => (s, None),
+        };
+        let (key, value) = rsplit_once(assignment, ASSIGNMENT_OPERATOR)?;
+        Some((key, value, comment))
+      }
+    }
+  }
+}
+
+/// The parts of a `KEY<<TERMINATOR` line.
+struct HeredocOpener<'a> {
+  key: &'a str,
+  exported: bool,
+  terminator: &'a str,
+  inline_comment: Option<&'a str>,
+}
+
+/// Reassembles a value cut at a quoted `#`, along with what remains of its inline comment.
+fn rejoin_split_value<'a>(var: &EnvVariable<'a>) -> Option<(String, Option<EnvComment<'a>>)> {
+  let comment = var.inline_comment.as_ref()?;
+  let quote = var
+    .value
+    .chars()
+    .next()
+    .filter(|c| matches!(c, '"' | '\''))?;
+  if comment.marker != COMMENT_PREFIX || var.value[1..].contains(quote) {
+    return None;
+  }
+
+  let (rest, after) = comment.content.split_once(quote)?;
+  let after = after.trim();
+  let inline_comment = if after.is_empty() {
+    None
+  } else {
+    let content = after.strip_prefix(COMMENT_PREFIX)?;
+    Some(EnvComment::new(content.to_string()))
+  };
+  let value = format!("{}{}{}{}", var.value, COMMENT_PREFIX, rest, quote);
+  Some((value, inline_comment))
+}
+
+/// Iterates over the lines that aren't empty or comments, with their 1-based line number
+/// and raw key, or `None` for lines that aren't assignments. Heredoc bodies are skipped.
+fn assignment_lines<'s>(
+  s: &'s str,
+  options: &ParseOptions,
+) -> impl Iterator<Item = (usize, Option<&'s str>)> {
+  let mut lines = s.lines().enumerate();
+  let split_on = options.split_on;
+  let bare_keys_as_true = options.bare_keys_as_true;
+  let comment_prefixes = options.comment_prefixes.clone();
+
+  core::iter::from_fn(move || {
+    loop {
+      let (index, line) = lines.next()?;
+      let trimmed = line.trim();
+      if trimmed.is_empty()
+        || trimmed.starts_with(COMMENT_PREFIX)
+        || comment_prefixes
+          .iter()
+          .any(|prefix| !prefix.is_empty() && trimmed.starts_with(prefix.as_str()))
+      {
+        continue;
+      }
+
+      let raw_key = if let Some(opener) = EnvVariable::heredoc_opener(line) {
+        // Skip the body so its lines aren't mistaken for variables
+        for (_, body_line) in lines.by_ref() {
+          if body_line.trim() == opener.terminator {
+            break;
+          }
+        }
+        line.split_once(HEREDOC_OPERATOR).map(|(key, _)| key)
+      } else if EnvVariable::multiline_quoted_opener(line).is_some()
+        && let Some((position, ..)) = multiline_quoted_close(lines.clone().map(|(_, next)| next))
+      {
+        // Skip the rest of the quoted value
+        lines.nth(position);
+        line.split_once(ASSIGNMENT_OPERATOR).map(|(key, _)| key)
+      } else if let Some((key, _, _)) = EnvVariable::split(line, split_on) {
+        Some(key)
+      } else if bare_keys_as_true && EnvVariable::parse_bare(trimmed).is_some() {
+        Some(split_once(line, COMMENT_PREFIX).map_or(line, |(key, _)| key))
+      } else {
+        None
+      };
+      return Some((index + 1, raw_key));
+    }
+  })
+}
+
+/// Applies [`ParseOptions::unknown_annotation`] to a full-line comment.
+fn check_annotation<'a>(
+  entry: EnvEntry<'a>,
+  source: &str,
+  line: &str,
+  options: &ParseOptions,
+) -> Result<EnvEntry<'a>, ParseError> {
+  let EnvEntry::OrphanComment(ref comment) = entry else {
+    return Ok(entry);
+  };
+  let Some(Annotation::Unknown { name, .. }) = comment.annotation() else {
+    return Ok(entry);
+  };
+
+  // `line` is a slice of `source`, so its offset gives the line number
+  let offset = line.as_ptr() as usize - source.as_ptr() as usize;
+  let line = source[..offset].matches('\n').count() + 1;
+  match options.unknown_annotation {
+    AnnotationPolicy::Preserve => {}
+    AnnotationPolicy::Warn => {
+      #[cfg(feature = "tracing")]
+      warn!("Unknown annotation @{} on line {}", name, line);
+    }
+    AnnotationPolicy::Error => {
+      return Err(ParseError::UnknownAnnotation {
+        name: name.to_string(),
+        line,
+      });
+    }
+  }
+  Ok(entry)
+}
+
+/// Strips one of the [`KEY_PREFIXES`] and the whitespace following it.
+fn strip_key_prefix(key: &str) -> &str {
+  KEY_PREFIXES
+    .iter()
+    .find_map(|prefix| key.strip_prefix(prefix))
+    .map_or(key, str::trim_start)
+}
+
+/// Finds an inline comment introduced by one of the custom `prefixes` in `value`, a slice of
+/// the variable line `line`.
+///
+/// The comment runs to the end of the line, including any `#` after it. Returns the value
+/// before the comment along with the comment.
+fn custom_inline_comment<'a>(
+  line: &'a str,
+  value: &'a str,
+  prefixes: &[String],
+) -> Option<(&'a str, EnvComment<'a>)> {
+  let (index, prefix) = prefixes
+    .iter()
+    .filter(|prefix| !prefix.is_empty())
+    .filter_map(|prefix| {
+      value
+        .match_indices(prefix.as_str())
+        .find(|(index, _)| value[..*index].ends_with(char::is_whitespace))
+        .map(|(index, _)| (index, prefix))
+    })
+    .min_by_key(|(index, _)| *index)?;
+
+  // `value` is a slice of `line`, so the comment continues past it to the end of the line
+  let start = value.as_ptr() as usize - line.as_ptr() as usize + index;
+  let (marker, content) = line[start..].split_at(prefix.len());
+  Some((&value[..index], EnvComment::with_marker(content, marker)))
+}
+
+/// Returns the index of the first `"` in `s` that isn't escaped with a backslash.
+fn closing_quote(s: &str) -> Option<usize> {
+  let mut chars = s.char_indices();
+  while let Some((index, c)) = chars.next() {
+    match c {
+      '\\' => {
+        chars.next();
+      }
+      '"' => return Some(index),
+      _ => {}
+    }
+  }
+  None
+}
+
+/// Finds the line closing a multi-line quoted value among the lines following its opener.
+///
+/// Returns the position of that line, the line, and the index of its closing quote. Only a
+/// quote ending the line, optionally followed by an inline comment, closes the value.
+fn multiline_quoted_close<'l>(
+  following: impl Iterator<Item = &'l str>,
+) -> Option<(usize, &'l str, usize)> {
+  for (position, line) in following.enumerate() {
+    let Some(quote) = closing_quote(line) else {
+      continue;
+    };
+    let rest = &line[quote + 1..];
+    let closes = match split_once(rest, COMMENT_PREFIX) {
+      Some((before, _)) => before.trim().is_empty(),
+      None => rest.trim().is_empty(),
+    };
+    return closes.then_some((position, line, quote));
+  }
+  None
+}
+
+/// Options controlling how [`EnvFile::resolve_with_options`] expands references.
+#[derive(Debug, Clone, Default)]
+pub struct ResolveOptions {
+  /// Look up references to keys the file doesn't define in the process environment, or
+  /// with [`ResolveOptions::env_lookup`] if set. Without the `std` feature, only
+  /// `env_lookup` is used.
+  pub env_fallback: bool,
+  /// Reads an environment variable for [`ResolveOptions::env_fallback`] instead of the
+  /// process environment.
+  pub env_lookup: Option<fn(&str) -> Option<String>>,
+  /// Leave references to unknown keys as written, e.g. `${MISSING}`, instead of expanding
+  /// them to nothing.
+  pub keep_unknown: bool,
+}
+
+/// Expands the `${NAME}` references of a file's values.
+struct Resolver<'s, 'a> {
+  file: &'s EnvFile<'a>,
+  options: &'s ResolveOptions,
+  /// Whether `$NAME` references and cycles are handled, as [`EnvFile::resolve`] does
+  strict: bool,
+  /// Keys being expanded, to break cycles
+  resolving: Vec<&'s str>,
+  /// Values set by `${NAME:=default}`
+  assigned: BTreeMap<String, String>,
+  /// The first cycle found, with the key that closes it repeated at the end
+  cycle: Option<Vec<String>>,
+}
+
+impl<'s, 'a> Resolver<'s, 'a> {
+  fn new(file: &'s EnvFile<'a>, options: &'s ResolveOptions, strict: bool) -> Self {
+    Self {
+      file,
+      options,
+      strict,
+      resolving: Vec::new(),
+      assigned: BTreeMap::new(),
+      cycle: None,
+    }
+  }
+
+  /// Returns the effective value of `key`, or `None` if the file doesn't define it.
+  fn value(&mut self, key: &str) -> Option<String> {
+    let var = self.file.get(key)?;
+    if let Some(position) = self.resolving.iter().position(|name| *name == var.name()) {
+      if self.strict && self.cycle.is_none() {
+        let mut chain: Vec<String> = self.resolving[position..]
+          .iter()
+          .map(|name| name.to_string())
+          .collect();
+        chain.push(var.name().to_string());
+        self.cycle = Some(chain);
+      }
+      return Some(String::new());
+    }
+
+    let (body, quote) = split_quotes(&var.value);
+    if quote == Some('\'') || var.heredoc.is_some() {
+      return Some(body.to_string());
+    }
+
+    self.resolving.push(var.name());
+    let value = self.expand(body, quote.is_some());
+    self.resolving.pop();
+    Some(value)
+  }
+
+  /// Expands the references and, in `quoted` text, the escapes of `body`.
+  fn expand(&mut self, body: &str, quoted: bool) -> String {
+    let mut value = String::new();
+    let mut chars = body.char_indices().peekable();
+    while let Some((index, c)) = chars.next() {
+      match c {
+        '\\' if quoted => match chars.next().map(|(_, c)| c) {
+          Some('n') => value.push('\n'),
+          Some('t') => value.push('\t'),
+          Some('r') => value.push('\r'),
+          Some(escaped) => value.push(escaped),
+          None => value.push('\\'),
+        },
+        '$' if chars.next_if(|(_, c)| *c == '{').is_some() => {
+          let start = index + 2;
+          match closing_brace(&body[start..]) {
+            Some(len) => {
+              let reference = &body[start..start + len];
+              let written = &body[index..start + len + 1];
+              value.push_str(&self.substitute(reference, written, quoted));
+              while chars.next_if(|(i, _)| *i <= start + len).is_some() {}
+            }
+            None => value.push_str("${"),
+          }
+        }
+        '$'
+          if self.strict
+            && chars
+              .peek()
+              .is_some_and(|(_, c)| c.is_ascii_alphabetic() || *c == '_') =>
+        {
+          let start = index + 1;
+          let len = body[start..]
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(body.len() - start);
+          let name = &body[start..start + len];
+          value.push_str(&self.substitute(name, &body[index..start + len], quoted));
+          while chars.next_if(|(i, _)| *i < start + len).is_some() {}
+        }
+        _ => value.push(c),
+      }
+    }
+    value
+  }
+
+  /// Resolves the inside of a reference, applying its `:-` or `:=` default. `written` is the
+  /// whole reference as it appears in the value.
+  fn substitute(&mut self, reference: &str, written: &str, quoted: bool) -> String {
+    let (name, default) = match reference.split_once(':') {
+      Some((name, rest)) if rest.starts_with(['-', '=']) => (name, Some(rest.split_at(1))),
+      _ => (reference, None),
+    };
+    let value = match self.assigned.get(name) {
+      Some(value) => Some(value.clone()),
+      None => self.value(name).or_else(|| self.env_value(name)),
+    };
+
+    match (value, default) {
+      (value, Some((operator, default))) if value.as_deref().is_none_or(str::is_empty) => {
+        let default = self.expand(default, quoted);
+        if operator == "=" {
+          self.assigned.insert(name.to_string(), default.clone());
+        }
+        default
+      }
+      (Some(value), _) => value,
+      (None, _) if self.strict && self.options.keep_unknown => written.to_string(),
+      (None, _) => String::new(),
+    }
+  }
+
+  /// Looks `name` up in the environment if [`ResolveOptions::env_fallback`] is set.
+  fn env_value(&self, name: &str) -> Option<String> {
+    if !(self.strict && self.options.env_fallback) {
+      return None;
+    }
+    if let Some(lookup) = self.options.env_lookup {
+      return lookup(name);
+    }
+    #[cfg(feature = "std")]
+    return std::env::var(name).ok();
+    #[cfg(not(feature = "std"))]
+    None
+  }
+}
+
+/// Returns the index of the `}` closing a reference whose content starts `s`, skipping the
+/// references nested in it.
+fn closing_brace(s: &str) -> Option<usize> {
+  let mut depth = 0;
+  let mut bytes = s.bytes().enumerate().peekable();
+  while let Some((index, byte)) = bytes.next() {
+    match byte {
+      b'$' if bytes.next_if(|(_, byte)| *byte == b'{').is_some() => depth += 1,
+      b'}' if depth == 0 => return Some(index),
+      b'}' => depth -= 1,
+      _ => {}
+    }
+  }
+  None
+}
+
+/// Splits a value into its body and the quote character surrounding it, if any.
+fn split_quotes(raw: &str) -> (&str, Option<char>) {
+  match raw.chars().next() {
+    Some(quote @ ('"' | '\'')) if raw.len() > 1 && raw.ends_with(quote) => {
+      (&raw[1..raw.len() - 1], Some(quote))
+    }
+    _ => (raw, None),
+  }
+}
+
+/// Returns the names of the `${NAME}` references that [`EnvFile::effective`] expands in `var`.
+fn references<'v>(var: &'v EnvVariable) -> Vec<&'v str> {
+  let (body, quote) = split_quotes(&var.value);
+  if quote == Some('\'') || var.heredoc.is_some() {
+    return Vec::new();
+  }
+
+  let mut names = Vec::new();
+  let mut rest = body;
+  while let Some(index) = rest.find(['\\', '$']) {
+    let (marker, after) = rest[index..].split_at(1);
+    rest = if marker == "\\" && quote.is_some() {
+      // Skips the escaped character
+      after.char_indices().nth(1).map_or("", |(i, _)| &after[i..])
+    } else if let Some(reference) = after.strip_prefix('{')
+      && let Some((reference, tail)) = reference.split_once('}')
+    {
+      // Defaults such as `${NAME:-fallback}` only name `NAME` before the `:`
+      names.push(reference.split(':').next().unwrap_or(reference));
+      tail
+    } else {
+      after
+    };
+  }
+  names
+}
+
+/// Splits a trimmed key into its name and whether it was prefixed with [`EXPORT_PREFIX`].
+///
+/// Only a single space counts as the prefix, so other spellings stay in the key and still
+/// roundtrip unchanged. A key named `export` itself is left alone.
+fn split_export(key: &str) -> (&str, bool) {
+  match key.strip_prefix(EXPORT_PREFIX) {
+    Some(name) if !name.is_empty() && !name.starts_with(char::is_whitespace) => (name, true),
+    _ => (key, false),
+  }
+}
+
+/// Returns `true` for names matching `[A-Za-z_][A-Za-z0-9_]*`.
+fn is_identifier(s: &str) -> bool {
+  let mut chars = s.chars();
+  chars
+    .next()
+    .is_some_and(|c| c.is_ascii_alphabetic() || c == '_')
+    && chars.all(|c| c.is_ascii_alphanumeric() || c == '_')
+}
+
+/// Splits `s` around the first occurrence of a single-byte ASCII delimiter.
+///
+/// Equivalent to [`str::split_once`]; with the `fast-parse` feature the byte is located
+/// with `memchr`.
+fn split_once<'s>(s: &'s str, delimiter: &str) -> Option<(&'s str, &'s str)> {
+  #[cfg(feature = "fast-parse")]
+  {
+    let index = memchr::memchr(delimiter.as_bytes()[0], s.as_bytes())?;
+    Some((&s[..index], &s[index + 1..]))
+  }
+  #[cfg(not(feature = "fast-parse"))]
+  {
+    s.split_once(delimiter)
+  }
+}
+
+/// Splits `s` around the last occurrence of a single-byte ASCII delimiter.
+///
+/// Equivalent to [`str::rsplit_once`]; with the `fast-parse` feature the byte is located
+/// with `memchr`.
+fn rsplit_once<'s>(s: &'s str, delimiter: &str) -> Option<(&'s str, &'s str)> {
+  #[cfg(feature = "fast-parse")]
+  {
+    let index = memchr::memrchr(delimiter.as_bytes()[0], s.as_bytes())?;
+    Some((&s[..index], &s[index + 1..]))
+  }
+  #[cfg(not(feature = "fast-parse"))]
+  {
+    s.rsplit_once(delimiter)
+  }
+}
+
+/// Origin of a synced variable's value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Source {
+  /// The template value, the key is missing locally or has a different value there
+  Template,
+  /// The local value, copied because the template leaves it empty
+  Local,
+  /// Both files define the key with the same value
+  Both,
+  /// A process environment variable, used because both files leave the value empty
+  Environment,
+  /// The fallback value from a defaults file, used because both files leave it empty
+  Defaults,
+  /// A value forced by the sync's overrides
+  Override,
+}
+
+/// Which definition [`EnvFile::dedup_keys`] keeps for a duplicated key, and where.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DedupKeep {
+  /// Keep the first definition where it is, matching [`EnvFile::get`]
+  #[default]
+  FirstKeepFirstPos,
+  /// Keep the last definition where it is, matching how shells evaluate the file
+  LastKeepLastPos,
+  /// Keep the last definition, moved to the position of the first one
+  LastKeepFirstPos,
+}
+
+/// What happens to the preceding comments of a removed variable, see
+/// [`EnvFile::remove_with`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum CommentOnRemove {
+  /// Remove the comments along with the variable
+  #[default]
+  Delete,
+  /// Keep the comments in the file as orphan comments, preserving the documentation
+  Orphan,
+}
+
+/// Whether a variable's value carries content.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ValueState {
+  /// `KEY=`
+  Empty,
+  /// `KEY=   ` (requires [`ParseOptions::preserve_whitespace`])
+  Whitespace,
+  /// `KEY=value`
+  Set,
+}
+
+/// Shell dialects supported by [`EnvVariable::quote_for`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShellDialect {
+  /// POSIX-compatible shells such as `sh`, `bash`, and `zsh`
+  Posix,
+  /// The fish shell
+  Fish,
+  /// PowerShell
+  PowerShell,
+}
+
+/// Represents a comment in an environment file.
+///
+/// The comment content excludes the leading comment marker (usually `#`) and keeps the text
+/// after it verbatim, including any space, so `#note` and `# note` are written back as they
+/// were read.
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnvComment<'a> {
+  content: Cow<'a, str>,
+  marker: Cow<'a, str>,
+}
+
+impl<'a> EnvComment<'a> {
+  /// Creates a comment from its content, excluding the leading `#`.
+  pub fn new(content: impl Into<Cow<'a, str>>) -> Self {
+    Self::with_marker(content, COMMENT_PREFIX)
+  }
+
+  /// Creates a comment introduced by `marker` instead of `#`.
+  pub fn with_marker(content: impl Into<Cow<'a, str>>, marker: impl Into<Cow<'a, str>>) -> Self {
+    EnvComment {
+      content: content.into(),
+      marker: marker.into(),
+    }
+  }
+
+  /// Returns a copy of the comment that borrows from this one.
+  #[cfg(feature = "std")]
+  pub(crate) fn borrowed(&self) -> EnvComment<'_> {
+    EnvComment {
+      content: Cow::Borrowed(&self.content),
+      marker: Cow::Borrowed(&self.marker),
+    }
+  }
+
+  /// Returns the marker that introduces the comment, e.g. `#` or `;`.
+  pub fn marker(&self) -> &str {
+    &self.marker
+  }
+
+  /// Parses the comment as a disabled variable, such as `# KEY=value`.
+  ///
+  /// Returns `None` unless the comment is a `KEY=value` assignment with a valid identifier
+  /// as key (optionally prefixed with `export `), so prose containing `=` isn't matched.
+  pub fn as_disabled_variable(&self) -> Option<EnvVariable<'_>> {
+    let body = self.content.trim();
+    if body.starts_with(COMMENT_PREFIX) || !body.contains(ASSIGNMENT_OPERATOR) {
+      return None;
+    }
+    let var = EnvVariable::parse_with_options(body, &ParseOptions::default()).ok()?;
+    is_identifier(var.name()).then_some(var)
+  }
+
+  /// Classifies the comment by what its content looks like.
+  pub fn style(&self) -> CommentStyle {
+    let body = self.content.trim();
+    if body.is_empty() {
+      CommentStyle::Blank
+    } else if body.len() >= MIN_DIVIDER_LEN && body.chars().all(|c| c.is_ascii_punctuation()) {
+      CommentStyle::Divider
+    } else if self.annotation().is_some() {
+      CommentStyle::Annotation
+    } else if self.as_disabled_variable().is_some() {
+      CommentStyle::DisabledVariable
+    } else {
+      CommentStyle::Text
+    }
+  }
+
+  /// Parses the comment as a `# @name value` annotation.
+  ///
+  /// Returns `None` for regular comments. The comment itself is left as is, so
+  /// annotations are preserved verbatim when the file is written back.
+  pub fn annotation(&self) -> Option<Annotation<'_>> {
+    let body = self.content.trim().strip_prefix(ANNOTATION_PREFIX)?;
+    let (name, value) = body.split_once(char::is_whitespace).unwrap_or((body, ""));
+    let value = value.trim();
+
+    match name {
+      "" => None,
+      "deprecated" => Some(Annotation::Deprecated(value)),
+      _ => Some(Annotation::Unknown { name, value }),
+    }
+  }
+}
+
+/// The kind of a comment, see [`EnvComment::style`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentStyle {
+  /// A comment marker with nothing after it
+  Blank,
+  /// A section divider made only of punctuation, e.g. `#############` or `# ------`
+  Divider,
+  /// A `# @name value` annotation
+  Annotation,
+  /// A commented-out `# KEY=value` assignment
+  DisabledVariable,
+  /// Any other comment
+  Text,
+}
+
+/// A structured annotation found in a `# @name value` comment.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Annotation<'a> {
+  /// `@deprecated [message]`: the variable should no longer be used
+  Deprecated(&'a str),
+  /// Any annotation that isn't recognized
+  Unknown { name: &'a str, value: &'a str },
+}
+
+impl<'a> fmt::Display for EnvComment<'a> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}{}", self.marker, self.content)
+  }
+}
+
+impl<'a> TryFrom<&'a str> for EnvComment<'a> {
+  type Error = ParseError;
+
+  fn try_from(s: &'a str) -> Result<Self, Self::Error> {
+    #[cfg(feature = "tracing")]
+    trace!("Parsing comment from: {:?}", s);
+
+    let trimmed = s.trim();
+    if let Some(content) = trimmed.strip_prefix(COMMENT_PREFIX) {
+      #[cfg(feature = "tracing")]
+      trace!("Parsed comment content: {:?}", content);
+
+      Ok(EnvComment::new(content))
+    } else {
+      Err(ParseError::InvalidLine(s.to_string()))
+    }
+  }
+}
+
+/// Padding applied to keys when a width is requested through the formatter.
+struct KeyColumn {
+  width: usize,
+  fill: char,
+  align: fmt::Alignment,
+}
+
+impl KeyColumn {
+  fn from_formatter(f: &fmt::Formatter<'_>) -> Option<Self> {
+    Some(Self {
+      width: f.width()?,
+      fill: f.fill(),
+      align: f.align().unwrap_or(fmt::Alignment::Left),
+    })
+  }
+
+  fn write_key<W: fmt::Write>(&self, f: &mut W, key: &str) -> fmt::Result {
+    let padding = self.width.saturating_sub(key.chars().count());
+    let (before, after) = match self.align {
+      fmt::Alignment::Left => (0, padding),
+      fmt::Alignment::Right => (padding, 0),
+      fmt::Alignment::Center => (padding / 2, padding - padding / 2),
+    };
+
+    for _ in 0..before {
+      f.write_char(self.fill)?;
+    }
+    f.write_str(key)?;
+    for _ in 0..after {
+      f.write_char(self.fill)?;
+    }
+    Ok(())
+  }
+}
+
+/// Options controlling how env files are parsed.
+#[derive(Debug, Clone, Default)]
+pub struct ParseOptions {
+  /// Which `=` separates the key from the value.
+  pub split_on: SplitOn,
+  /// Parse a bare identifier line such as `DEBUG` as `DEBUG=true` instead of failing.
+  pub bare_keys_as_true: bool,
+  /// Keep the whitespace around values instead of trimming it, so `KEY=   ` keeps its
+  /// spaces. Whitespace between a value and its inline comment is still dropped.
+  pub preserve_whitespace: bool,
+  /// Attach comments to the next variable even when empty lines separate them, instead of
+  /// keeping them as orphan comments. The separating empty lines are dropped.
+  pub attach_across_blank: bool,
+  /// Additional markers, e.g. `//` or `;`, that start full-line and inline comments besides `#`.
+  /// An inline comment using one of them must follow whitespace, so values such as
+  /// `http://host` aren't cut.
+  pub comment_prefixes: Vec<String>,
+  /// Parse `# region Name` and `# endregion` comments as [`EnvEntry::RegionStart`] and
+  /// [`EnvEntry::RegionEnd`] markers. Comments directly above a marker stay orphan
+  /// comments instead of attaching to the next variable.
+  pub regions: bool,
+  /// What to do with comment annotations other than the known ones, e.g. `# @typo`.
+  pub unknown_annotation: AnnotationPolicy,
+}
+
+/// How [`ParseOptions`] treats unknown `# @name` annotations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AnnotationPolicy {
+  /// Keep them as regular comments
+  #[default]
+  Preserve,
+  /// Fail with [`ParseError::UnknownAnnotation`]
+  Error,
+  /// Keep them, but log a warning when the `tracing` feature is enabled
+  Warn,
+}
+
+/// Options controlling how env files are rendered.
+#[derive(Debug, Clone)]
+pub struct RenderOptions {
+  /// Write variables parsed from bare `KEY` lines back without `=true`, as long as their
+  /// value is still `true`. Defaults to `true`.
+  pub bare_keys: bool,
+  /// Separate the inline comment of an empty value with a space, as in `KEY= # hint`,
+  /// instead of writing `KEY=# hint`. Defaults to `true`.
+  pub space_before_comment_on_empty: bool,
+}
+
+impl Default for RenderOptions {
+  fn default() -> Self {
+    Self {
+      bare_keys: true,
+      space_before_comment_on_empty: true,
+    }
+  }
+}
+
+/// Which assignment operator separates a key from its value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SplitOn {
+  /// Split on the first `=`, so values may contain `=` (e.g. base64 padding or query strings)
+  #[default]
+  First,
+  /// Split on the last `=` before any inline comment, so keys may contain `=`.
+  ///
+  /// Use with care: any value containing `=` will have part of it moved into the key.
+  Last,
+}
+
+/// Errors that can occur during parsing.
+#[derive(Debug, thiserror::Error)]
+pub enum ParseError {
+  /// A line that cannot be parsed as a variable, comment, or empty line
+  #[error("Invalid line: {0}")]
+  InvalidLine(String),
+  /// A `KEY<<TERMINATOR` block without a closing terminator line
+  #[error("Heredoc for {key} is missing its terminator {terminator}")]
+  UnterminatedHeredoc { key: String, terminator: String },
+  /// A `# @name` annotation that isn't recognized, with its line number starting at 1
+  #[error("Unknown annotation @{name} on line {line}")]
+  UnknownAnnotation { name: String, line: usize },
+}
+
+/// A likely mistake reported by [`EnvFile::validate`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Diagnostic {
+  /// Two lines define the same key with different surrounding whitespace, e.g. ` KEY =1`
+  /// and `KEY=2`. Line numbers start at 1.
+  WhitespaceDuplicateKey {
+    key: String,
+    first_line: usize,
+    line: usize,
+  },
+  /// A key that isn't a valid variable name, e.g. `MY-KEY=1`
+  InvalidKey { key: String, line: usize },
+  /// A key defined again on a later line, only reported by [`EnvFile::lint`]
+  DuplicateKey {
+    key: String,
+    first_line: usize,
+    line: usize,
+  },
+  /// A line that is neither a variable, a comment, nor empty, only reported by
+  /// [`EnvFile::lint`]
+  InvalidLine { line: usize },
+}
+
+impl Diagnostic {
+  /// Returns the line the problem was found on, starting at 1.
+  pub fn line(&self) -> usize {
+    match self {
+      Diagnostic::WhitespaceDuplicateKey { line, .. }
+      | Diagnostic::InvalidKey { line, .. }
+      | Diagnostic::DuplicateKey { line, .. }
+      | Diagnostic::InvalidLine { line } => *line,
+    }
+  }
+}
+
+impl fmt::Display for Diagnostic {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Diagnostic::WhitespaceDuplicateKey {
+        key, first_line, ..
+      } => write!(
+        f,
+        "{} is already defined on line {} with different whitespace",
+        key, first_line
+      ),
+      Diagnostic::InvalidKey { key, .. } => write!(f, "{} is not a valid variable name", key),
+      Diagnostic::DuplicateKey {
+        key, first_line, ..
+      } => write!(f, "{} is already defined on line {}", key, first_line),
+      Diagnostic::InvalidLine { .. } => f.write_str("not a variable, comment, or empty line"),
+    }
+  }
+}
+
+/// Errors that can occur when resolving references with [`EnvFile::resolve`].
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum ResolveError {
+  /// References that lead back to where they started, e.g. `["A", "B", "A"]`
+  #[error("Circular reference: {}", chain.join(" -> "))]
+  Cycle { chain: Vec<String> },
+}
+
+/// Errors that can occur when renaming keys.
+#[derive(Debug, thiserror::Error, PartialEq)]
+pub enum RekeyError {
+  /// Renaming `from` to `to` would result in a duplicate key
+  #[error("Renaming {from} to {to} collides with an existing key")]
+  Collision { from: String, to: String },
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_parse_simple() {
+    let input = "KEY=value\nANOTHER=test";
+    let env: EnvFile = input.try_into().unwrap();
+
+    assert_eq!(env.entries.len(), 2);
+    match &env.entries[0] {
+      EnvEntry::Variable(var) => {
+        assert_eq!(var.key, "KEY");
+        assert_eq!(var.value, "value");
       }
       _ => panic!("Expected variable"),
     }
@@ -291,111 +2565,1425 @@ mod tests {
   }
 
   #[test]
-  fn test_parse_with_comments() {
-    let input = "# This is a comment\nKEY=value\n# Another comment\n# Multi line\nTEST=123";
+  fn test_parse_with_comments() {
+    let input = "# This is a comment\nKEY=value\n# Another comment\n# Multi line\nTEST=123";
+    let env: EnvFile = input.try_into().unwrap();
+
+    let mut iter = env.entries.iter();
+
+    // First entry should be KEY variable with one preceding comment
+    match iter.next().unwrap() {
+      EnvEntry::Variable(var) => {
+        assert_eq!(var.key, "KEY");
+        assert_eq!(var.value, "value");
+        assert_eq!(var.preceding_comments.len(), 1);
+        assert_eq!(var.preceding_comments[0].to_string(), "# This is a comment");
+      }
+      _ => panic!("Expected variable"),
+    }
+
+    // Second entry should be TEST variable with two preceding comments
+    match iter.next().unwrap() {
+      EnvEntry::Variable(var) => {
+        assert_eq!(var.key, "TEST");
+        assert_eq!(var.value, "123");
+        assert_eq!(var.preceding_comments.len(), 2);
+        assert_eq!(var.preceding_comments[0].to_string(), "# Another comment");
+        assert_eq!(var.preceding_comments[1].to_string(), "# Multi line");
+      }
+      _ => panic!("Expected variable"),
+    }
+
+    assert!(iter.next().is_none());
+  }
+
+  #[test]
+  fn test_parse_inline_comments() {
+    let input = "KEY=value # This is inline\nTEST=123";
+    let env: EnvFile = input.try_into().unwrap();
+
+    match &env.entries[0] {
+      EnvEntry::Variable(var) => {
+        assert_eq!(var.key, "KEY");
+        assert_eq!(var.value, "value");
+        assert_eq!(var.inline_comment, Some(EnvComment::new(" This is inline")));
+      }
+      _ => panic!("Expected variable"),
+    }
+  }
+
+  #[test]
+  fn test_roundtrip() {
+    let input = "# Comment\nKEY=value\n\n# Orphan\nTEST=123 # inline";
+    let env: EnvFile = input.try_into().unwrap();
+    let output = env.to_string();
+
+    // Parse the output again and compare
+    let env2: EnvFile = output.as_str().try_into().unwrap();
+    assert_eq!(env, env2);
+  }
+
+  #[test]
+  fn test_env_entry_from_str() {
+    // Test empty line
+    let entry: EnvEntry = "".try_into().unwrap();
+    assert_eq!(entry, EnvEntry::EmptyLine);
+
+    // Test comment
+    let entry: EnvEntry = "# This is a comment".try_into().unwrap();
+    match entry {
+      EnvEntry::OrphanComment(comment) => {
+        assert_eq!(comment, EnvComment::new(" This is a comment"))
+      }
+      _ => panic!("Expected OrphanComment"),
+    }
+
+    // Test variable
+    let entry: EnvEntry = "KEY=value".try_into().unwrap();
+    match entry {
+      EnvEntry::Variable(var) => {
+        assert_eq!(var.key, "KEY");
+        assert_eq!(var.value, "value");
+        assert!(var.preceding_comments.is_empty());
+        assert!(var.inline_comment.is_none());
+      }
+      _ => panic!("Expected Variable"),
+    }
+
+    // Test variable with inline comment
+    let entry: EnvEntry = "KEY=value # comment".try_into().unwrap();
+    match entry {
+      EnvEntry::Variable(var) => {
+        assert_eq!(var.key, "KEY");
+        assert_eq!(var.value, "value");
+        assert_eq!(var.inline_comment, Some(EnvComment::new(" comment")));
+      }
+      _ => panic!("Expected Variable"),
+    }
+
+    // Test invalid line
+    assert!(EnvEntry::try_from("invalid line without equals").is_err());
+  }
+
+  #[test]
+  fn test_orphan_comments() {
+    let input =
+      "# Header\n\nKEY=value\n# Attached\nOTHER=1\n\n# Middle\n\nLAST=2 # inline\n# Footer";
+    let env: EnvFile = input.try_into().unwrap();
+
+    let comments: Vec<String> = env
+      .orphan_comments()
+      .iter()
+      .map(|c| c.to_string())
+      .collect();
+    assert_eq!(comments, vec!["# Header", "# Middle", "# Footer"]);
+  }
+
+  #[test]
+  fn test_value_eq_ignores_comments() {
+    let a: EnvVariable = "KEY=value # one".try_into().unwrap();
+    let mut b: EnvVariable = "KEY=value".try_into().unwrap();
+    b.preceding_comments.push(EnvComment::new(" docs"));
+
+    assert!(a.value_eq(&b));
+    assert_ne!(a, b);
+
+    let c: EnvVariable = "KEY=other # one".try_into().unwrap();
+    assert!(!a.value_eq(&c));
+  }
+
+  #[test]
+  fn test_parse_multi() {
+    let input = "---\n# First\nA=1\nB=2\n---\nC=3 # third\n";
+    let documents = EnvFile::parse_multi(input).unwrap();
+
+    assert_eq!(documents.len(), 2);
+    assert_eq!(documents[0].entries.len(), 2);
+    assert_eq!(documents[0].get("A").unwrap().value, "1");
+    assert_eq!(documents[0].get("B").unwrap().value, "2");
+    assert!(documents[0].get("C").is_none());
+    assert_eq!(documents[1].entries.len(), 1);
+    assert_eq!(documents[1].get("C").unwrap().value, "3");
+
+    let without_leading = EnvFile::parse_multi("A=1\n---\nB=2").unwrap();
+    assert_eq!(without_leading.len(), 2);
+  }
+
+  #[test]
+  fn test_set() {
+    let mut env: EnvFile = "# Docs\nKEY=old # inline".try_into().unwrap();
+
+    env.set("KEY", "new");
+    env.set("OTHER", String::from("added"));
+
+    assert_eq!(env.to_string(), "# Docs\nKEY=new # inline\nOTHER=added\n");
+  }
+
+  #[test]
+  fn test_to_triples() {
+    let input = "# Header\nKEY=value # inline note\n\nOTHER=1";
+    let env: EnvFile = input.try_into().unwrap();
+
+    assert_eq!(
+      env.to_triples(),
+      vec![
+        (
+          "KEY".to_string(),
+          "value".to_string(),
+          Some("inline note".to_string())
+        ),
+        ("OTHER".to_string(), "1".to_string(), None),
+      ]
+    );
+  }
+
+  #[test]
+  fn test_split_on_first_and_last() {
+    let first = EnvVariable::parse_with_options("A=B=C # note", &ParseOptions::default()).unwrap();
+    assert_eq!(first.key, "A");
+    assert_eq!(first.value, "B=C");
+
+    let options = ParseOptions {
+      split_on: SplitOn::Last,
+      ..Default::default()
+    };
+    let last = EnvVariable::parse_with_options("A=B=C # note", &options).unwrap();
+    assert_eq!(last.key, "A=B");
+    assert_eq!(last.value, "C");
+    assert_eq!(last.inline_comment.unwrap().to_string(), "# note");
+
+    let env = EnvFile::parse_with_options("A=B=C\nD=E", &options).unwrap();
+    assert_eq!(env.get("A=B").unwrap().value, "C");
+    assert_eq!(env.get("D").unwrap().value, "E");
+  }
+
+  #[test]
+  fn test_quote_for_dialects() {
+    let var: EnvVariable = r#"MSG=it's $HOME \ "quoted""#.try_into().unwrap();
+
+    assert_eq!(
+      var.quote_for(ShellDialect::Posix),
+      r#"export MSG='it'\''s $HOME \ "quoted"'"#
+    );
+    assert_eq!(
+      var.quote_for(ShellDialect::Fish),
+      r#"set -x MSG 'it\'s $HOME \\ "quoted"'"#
+    );
+    assert_eq!(
+      var.quote_for(ShellDialect::PowerShell),
+      r#"$env:MSG='it''s $HOME \ "quoted"'"#
+    );
+  }
+
+  #[test]
+  fn test_collapse_preceding_comments() {
+    let input = "# Database host\n# Used by the API\nDB_HOST=localhost\n# Single\nPORT=1";
+    let mut env: EnvFile = input.try_into().unwrap();
+
+    env.collapse_preceding_comments("; ");
+
+    assert_eq!(
+      env.to_string(),
+      "# Database host; Used by the API\nDB_HOST=localhost\n# Single\nPORT=1\n"
+    );
+  }
+
+  #[test]
+  fn test_deprecated_annotation() {
+    let input = "# Old API host\n# @deprecated use API_URL instead\nAPI_HOST=\n# @deprecated\nLEGACY=1\nAPI_URL=";
+    let env: EnvFile = input.try_into().unwrap();
+
+    assert_eq!(
+      env.get("API_HOST").unwrap().deprecation(),
+      Some("use API_URL instead")
+    );
+    assert_eq!(env.get("LEGACY").unwrap().deprecation(), Some(""));
+    assert_eq!(env.get("API_URL").unwrap().deprecation(), None);
+    assert_eq!(env.to_string(), format!("{}\n", input));
+  }
+
+  #[test]
+  fn test_strip_inline_comments() {
+    let input = "# Header\n\n# Docs\nKEY=value # inline\nOTHER=1 # another";
+    let mut env: EnvFile = input.try_into().unwrap();
+
+    env.strip_inline_comments();
+
+    assert_eq!(env.to_string(), "# Header\n\n# Docs\nKEY=value\nOTHER=1\n");
+  }
+
+  #[test]
+  fn test_bare_keys_as_true() {
+    assert!(EnvFile::try_from("DEBUG").is_err());
+
+    let options = ParseOptions {
+      bare_keys_as_true: true,
+      ..Default::default()
+    };
+    let input = "# Enable debugging\nDEBUG\nVERBOSE # noisy\nLEVEL=3\n";
+    let mut env = EnvFile::parse_with_options(input, &options).unwrap();
+
+    let debug = env.get("DEBUG").unwrap();
+    assert_eq!(debug.value, "true");
+    assert!(debug.bare);
+    assert!(!env.get("LEVEL").unwrap().bare);
+    assert!(EnvFile::parse_with_options("not a key", &options).is_err());
+
+    assert_eq!(env.to_string(), input);
+    assert_eq!(
+      env.render(&RenderOptions {
+        bare_keys: false,
+        ..Default::default()
+      }),
+      "# Enable debugging\nDEBUG=true\nVERBOSE=true # noisy\nLEVEL=3\n"
+    );
+
+    env.set("DEBUG", "false");
+    assert!(env.to_string().contains("DEBUG=false\n"));
+  }
+
+  #[test]
+  fn test_unknown_annotation_policy() {
+    let input = "# @deprecated use NEW_KEY\nOLD_KEY=1\n\n# @tpyo\nNEW_KEY=2\n";
+    let options = |unknown_annotation| ParseOptions {
+      unknown_annotation,
+      ..Default::default()
+    };
+
+    for policy in [AnnotationPolicy::Preserve, AnnotationPolicy::Warn] {
+      let env = EnvFile::parse_with_options(input, &options(policy)).unwrap();
+      assert_eq!(env.to_string(), input);
+    }
+
+    let error = EnvFile::parse_with_options(input, &options(AnnotationPolicy::Error)).unwrap_err();
+    assert!(matches!(
+      error,
+      ParseError::UnknownAnnotation { ref name, line: 4 } if name == "tpyo"
+    ));
+    assert!(
+      EnvFile::parse_with_options(
+        "# @deprecated\nOLD_KEY=1\n",
+        &options(AnnotationPolicy::Error)
+      )
+      .is_ok()
+    );
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn test_rekey_with_map() {
+    let mut env: EnvFile = "# Docs\nOLD_HOST=localhost\nOLD_PORT=5432\nKEEP=1"
+      .try_into()
+      .unwrap();
+
+    let map = HashMap::from([
+      ("OLD_HOST", "DB_HOST"),
+      ("OLD_PORT", "DB_PORT"),
+      ("MISSING", "IGNORED"),
+    ]);
+    env.rekey_with_map(&map).unwrap();
+
+    assert_eq!(
+      env.to_string(),
+      "# Docs\nDB_HOST=localhost\nDB_PORT=5432\nKEEP=1\n"
+    );
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn test_rekey_with_map_collision() {
+    let input = "OLD_HOST=localhost\nDB_HOST=db";
+    let mut env: EnvFile = input.try_into().unwrap();
+
+    let map = HashMap::from([("OLD_HOST", "DB_HOST")]);
+    assert_eq!(
+      env.rekey_with_map(&map),
+      Err(RekeyError::Collision {
+        from: "OLD_HOST".to_string(),
+        to: "DB_HOST".to_string(),
+      })
+    );
+    assert_eq!(env.to_string(), format!("{}\n", input));
+  }
+
+  #[test]
+  fn test_unknown_annotations_preserved() {
+    let input =
+      "# @deprecated use NEW_KEY\n# @owner platform-team\n# @foo\nOLD_KEY=1\n# @bar baz\n";
+    let env: EnvFile = input.try_into().unwrap();
+
+    let annotations: Vec<Annotation> = env.get("OLD_KEY").unwrap().annotations().collect();
+    assert_eq!(
+      annotations,
+      vec![
+        Annotation::Deprecated("use NEW_KEY"),
+        Annotation::Unknown {
+          name: "owner",
+          value: "platform-team"
+        },
+        Annotation::Unknown {
+          name: "foo",
+          value: ""
+        },
+      ]
+    );
+    assert_eq!(env.get("OLD_KEY").unwrap().preceding_comments.len(), 3);
+    assert_eq!(env.to_string(), input);
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn test_to_template() {
+    let input = "# API access\nAPI_KEY=abc123 # rotate monthly\n\n# Database\nDB_HOST=localhost\nDB_PASSWORD=hunter2\n";
+    let env: EnvFile = input.try_into().unwrap();
+
+    assert_eq!(
+      env.to_template().to_string(),
+      "# API access\nAPI_KEY= # rotate monthly\n\n# Database\nDB_HOST=localhost\nDB_PASSWORD=\n"
+    );
+  }
+
+  #[test]
+  fn test_parse_lossy() {
+    let input = "# Docs\nKEY=value\nnot a variable\n# Kept\nOTHER=1";
+
+    assert!(EnvFile::try_from(input).is_err());
+
+    let (env, errors) = EnvFile::parse_lossy(input, &ParseOptions::default());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(env.to_string(), "# Docs\nKEY=value\n# Kept\nOTHER=1\n");
+  }
+
+  #[test]
+  fn test_parse_lossy_borrows_input() {
+    let input = "# Docs\nKEY=value # note\nOTHER=1";
+
+    let (lossy, errors) = EnvFile::parse_lossy(input, &ParseOptions::default());
+    assert!(errors.is_empty());
+    assert_eq!(lossy, EnvFile::try_from(input).unwrap());
+
+    let var = lossy.get("KEY").unwrap();
+    assert!(matches!(var.key, Cow::Borrowed(_)));
+    assert!(matches!(var.value, Cow::Borrowed(_)));
+    assert!(matches!(
+      var.preceding_comments[0].content,
+      Cow::Borrowed(_)
+    ));
+    assert!(matches!(
+      var.inline_comment.as_ref().unwrap().content,
+      Cow::Borrowed(_)
+    ));
+  }
+
+  #[test]
+  fn test_split_matches_str_search() {
+    let inputs = [
+      "KEY=value",
+      "KEY=a=b # c # d",
+      "URL=http://host/#anchor",
+      "KEY=",
+      "=",
+      "#",
+      "no delimiter",
+      "KÉY=välue # ñote",
+      "",
+    ];
+
+    for input in inputs {
+      for delimiter in [ASSIGNMENT_OPERATOR, COMMENT_PREFIX] {
+        assert_eq!(
+          split_once(input, delimiter),
+          input.split_once(delimiter),
+          "{input:?}"
+        );
+        assert_eq!(
+          rsplit_once(input, delimiter),
+          input.rsplit_once(delimiter),
+          "{input:?}"
+        );
+      }
+    }
+  }
+
+  #[test]
+  fn test_footer_start() {
+    let env: EnvFile = "# Docs\nKEY=value\n\n# Footer\n# More".try_into().unwrap();
+    assert_eq!(env.footer_start(), 1);
+
+    let env: EnvFile = "KEY=value\n\n".try_into().unwrap();
+    assert_eq!(env.footer_start(), env.entries.len());
+
+    let env: EnvFile = "# Only comments".try_into().unwrap();
+    assert_eq!(env.footer_start(), 1);
+  }
+
+  #[test]
+  fn test_display_pads_key_column() {
+    let var: EnvVariable = "DB_HOST=localhost # primary".try_into().unwrap();
+
+    assert_eq!(format!("{}", var), "DB_HOST=localhost # primary");
+    assert_eq!(format!("{:10}", var), "DB_HOST   =localhost # primary");
+    assert_eq!(format!("{:>10}", var), "   DB_HOST=localhost # primary");
+    assert_eq!(format!("{:.^11}", var), "..DB_HOST..=localhost # primary");
+    assert_eq!(format!("{:3}", var), "DB_HOST=localhost # primary");
+
+    let env: EnvFile = "# Docs\nHOST=a\nPORT_NUMBER=1\n".try_into().unwrap();
+    assert_eq!(
+      format!("{:12}", env),
+      "# Docs\nHOST        =a\nPORT_NUMBER =1\n"
+    );
+    assert_eq!(format!("{}", env), "# Docs\nHOST=a\nPORT_NUMBER=1\n");
+  }
+
+  #[test]
+  fn test_content_hash() {
+    let hash = |input: &str| EnvFile::try_from(input).unwrap().content_hash();
+    let base = hash("HOST=localhost\nPORT=5432");
+
+    assert_eq!(hash("# Docs\nHOST=localhost # inline\n\nPORT=5432"), base);
+    assert_eq!(hash("PORT=5432\nHOST=localhost"), base);
+    assert_eq!(hash("HOST=localhost\nPORT=5432\nHOST=ignored"), base);
+    assert_ne!(hash("HOST=localhost\nPORT=5433"), base);
+    assert_ne!(hash("HOST=localhost"), base);
+    assert_ne!(hash("AB=C"), hash("A=BC"));
+  }
+
+  #[test]
+  fn test_heredoc_value() {
+    let input = "# Signing key\nPRIVATE_KEY<<EOF # PEM\n-----BEGIN KEY-----\nabc=\n-----END KEY-----\nEOF\nNEXT=1\n";
+    let env: EnvFile = input.try_into().unwrap();
+
+    let var = env.get("PRIVATE_KEY").unwrap();
+    assert_eq!(var.value, "-----BEGIN KEY-----\nabc=\n-----END KEY-----");
+    assert_eq!(var.heredoc.as_deref(), Some("EOF"));
+    assert_eq!(var.inline_comment.as_ref().unwrap().to_string(), "# PEM");
+    assert_eq!(var.preceding_comments.len(), 1);
+    assert_eq!(env.get("NEXT").unwrap().value, "1");
+    assert_eq!(env.to_string(), input);
+  }
+
+  #[test]
+  fn test_entries_with_lines() {
+    let input = "# Signing key\nPRIVATE_KEY<<EOF\nline one\nline two\nEOF\n\nEMPTY<<EOF\nEOF\n# Footer\nNEXT=1\n";
+    let env: EnvFile = input.try_into().unwrap();
+
+    let lines: Vec<(usize, &EnvEntry)> = env.entries_with_lines().collect();
+    assert_eq!(lines.len(), 4);
+    assert_eq!(lines[0].0, 1);
+    assert!(matches!(lines[0].1, EnvEntry::Variable(var) if var.key == "PRIVATE_KEY"));
+    assert_eq!(lines[1], (6, &EnvEntry::EmptyLine));
+    assert_eq!(lines[2].0, 7);
+    assert_eq!(lines[3].0, 9);
+    assert!(matches!(lines[3].1, EnvEntry::Variable(var) if var.key == "NEXT"));
+  }
+
+  #[test]
+  fn test_multiline_quoted_value() {
+    let input =
+      "PRIVATE_KEY=\"-----BEGIN KEY-----\nabc\\\"def\n-----END KEY-----\" # PEM\nNEXT=1\n";
     let env: EnvFile = input.try_into().unwrap();
 
-    let mut iter = env.entries.iter();
+    let var = env.get("PRIVATE_KEY").unwrap();
+    assert_eq!(
+      var.value,
+      "\"-----BEGIN KEY-----\nabc\\\"def\n-----END KEY-----\""
+    );
+    assert_eq!(var.inline_comment.as_ref().unwrap().to_string(), "# PEM");
+    assert_eq!(env.get("NEXT").unwrap().value, "1");
+    assert_eq!(env.to_string(), input);
+    assert_eq!(
+      env
+        .entries_with_lines()
+        .map(|(line, _)| line)
+        .collect::<Vec<_>>(),
+      vec![1, 4]
+    );
+    assert!(EnvFile::validate(input, &ParseOptions::default()).is_empty());
 
-    // First entry should be KEY variable with one preceding comment
-    match iter.next().unwrap() {
-      EnvEntry::Variable(var) => {
-        assert_eq!(var.key, "KEY");
-        assert_eq!(var.value, "value");
-        assert_eq!(var.preceding_comments.len(), 1);
-        assert_eq!(var.preceding_comments[0].to_string(), "# This is a comment");
-      }
-      _ => panic!("Expected variable"),
-    }
+    let unterminated: EnvFile = "KEY=\"open\nOTHER=1\n".try_into().unwrap();
+    assert_eq!(unterminated.get("KEY").unwrap().value, "\"open");
+    assert!(EnvFile::try_from("KEY=\"a\nb\" trailing\n").is_err());
+  }
 
-    // Second entry should be TEST variable with two preceding comments
-    match iter.next().unwrap() {
-      EnvEntry::Variable(var) => {
-        assert_eq!(var.key, "TEST");
-        assert_eq!(var.value, "123");
-        assert_eq!(var.preceding_comments.len(), 2);
-        assert_eq!(var.preceding_comments[0].to_string(), "# Another comment");
-        assert_eq!(var.preceding_comments[1].to_string(), "# Multi line");
+  #[test]
+  fn test_heredoc_missing_terminator() {
+    let input = "CERT<<END\nline one\nline two\n";
+
+    match EnvFile::try_from(input) {
+      Err(ParseError::UnterminatedHeredoc { key, terminator }) => {
+        assert_eq!(key, "CERT");
+        assert_eq!(terminator, "END");
       }
-      _ => panic!("Expected variable"),
+      other => panic!("Expected UnterminatedHeredoc, got {:?}", other),
     }
 
-    assert!(iter.next().is_none());
+    let (env, errors) = EnvFile::parse_lossy("CERT<<END\nKEY=value", &ParseOptions::default());
+    assert_eq!(errors.len(), 1);
+    assert_eq!(env.get("KEY").unwrap().value, "value");
   }
 
   #[test]
-  fn test_parse_inline_comments() {
-    let input = "KEY=value # This is inline\nTEST=123";
+  fn test_squeeze_blank_lines() {
+    let mut env: EnvFile = "A=1\n\n\n\n# Section\nB=2\n\nC=3".try_into().unwrap();
+
+    assert!(env.squeeze_blank_lines());
+    assert_eq!(env.to_string(), "A=1\n\n# Section\nB=2\n\nC=3\n");
+    assert!(!env.squeeze_blank_lines());
+  }
+
+  #[test]
+  fn test_comment_style() {
+    let style = |line: &str| EnvComment::try_from(line).unwrap().style();
+
+    assert_eq!(style("#"), CommentStyle::Blank);
+    assert_eq!(style("#############"), CommentStyle::Divider);
+    assert_eq!(style("# -=-=-=-"), CommentStyle::Divider);
+    assert_eq!(style("# ##"), CommentStyle::Text);
+    assert_eq!(style("# @deprecated"), CommentStyle::Annotation);
+    assert_eq!(style("# DEBUG=true"), CommentStyle::DisabledVariable);
+    assert_eq!(style("# === Database ==="), CommentStyle::Text);
+  }
+
+  #[test]
+  fn test_compact_keeps_dividers() {
+    let input = "A=1\n\n\n#############\n\n\n#\n\n# Notes\n\nB=2\n";
+    let mut env: EnvFile = input.try_into().unwrap();
+
+    assert!(env.compact(true));
+    assert_eq!(env.to_string(), "A=1\n\n#############\n\n# Notes\n\nB=2\n");
+    assert!(!env.compact(true));
+
+    let mut env: EnvFile = input.try_into().unwrap();
+    assert!(env.compact(false));
+    assert_eq!(env.to_string(), "A=1\n\n# Notes\n\nB=2\n");
+  }
+
+  #[test]
+  fn test_set_prefixed_keys() {
+    let env: EnvFile = "set FOO=1\nexport BAR=2\nFOO=3\nset DB_HOST=db\n"
+      .try_into()
+      .unwrap();
+
+    #[cfg(feature = "std")]
+    assert_eq!(
+      env.keys_set(),
+      ["FOO", "BAR", "DB_HOST"].into_iter().collect()
+    );
+    let template: EnvFile = "FOO=\nDB_HOST=\nNEW=\n".try_into().unwrap();
+    assert_eq!(env.missing_keys_from(&template), ["NEW"]);
+
+    let plain: EnvFile = "FOO=1\nBAR=2\nDB_HOST=db\n".try_into().unwrap();
+    assert_eq!(env.content_hash(), plain.content_hash());
+
+    let grouped = env.grouped_by_prefix(false);
+    let order: Vec<&str> = grouped.keys().collect();
+    assert_eq!(order, ["BAR", "FOO", "FOO", "DB_HOST"]);
+
+    let mut deduped = env.clone();
+    assert_eq!(deduped.dedup_keys(DedupKeep::FirstKeepFirstPos), 1);
+    assert_eq!(
+      deduped.to_string(),
+      "set FOO=1\nexport BAR=2\nset DB_HOST=db\n"
+    );
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn test_keys_set_difference() {
+    let local: EnvFile = "# Docs\nHOST=a\nLEGACY=1\nPORT=80\nLEGACY=2"
+      .try_into()
+      .unwrap();
+    let template: EnvFile = "HOST=\nPORT=\nNEW=".try_into().unwrap();
+
+    let local_keys = local.keys_set();
+    let template_keys = template.keys_set();
+
+    assert_eq!(local_keys.len(), 3);
+    assert_eq!(
+      local_keys.difference(&template_keys).collect::<Vec<_>>(),
+      vec![&"LEGACY"]
+    );
+    assert_eq!(
+      template_keys.difference(&local_keys).collect::<Vec<_>>(),
+      vec![&"NEW"]
+    );
+  }
+
+  #[test]
+  fn test_attach_across_blank() {
+    let input = "# Database\n\nDB_HOST=localhost\n# Trailing\n\n";
+
     let env: EnvFile = input.try_into().unwrap();
+    assert!(env.get("DB_HOST").unwrap().preceding_comments.is_empty());
+    assert_eq!(env.orphan_comments().len(), 2);
+    assert_eq!(env.to_string(), input);
 
-    match &env.entries[0] {
-      EnvEntry::Variable(var) => {
-        assert_eq!(var.key, "KEY");
-        assert_eq!(var.value, "value");
-        assert_eq!(
-          var.inline_comment,
-          Some(EnvComment(Cow::Owned(" This is inline".to_string())))
-        );
-      }
-      _ => panic!("Expected variable"),
-    }
+    let options = ParseOptions {
+      attach_across_blank: true,
+      ..Default::default()
+    };
+    let env = EnvFile::parse_with_options(input, &options).unwrap();
+    let var = env.get("DB_HOST").unwrap();
+    assert_eq!(var.preceding_comments[0].to_string(), "# Database");
+    assert_eq!(env.orphan_comments().len(), 1);
+    assert_eq!(
+      env.to_string(),
+      "# Database\nDB_HOST=localhost\n# Trailing\n\n"
+    );
   }
 
   #[test]
-  fn test_roundtrip() {
-    let input = "# Comment\nKEY=value\n\n# Orphan\nTEST=123 # inline";
+  fn test_rewrite_values() {
+    let input = "# Flags\nFEATURE_A=on # docs\nFEATURE_B=Off\nMODE=debug\n";
+    let mut env: EnvFile = input.try_into().unwrap();
+
+    env.rewrite_values(|key, value| key.starts_with("FEATURE_").then(|| value.to_uppercase()));
+
+    assert_eq!(
+      env.to_string(),
+      "# Flags\nFEATURE_A=ON # docs\nFEATURE_B=OFF\nMODE=debug\n"
+    );
+  }
+
+  #[test]
+  fn test_validate_whitespace_duplicate_keys() {
+    let input = "# Docs\n KEY =1\nOTHER=x\nKEY=2\n KEY =3\nOTHER=y";
+
+    assert_eq!(
+      EnvFile::validate(input, &ParseOptions::default()),
+      vec![Diagnostic::WhitespaceDuplicateKey {
+        key: "KEY".to_string(),
+        first_line: 2,
+        line: 4,
+      }]
+    );
+    assert!(EnvFile::validate("KEY=1\nKEY=2", &ParseOptions::default()).is_empty());
+  }
+
+  #[test]
+  fn test_validate_invalid_keys() {
+    let diagnostics =
+      EnvFile::validate("MY-KEY=1\nexport OK=2\n2FA=on\n", &ParseOptions::default());
+
+    assert_eq!(
+      diagnostics,
+      vec![
+        Diagnostic::InvalidKey {
+          key: "MY-KEY".to_string(),
+          line: 1,
+        },
+        Diagnostic::InvalidKey {
+          key: "2FA".to_string(),
+          line: 3,
+        },
+      ]
+    );
+  }
+
+  #[test]
+  fn test_lint() {
+    let input =
+      "# Docs\nKEY=1\nnot an assignment\n KEY =2\nKEY=3\nCERT<<EOF\nbody line\nEOF\nCERT=again\n";
+    let diagnostics = EnvFile::lint(input, &ParseOptions::default());
+
+    assert_eq!(
+      diagnostics,
+      vec![
+        Diagnostic::InvalidLine { line: 3 },
+        Diagnostic::WhitespaceDuplicateKey {
+          key: "KEY".to_string(),
+          first_line: 2,
+          line: 4,
+        },
+        Diagnostic::DuplicateKey {
+          key: "KEY".to_string(),
+          first_line: 2,
+          line: 5,
+        },
+        Diagnostic::DuplicateKey {
+          key: "CERT".to_string(),
+          first_line: 6,
+          line: 9,
+        },
+      ]
+    );
+    assert_eq!(
+      diagnostics[2].to_string(),
+      "KEY is already defined on line 2"
+    );
+    assert!(EnvFile::lint("# Docs\nA=1\nB=2\n", &ParseOptions::default()).is_empty());
+  }
+
+  #[test]
+  fn test_reorder_like() {
+    let template: EnvFile = "DB_HOST=\nDB_PORT=5432\nAPI_KEY=\n".try_into().unwrap();
+    let mut env: EnvFile =
+      "# Local\nEXTRA=1\nAPI_KEY=secret\n\n# Database\nDB_PORT=6543\nLATE=2\nDB_HOST=localhost\n"
+        .try_into()
+        .unwrap();
+
+    env.reorder_like(&template);
+    let keys: Vec<&str> = env.variables().map(|var| var.name()).collect();
+    assert_eq!(keys, vec!["DB_HOST", "DB_PORT", "API_KEY", "EXTRA", "LATE"]);
+    assert_eq!(
+      env.to_string(),
+      "DB_HOST=localhost\n# Database\nDB_PORT=6543\n\nAPI_KEY=secret\n# Local\nEXTRA=1\nLATE=2\n"
+    );
+  }
+
+  #[cfg(feature = "std")]
+  #[test]
+  fn test_group_by() {
+    let env: EnvFile =
+      "DB_HOST=localhost\nAPI_KEY=secret\n# Port\nDB_PORT=5432\nDEBUG=true\nexport API_URL=http://api\n"
+        .try_into()
+        .unwrap();
+
+    let groups = env.group_by(|key| key.split_once('_').map(|(prefix, _)| prefix.to_string()));
+    let keys = |group: Option<&str>| -> Vec<&str> {
+      groups[&group.map(str::to_string)]
+        .iter()
+        .map(|var| var.name())
+        .collect()
+    };
+
+    assert_eq!(groups.len(), 3);
+    assert_eq!(keys(Some("DB")), vec!["DB_HOST", "DB_PORT"]);
+    assert_eq!(keys(Some("API")), vec!["API_KEY", "API_URL"]);
+    assert_eq!(keys(None), vec!["DEBUG"]);
+  }
+
+  #[test]
+  fn test_grouped_by_prefix() {
+    let env: EnvFile =
+      "DB_PORT=5432\nDEBUG=1\n\nAPP_NAME=demo\n# Database host\nDB_HOST=localhost\nAPP_ENV=dev"
+        .try_into()
+        .unwrap();
+
+    assert_eq!(
+      env.grouped_by_prefix(false).to_string(),
+      "DEBUG=1\n\nAPP_ENV=dev\nAPP_NAME=demo\n\n# Database host\nDB_HOST=localhost\nDB_PORT=5432\n"
+    );
+    assert_eq!(
+      env.grouped_by_prefix(true).to_string(),
+      "DEBUG=1\n\n# APP\nAPP_ENV=dev\nAPP_NAME=demo\n\n# DB\n# Database host\nDB_HOST=localhost\nDB_PORT=5432\n"
+    );
+  }
+
+  #[test]
+  fn test_dedup_keys_policies() {
+    let input = "KEY=1\nA=a\n# Second\nKEY=2\nB=b\nKEY=3 # last\nC=c";
+    let dedup = |keep| {
+      let mut env: EnvFile = input.try_into().unwrap();
+      assert_eq!(env.dedup_keys(keep), 2);
+      env.to_string()
+    };
+
+    assert_eq!(
+      dedup(DedupKeep::FirstKeepFirstPos),
+      "KEY=1\nA=a\nB=b\nC=c\n"
+    );
+    assert_eq!(
+      dedup(DedupKeep::LastKeepLastPos),
+      "A=a\nB=b\nKEY=3 # last\nC=c\n"
+    );
+    assert_eq!(
+      dedup(DedupKeep::LastKeepFirstPos),
+      "KEY=3 # last\nA=a\nB=b\nC=c\n"
+    );
+
+    let mut env: EnvFile = "A=1\nB=2".try_into().unwrap();
+    assert_eq!(env.dedup_keys(DedupKeep::default()), 0);
+  }
+
+  #[test]
+  fn test_extract() {
+    let env: EnvFile =
+      "# Header\n\n# Database host\n# Required\nDB_HOST=localhost # primary\nPORT=80"
+        .try_into()
+        .unwrap();
+
+    let extracted = env.extract("DB_HOST").unwrap();
+    assert_eq!(
+      extracted.to_string(),
+      "# Database host\n# Required\nDB_HOST=localhost # primary\n"
+    );
+    assert!(env.extract("MISSING").is_none());
+  }
+
+  #[test]
+  fn test_custom_comment_prefixes() {
+    let options = ParseOptions {
+      comment_prefixes: vec!["//".to_string()],
+      ..Default::default()
+    };
+    let input = "// Service\nKEY=value // note\nURL=http://host\nMIXED=1 // a # b\nHASH=2 # c\n";
+    let env = EnvFile::parse_with_options(input, &options).unwrap();
+
+    let key = env.get("KEY").unwrap();
+    assert_eq!(key.preceding_comments[0].marker(), "//");
+    assert_eq!(key.value, "value");
+    assert_eq!(key.inline_comment.as_ref().unwrap().marker(), "//");
+    assert_eq!(key.inline_comment.as_ref().unwrap().to_string(), "// note");
+    assert_eq!(env.get("URL").unwrap().value, "http://host");
+    assert!(env.get("URL").unwrap().inline_comment.is_none());
+    assert_eq!(
+      env
+        .get("MIXED")
+        .unwrap()
+        .inline_comment
+        .as_ref()
+        .unwrap()
+        .to_string(),
+      "// a # b"
+    );
+    assert_eq!(
+      env
+        .get("HASH")
+        .unwrap()
+        .inline_comment
+        .as_ref()
+        .unwrap()
+        .marker(),
+      "#"
+    );
+    assert_eq!(env.to_string(), input);
+    assert!(EnvFile::validate(input, &options).is_empty());
+
+    let default = EnvVariable::try_from("KEY=value // note").unwrap();
+    assert_eq!(default.value, "value // note");
+  }
+
+  #[test]
+  fn test_canonicalize_comment_marker() {
+    let options = ParseOptions {
+      comment_prefixes: vec![";".to_string()],
+      ..Default::default()
+    };
+    let input = "; Legacy section\n\n# Database\n; Host name\nDB_HOST=localhost # primary\n";
+    let mut env = EnvFile::parse_with_options(input, &options).unwrap();
+
+    assert_eq!(env.to_string(), input);
+    assert_eq!(env.orphan_comments()[0].marker(), ";");
+
+    env.canonicalize_comment_marker("#");
+    assert_eq!(
+      env.to_string(),
+      "# Legacy section\n\n# Database\n# Host name\nDB_HOST=localhost # primary\n"
+    );
+    assert!(EnvFile::try_from(input).is_err());
+  }
+
+  #[test]
+  fn test_export_prefix() {
+    let var = EnvVariable::try_from("export FOO=bar").unwrap();
+    assert_eq!(var.key, "FOO");
+    assert_eq!(var.value, "bar");
+    assert!(var.exported);
+    assert_eq!(var.to_string(), "export FOO=bar");
+
+    let var = EnvVariable::try_from("export FOO=bar # comment").unwrap();
+    assert_eq!(var.key, "FOO");
+    assert!(var.exported);
+    assert_eq!(
+      var.inline_comment.as_ref().unwrap().to_string(),
+      "# comment"
+    );
+    assert_eq!(var.to_string(), "export FOO=bar # comment");
+
+    let var = EnvVariable::try_from("export=1").unwrap();
+    assert_eq!(var.key, "export");
+    assert!(!var.exported);
+    assert_eq!(var.to_string(), "export=1");
+
+    let input = "export  SPACED=1
+export CERT<<EOF
+line
+EOF
+";
     let env: EnvFile = input.try_into().unwrap();
-    let output = env.to_string();
+    assert!(!env.get("SPACED").unwrap().exported);
+    assert!(env.get("CERT").unwrap().exported);
+    assert_eq!(env.to_string(), input);
+  }
 
-    // Parse the output again and compare
-    let env2: EnvFile = output.as_str().try_into().unwrap();
-    assert_eq!(env, env2);
+  #[test]
+  fn test_lookup_ignores_export_prefix() {
+    let mut env: EnvFile = "export FOO=1\nset  BAR=2\nBAZ=3".try_into().unwrap();
+
+    assert_eq!(env.get("FOO").unwrap().value, "1");
+    assert_eq!(env.get("FOO").unwrap().name(), "FOO");
+    assert_eq!(env.get("BAR").unwrap().value, "2");
+    assert_eq!(env.get("export BAZ").unwrap().value, "3");
+    assert!(env.get("EXPORT").is_none());
+
+    env.set("FOO", "changed");
+    assert_eq!(env.to_string(), "export FOO=changed\nset  BAR=2\nBAZ=3\n");
   }
 
   #[test]
-  fn test_env_entry_from_str() {
-    // Test empty line
-    let entry: EnvEntry = "".try_into().unwrap();
-    assert_eq!(entry, EnvEntry::EmptyLine);
+  fn test_space_before_comment_on_empty() {
+    let compact = RenderOptions {
+      space_before_comment_on_empty: false,
+      ..Default::default()
+    };
 
-    // Test comment
-    let entry: EnvEntry = "# This is a comment".try_into().unwrap();
-    match entry {
-      EnvEntry::OrphanComment(comment) => assert_eq!(
-        comment,
-        EnvComment(Cow::Owned(" This is a comment".to_string()))
-      ),
-      _ => panic!("Expected OrphanComment"),
+    let spaced = "API_KEY= # ask the team\nPORT=8080 # default\n";
+    let env: EnvFile = spaced.try_into().unwrap();
+    assert_eq!(env.render(&RenderOptions::default()), spaced);
+    assert_eq!(
+      env.render(&compact),
+      "API_KEY=# ask the team\nPORT=8080 # default\n"
+    );
+
+    let input = "API_KEY=# ask the team\nPORT=8080 # default\n";
+    let env: EnvFile = input.try_into().unwrap();
+    assert_eq!(env.get("API_KEY").unwrap().value, "");
+    assert_eq!(env.render(&compact), input);
+    assert_eq!(env.to_string(), spaced);
+  }
+
+  #[test]
+  fn test_repair_split_values() {
+    let mut env: EnvFile = "URL=\"https://example.com/#top\"\nCOLOR='#fff' # theme\n"
+      .try_into()
+      .unwrap();
+    assert_eq!(env.get("URL").unwrap().value, "\"https://example.com/");
+
+    assert_eq!(env.repair_split_values(), 2);
+    assert_eq!(
+      env.get("URL").unwrap().value,
+      "\"https://example.com/#top\""
+    );
+    assert!(env.get("URL").unwrap().inline_comment.is_none());
+    assert_eq!(env.get("COLOR").unwrap().value, "'#fff'");
+    assert_eq!(
+      env.to_string(),
+      "URL=\"https://example.com/#top\"\nCOLOR='#fff' # theme\n"
+    );
+  }
+
+  #[test]
+  fn test_repair_split_values_ambiguous() {
+    let input = "MOTD=\"hello # world\nQUOTED=\"a\" # b\"\nPLAIN=1 # note\nODD=\"x # y\" z\n";
+    let mut env: EnvFile = input.try_into().unwrap();
+
+    assert_eq!(env.repair_split_values(), 0);
+    assert_eq!(env.to_string(), input);
+  }
+
+  #[test]
+  fn test_missing_keys_from() {
+    let template: EnvFile =
+      "# Database\nDB_HOST=\nDB_PORT=5432\nAPI_KEY=\nDEBUG=false\nDB_PORT=5433\n"
+        .try_into()
+        .unwrap();
+    let local: EnvFile = "export DB_HOST=localhost\nDEBUG=true\nEXTRA=1\n"
+      .try_into()
+      .unwrap();
+
+    assert_eq!(
+      local.missing_keys_from(&template),
+      vec!["DB_PORT", "API_KEY"]
+    );
+    assert!(template.missing_keys_from(&template).is_empty());
+  }
+
+  #[test]
+  fn test_as_disabled_variable() {
+    let env: EnvFile = "# DEBUG=true # verbose logs\n#export TOKEN=\nKEY=1\n"
+      .try_into()
+      .unwrap();
+    let EnvEntry::Variable(var) = &env.entries[0] else {
+      panic!("Expected a variable");
+    };
+
+    let disabled = var.preceding_comments[0].as_disabled_variable().unwrap();
+    assert_eq!(disabled.key, "DEBUG");
+    assert_eq!(disabled.value, "true");
+    assert_eq!(
+      disabled.inline_comment.unwrap().to_string(),
+      "# verbose logs"
+    );
+
+    let disabled = var.preceding_comments[1].as_disabled_variable().unwrap();
+    assert_eq!(disabled.name(), "TOKEN");
+    assert_eq!(disabled.value, "");
+  }
+
+  #[test]
+  fn test_as_disabled_variable_prose() {
+    for content in [
+      " Set the log level, e.g. LOG_LEVEL=debug",
+      " Database settings",
+      " see https://example.com/?a=b",
+      "# NESTED=1",
+      " DEBUG",
+    ] {
+      assert!(EnvComment::new(content).as_disabled_variable().is_none());
     }
+  }
 
-    // Test variable
-    let entry: EnvEntry = "KEY=value".try_into().unwrap();
-    match entry {
-      EnvEntry::Variable(var) => {
-        assert_eq!(var.key, "KEY");
-        assert_eq!(var.value, "value");
-        assert!(var.preceding_comments.is_empty());
-        assert!(var.inline_comment.is_none());
+  #[test]
+  fn test_regions() {
+    let options = ParseOptions {
+      regions: true,
+      ..Default::default()
+    };
+    let input = "# region Database\n# Primary host\nDB_HOST=localhost\n#region Replica\nDB_REPLICA=replica\n#endregion\nDB_PORT=5432\n# Closing note\n# endregion\nDEBUG=false\n";
+    let env = EnvFile::parse_with_options(input, &options).unwrap();
+
+    assert_eq!(
+      env.entries[0],
+      EnvEntry::RegionStart {
+        name: "Database".into(),
+        comment: EnvComment::new(" region Database"),
       }
-      _ => panic!("Expected Variable"),
-    }
+    );
+    assert!(matches!(&env.entries[2], EnvEntry::RegionStart { name, .. } if name == "Replica"));
+    assert_eq!(
+      env.entries[7],
+      EnvEntry::RegionEnd {
+        comment: EnvComment::new(" endregion")
+      }
+    );
+    assert_eq!(
+      env.entries[6],
+      EnvEntry::OrphanComment(EnvComment::new(" Closing note"))
+    );
+    assert_eq!(env.get("DB_HOST").unwrap().preceding_comments.len(), 1);
+    assert_eq!(env.to_string(), input);
 
-    // Test variable with inline comment
-    let entry: EnvEntry = "KEY=value # comment".try_into().unwrap();
-    match entry {
-      EnvEntry::Variable(var) => {
-        assert_eq!(var.key, "KEY");
-        assert_eq!(var.value, "value");
-        assert_eq!(
-          var.inline_comment,
-          Some(EnvComment(Cow::Owned(" comment".to_string())))
-        );
+    let database = env.region("Database").unwrap();
+    assert_eq!(database.len(), 6);
+    let replica: Vec<&str> = env
+      .region("Replica")
+      .unwrap()
+      .iter()
+      .filter_map(|entry| match entry {
+        EnvEntry::Variable(var) => Some(var.key.as_ref()),
+        _ => None,
+      })
+      .collect();
+    assert_eq!(replica, vec!["DB_REPLICA"]);
+    assert!(env.region("Missing").is_none());
+
+    let plain: EnvFile = input.try_into().unwrap();
+    assert!(
+      plain
+        .entries
+        .iter()
+        .all(|entry| !matches!(entry, EnvEntry::RegionStart { .. }))
+    );
+    assert_eq!(plain.to_string(), input);
+  }
+
+  #[test]
+  fn test_canonicalize_booleans() {
+    let mut env: EnvFile =
+      "DEBUG=yes\nexport CACHE=off\nMETRICS=1 # opt-in\nVERBOSE=maybe\nPORT=0\n"
+        .try_into()
+        .unwrap();
+
+    env.canonicalize_booleans(&["DEBUG", "CACHE", "METRICS", "VERBOSE"], "true", "false");
+    assert_eq!(
+      env.to_string(),
+      "DEBUG=true\nexport CACHE=false\nMETRICS=true # opt-in\nVERBOSE=maybe\nPORT=0\n"
+    );
+
+    env.canonicalize_booleans(&["DEBUG", "CACHE"], "1", "0");
+    assert_eq!(env.get("DEBUG").unwrap().value, "1");
+    assert_eq!(env.get("CACHE").unwrap().value, "0");
+  }
+
+  #[test]
+  fn test_effective() {
+    let env: EnvFile = r#"NAME='World'
+HOST=localhost
+PORT=5432
+GREETING="Hello\t${NAME}!\n\${NAME} \"quoted\""
+URL=postgres://${HOST}:${PORT}/${MISSING}db
+LITERAL='${HOST}\n'
+A=${B}
+B=x${A}
+OPEN="${HOST"
+"#
+    .try_into()
+    .unwrap();
+
+    assert_eq!(
+      env.effective("GREETING").unwrap(),
+      "Hello\tWorld!\n${NAME} \"quoted\""
+    );
+    assert_eq!(
+      env.effective("URL").unwrap(),
+      "postgres://localhost:5432/db"
+    );
+    assert_eq!(env.effective("LITERAL").unwrap(), "${HOST}\\n");
+    assert_eq!(env.effective("A").unwrap(), "x");
+    assert_eq!(env.effective("OPEN").unwrap(), "${HOST");
+    assert!(env.effective("UNDEFINED").is_none());
+  }
+
+  #[test]
+  fn test_comment_marker_spacing() {
+    let input = "#no-space\n# space\nA=v #no-space\nB=v # space\nC=v #  two\n";
+    let env: EnvFile = input.try_into().unwrap();
+    assert_eq!(env.to_string(), input);
+
+    let a = env.get("A").unwrap();
+    assert_eq!(a.preceding_comments[0], EnvComment::new("no-space"));
+    assert_eq!(a.preceding_comments[1], EnvComment::new(" space"));
+    assert_eq!(a.inline_comment, Some(EnvComment::new("no-space")));
+    assert_eq!(a.to_string(), "#no-space\n# space\nA=v #no-space");
+    assert_eq!(
+      env.get("B").unwrap().inline_comment,
+      Some(EnvComment::new(" space"))
+    );
+    assert_eq!(
+      EnvVariable::try_from("C=v #  two").unwrap().to_string(),
+      "C=v #  two"
+    );
+  }
+
+  #[test]
+  fn test_remove_with() {
+    let input = "A=1\n# Legacy switch\n# Remove after 2.0\nLEGACY=1 # old\nB=2\n";
+
+    let mut env: EnvFile = input.try_into().unwrap();
+    let var = env.remove_with("LEGACY", CommentOnRemove::Delete).unwrap();
+    assert_eq!(var.preceding_comments.len(), 2);
+    assert_eq!(env.to_string(), "A=1\nB=2\n");
+
+    let mut env: EnvFile = input.try_into().unwrap();
+    let var = env.remove_with("LEGACY", CommentOnRemove::Orphan).unwrap();
+    assert!(var.preceding_comments.is_empty());
+    assert_eq!(var.inline_comment.unwrap().to_string(), "# old");
+    assert_eq!(
+      env.to_string(),
+      "A=1\n# Legacy switch\n# Remove after 2.0\nB=2\n"
+    );
+    assert_eq!(env.orphan_comments().len(), 2);
+
+    assert!(
+      env
+        .remove_with("MISSING", CommentOnRemove::Orphan)
+        .is_none()
+    );
+  }
+
+  #[test]
+  fn test_remove() {
+    let input = "# Rotated weekly\nAPI_KEY=old\nDB_HOST=localhost\n";
+    let mut env: EnvFile = input.try_into().unwrap();
+
+    let removed = env.remove("API_KEY").unwrap();
+    assert_eq!(removed.value, "old");
+    assert_eq!(removed.preceding_comments.len(), 1);
+    assert_eq!(env.to_string(), "DB_HOST=localhost\n");
+    assert!(env.remove("API_KEY").is_none());
+
+    let mut env: EnvFile = input.try_into().unwrap();
+    let removed = env.remove_keep_comments("API_KEY").unwrap();
+    assert!(removed.preceding_comments.is_empty());
+    assert_eq!(env.to_string(), "# Rotated weekly\nDB_HOST=localhost\n");
+  }
+
+  #[test]
+  fn test_effective_defaults() {
+    let env: EnvFile = r#"HOST=
+PORT=5432
+URL=${HOST:-localhost}:${PORT:-80}
+FALLBACK="${MISSING:-${HOST:-${PORT}}}/db"
+ASSIGN=${REGION:=eu-west}-${REGION}
+LITERAL='${HOST:-x}'
+"#
+    .try_into()
+    .unwrap();
+
+    assert_eq!(env.effective("URL").unwrap(), "localhost:5432");
+    assert_eq!(env.effective("FALLBACK").unwrap(), "5432/db");
+    assert_eq!(env.effective("ASSIGN").unwrap(), "eu-west-eu-west");
+    assert_eq!(env.effective("LITERAL").unwrap(), "${HOST:-x}");
+    assert_eq!(env.effective("HOST").unwrap(), "");
+  }
+
+  #[test]
+  fn test_maps_and_iterators() {
+    let env: EnvFile = "# Database\nexport HOST=localhost\n\nPORT=5432\nHOST=example.com\n"
+      .try_into()
+      .unwrap();
+
+    assert_eq!(env.keys().collect::<Vec<_>>(), ["HOST", "PORT", "HOST"]);
+    assert_eq!(
+      env.values().collect::<Vec<_>>(),
+      ["localhost", "5432", "example.com"]
+    );
+
+    let map = env.to_btreemap();
+    assert_eq!(map.len(), 2);
+    assert_eq!(map["HOST"], "localhost");
+    assert_eq!(map["PORT"], "5432");
+    #[cfg(feature = "std")]
+    assert_eq!(
+      env.to_hashmap().into_iter().collect::<BTreeMap<_, _>>(),
+      map
+    );
+  }
+
+  #[test]
+  fn test_variables_and_into_iterator() {
+    let mut env: EnvFile = "# Database\nHOST=localhost\n\nPORT=5432\n"
+      .try_into()
+      .unwrap();
+
+    let names: Vec<&str> = env.variables().map(EnvVariable::name).collect();
+    assert_eq!(names, ["HOST", "PORT"]);
+
+    for var in env.variables_mut() {
+      if var.value == "localhost" {
+        var.value = "127.0.0.1".into();
       }
-      _ => panic!("Expected Variable"),
     }
+    assert_eq!(env.to_string(), "# Database\nHOST=127.0.0.1\n\nPORT=5432\n");
 
-    // Test invalid line
-    assert!(EnvEntry::try_from("invalid line without equals").is_err());
+    assert_eq!((&env).into_iter().count(), 3);
+    let entries: Vec<EnvEntry> = env.into_iter().collect();
+    assert_eq!(entries.len(), 3);
+    assert_eq!(entries[1], EnvEntry::EmptyLine);
+  }
+
+  #[test]
+  fn test_resolve() {
+    let env: EnvFile = r#"HOST=localhost
+PORT=5432
+BASE_URL=http://${HOST}:$PORT/
+QUOTED="$HOST\$PORT"
+MISSING=${UNDEFINED}-$UNDEFINED
+DEFAULTED=${REGION:=eu-west}
+"#
+    .try_into()
+    .unwrap();
+
+    let resolved = env.resolve().unwrap();
+    assert_eq!(resolved["BASE_URL"], "http://localhost:5432/");
+    assert_eq!(resolved["QUOTED"], "localhost$PORT");
+    assert_eq!(resolved["MISSING"], "-");
+    assert_eq!(resolved["REGION"], "eu-west");
+    assert_eq!(resolved.len(), 7);
+
+    let options = ResolveOptions {
+      keep_unknown: true,
+      ..Default::default()
+    };
+    assert_eq!(
+      env.resolve_with_options(&options).unwrap()["MISSING"],
+      "${UNDEFINED}-$UNDEFINED"
+    );
+
+    let cyclic: EnvFile = "A=${B}\nB=x$A\n".try_into().unwrap();
+    assert_eq!(
+      cyclic.resolve(),
+      Err(ResolveError::Cycle {
+        chain: vec!["A".to_string(), "B".to_string(), "A".to_string()]
+      })
+    );
+    assert_eq!(cyclic.effective("A").unwrap(), "x$A");
+  }
+
+  #[test]
+  fn test_resolve_env_fallback() {
+    // Stands in for the process environment, which tests running in parallel can't mutate
+    fn lookup(name: &str) -> Option<String> {
+      (name == "ENV_SYNC_TEST_RESOLVE_FALLBACK").then(|| "from-env".to_string())
+    }
+
+    let env: EnvFile = "URL=http://${ENV_SYNC_TEST_RESOLVE_FALLBACK}/"
+      .try_into()
+      .unwrap();
+
+    let options = ResolveOptions {
+      env_fallback: true,
+      env_lookup: Some(lookup),
+      ..Default::default()
+    };
+    assert_eq!(
+      env.resolve_with_options(&options).unwrap()["URL"],
+      "http://from-env/"
+    );
+    assert_eq!(env.resolve().unwrap()["URL"], "http:///");
+  }
+
+  #[test]
+  fn test_detect_cycles() {
+    let env: EnvFile = r#"A=${B}
+B="x${A}"
+SELF=${SELF}
+URL=${HOST}/${A}
+HOST=localhost
+LITERAL='${LITERAL}'
+ESCAPED="\${ESCAPED}"
+"#
+    .try_into()
+    .unwrap();
+
+    assert_eq!(
+      env.detect_cycles(),
+      vec![vec!["A", "B", "A"], vec!["SELF", "SELF"]]
+    );
+    assert!(
+      EnvFile::try_from("A=${B}\nB=${C}\nC=1")
+        .unwrap()
+        .detect_cycles()
+        .is_empty()
+    );
+  }
+
+  #[test]
+  fn test_list_values() {
+    let mut env: EnvFile = "HOSTS=a, b, c, # replicas\nEMPTY=\nPATHS=/usr/bin:/bin"
+      .try_into()
+      .unwrap();
+
+    let hosts = env.get_mut("HOSTS").unwrap();
+    assert_eq!(hosts.as_list(','), vec!["a", "b", "c"]);
+    hosts.set_list(&["db1", " db2 "], ',');
+    assert_eq!(hosts.value, "db1,db2");
+
+    assert!(env.get("EMPTY").unwrap().as_list(',').is_empty());
+    assert_eq!(
+      env.get("PATHS").unwrap().as_list(':'),
+      vec!["/usr/bin", "/bin"]
+    );
+    assert_eq!(
+      env.to_string(),
+      "HOSTS=db1,db2 # replicas\nEMPTY=\nPATHS=/usr/bin:/bin\n"
+    );
+  }
+
+  #[test]
+  fn test_value_state() {
+    let options = ParseOptions {
+      preserve_whitespace: true,
+      ..Default::default()
+    };
+    let input = "EMPTY=\nSPACES=   \nSET=value # note\n";
+    let env = EnvFile::parse_with_options(input, &options).unwrap();
+
+    assert_eq!(env.get("EMPTY").unwrap().value_state(), ValueState::Empty);
+    assert_eq!(
+      env.get("SPACES").unwrap().value_state(),
+      ValueState::Whitespace
+    );
+    assert_eq!(env.get("SET").unwrap().value_state(), ValueState::Set);
+    assert!(env.get("MISSING").is_none());
+    assert_eq!(env.to_string(), input);
+
+    let trimmed: EnvFile = input.try_into().unwrap();
+    assert_eq!(
+      trimmed.get("SPACES").unwrap().value_state(),
+      ValueState::Empty
+    );
   }
 
   #[test]
@@ -422,4 +4010,15 @@ mod tests {
       _ => panic!("Expected Variable"),
     }
   }
+
+  #[cfg(not(feature = "std"))]
+  #[test]
+  fn test_parse_without_std() {
+    let input = "# Docs\nKEY=value # note\n\nOTHER=1\n";
+    let env = EnvFile::try_from(input).unwrap();
+
+    assert_eq!(env.get("KEY").unwrap().value, "value");
+    assert_eq!(env.variables().count(), 2);
+    assert_eq!(env.to_string(), input);
+  }
 }
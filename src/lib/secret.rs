@@ -0,0 +1,104 @@
+//! Secret detection and masking.
+//!
+//! Values of secret keys should never be printed in reports or logs. By default a key is
+//! considered secret when its name ends with a well-known suffix such as `_TOKEN` or
+//! `_PASSWORD`. Alternatively an explicit allowlist of non-secret keys can be used, in which
+//! case every key that isn't listed is treated as secret.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use env_sync::secret::SecretPolicy;
+//!
+//! let policy = SecretPolicy::Heuristic;
+//! assert_eq!(policy.mask("API_TOKEN", "abc123"), "****");
+//! assert_eq!(policy.mask("PORT", "8080"), "8080");
+//! ```
+
+use std::collections::HashSet;
+
+const SECRET_SUFFIXES: [&str; 8] = [
+  "KEY",
+  "SECRET",
+  "TOKEN",
+  "PASSWORD",
+  "PASS",
+  "PWD",
+  "CREDENTIALS",
+  "PRIVATE",
+];
+const MASK: &str = "****";
+
+/// Decides which keys hold secret values.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum SecretPolicy {
+  /// Keys whose last `_`-separated segment is a well-known secret suffix (e.g. `API_KEY`)
+  #[default]
+  Heuristic,
+  /// Only the listed keys are non-secret, every other key is secret
+  Allowlist(HashSet<String>),
+}
+
+impl SecretPolicy {
+  /// Builds an allowlist policy from newline-delimited key names.
+  ///
+  /// Blank lines and lines starting with `#` are ignored.
+  pub fn from_allowlist(content: &str) -> Self {
+    let keys = content
+      .lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .map(str::to_string)
+      .collect();
+    SecretPolicy::Allowlist(keys)
+  }
+
+  /// Returns `true` if the value of `key` must not be exposed.
+  pub fn is_secret(&self, key: &str) -> bool {
+    match self {
+      SecretPolicy::Heuristic => {
+        let last = key.rsplit('_').next().unwrap_or(key);
+        SECRET_SUFFIXES
+          .iter()
+          .any(|suffix| last.eq_ignore_ascii_case(suffix))
+      }
+      SecretPolicy::Allowlist(keys) => !keys.contains(key),
+    }
+  }
+
+  /// Returns the value to display for `key`, masking it if the key is secret.
+  pub fn mask<'v>(&self, key: &str, value: &'v str) -> &'v str {
+    if self.is_secret(key) && !value.is_empty() {
+      MASK
+    } else {
+      value
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_heuristic() {
+    let policy = SecretPolicy::Heuristic;
+
+    assert!(policy.is_secret("API_KEY"));
+    assert!(policy.is_secret("DB_PASSWORD"));
+    assert!(policy.is_secret("TOKEN"));
+    assert!(!policy.is_secret("DB_HOST"));
+    assert!(!policy.is_secret("KEYBOARD_LAYOUT"));
+    assert_eq!(policy.mask("API_KEY", ""), "");
+  }
+
+  #[test]
+  fn test_allowlist() {
+    let policy = SecretPolicy::from_allowlist("# Safe to log\nDB_HOST\n\n PORT \n");
+
+    assert!(!policy.is_secret("DB_HOST"));
+    assert!(!policy.is_secret("PORT"));
+    assert!(policy.is_secret("DB_USER"));
+    assert_eq!(policy.mask("DB_USER", "admin"), "****");
+  }
+}
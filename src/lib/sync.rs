@@ -12,30 +12,56 @@
 //!    - If template value is empty and local has a value, use local value
 //!    - If template has no inline comment but local does, copy local comment
 //!    - If template has no preceding comments but local does, copy local comments
-//! 3. Writes the result back to the local file
+//!    - With `consolidate_comments`, if the merge leaves documentation in both slots (one taken
+//!      from the template and the other from local), the inline comment is moved to the end
+//!      of the preceding comments
+//! 3. Appends the local variables missing from the template to the end, or drops them with
+//!    [`EnvSyncOptions::prune`]
+//! 4. Writes the result back to the local file
+//!
+//! An empty template is treated as "nothing to sync" and leaves the local file untouched.
 //!
 //! # Examples
 //!
 //! ```rust,no_run
-//! use env_sync::sync::{EnvSync, EnvSyncOptions};
+//! use env_sync::sync::{EnvSync, EnvSyncOptions, TemplateSource};
 //! use std::path::PathBuf;
 //!
 //! let options = EnvSyncOptions {
 //!     local_file: Some(PathBuf::from(".env")),
-//!     template_file: PathBuf::from(".env.template"),
+//!     template_source: TemplateSource::File(PathBuf::from(".env.template")),
+//!     ..Default::default()
 //! };
 //!
 //! EnvSync::sync_with_options(options).unwrap();
 //! ```
 
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::{self, Write};
 use std::path::{Path, PathBuf};
 
 #[cfg(feature = "tracing")]
-use tracing::{debug, info, trace};
+use tracing::{debug, info, trace, warn};
 
-use crate::parse::{EnvEntry, EnvFile, ParseError};
+use crate::diff::{Change, DiffOptions, EnvDiff};
+use crate::merge::{MergePolicy, merge};
+use crate::parse::{
+  CommentOnRemove, DedupKeep, EnvComment, EnvEntry, EnvFile, EnvVariable, ParseError, ParseOptions,
+  Source,
+};
+use crate::secret::SecretPolicy;
 
 const DEFAULT_LOCAL_FILENAME: &str = ".env";
+const DEFAULT_TEMPLATE_FILENAME: &str = ".env.template";
+const COMMENT_PREFIX: &str = "#";
+/// Byte order marks of encodings other than UTF-8, longest first so UTF-32 LE isn't read as UTF-16 LE.
+const FOREIGN_BOMS: [&[u8]; 4] = [
+  &[0xFF, 0xFE, 0x00, 0x00],
+  &[0x00, 0x00, 0xFE, 0xFF],
+  &[0xFF, 0xFE],
+  &[0xFE, 0xFF],
+];
 
 /// Main synchronization service for environment files.
 pub struct EnvSync;
@@ -44,56 +70,445 @@ impl EnvSync {
   /// Synchronizes environment files using the provided options.
   ///
   /// Creates the local file if it doesn't exist. Returns an error if the template file doesn't exist.
-  pub fn sync_with_options(options: EnvSyncOptions) -> Result<(), EnvSyncError> {
+  /// An empty template leaves the local file untouched, unless
+  /// [`EnvSyncOptions::allow_empty_template`] is disabled. With [`EnvSyncOptions::dry_run`] set,
+  /// nothing is written and the returned report describes what would change.
+  pub fn sync_with_options(options: EnvSyncOptions) -> Result<SyncReport, EnvSyncError> {
     #[cfg(feature = "tracing")]
     info!("Starting env sync");
 
     let EnvSyncOptions {
       local_file,
-      template_file,
+      template_source,
+      header,
+      overrides,
+      allow_empty_template,
+      listener,
+      dry_run,
+      consolidate_comments,
+      nonsecret_allowlist,
+      #[cfg(feature = "diff")]
+      write_patch,
+      on_local_parse_error,
+      orphan_placement,
+      follow_symlinks,
+      tidy,
+      defaults_file,
+      minimal_rewrite,
+      group_by_prefix,
+      group_headers,
+      comments,
+      fill_from_env,
+      env_lookup,
+      deletions,
+      keep_local_header,
+      region,
+      file_mode,
+      mut local_files,
+      prune,
+      allow_duplicate_template_keys,
+      backup,
+      prefer_local,
+      keys_only,
+      pruned_comments,
+      diff_options,
     } = options;
 
-    let local_path = local_file.unwrap_or_else(|| {
+    let local_path = local_file.or_else(|| local_files.pop()).unwrap_or_else(|| {
       std::env::current_dir()
         .unwrap_or_else(|_| PathBuf::from("."))
         .join(DEFAULT_LOCAL_FILENAME)
     });
+    local_files.retain(|path| *path != local_path);
+    let layer_strs = local_files
+      .iter()
+      .filter(|path| path.exists())
+      .map(|path| Self::read_env_file(path, EnvSyncError::LocalIo))
+      .collect::<Result<Vec<_>, _>>()?;
 
     #[cfg(feature = "tracing")]
-    debug!(?local_path, ?template_file, "Resolved file paths");
+    debug!(?local_path, ?template_source, "Resolved sources");
+
+    let template_str = match template_source {
+      TemplateSource::File(template_file) => {
+        if !template_file.exists() {
+          return Err(EnvSyncError::TemplateNotFound(template_file));
+        }
+        Self::read_env_file(&template_file, EnvSyncError::TemplateIo)?
+      }
+      TemplateSource::Inline(content) => content,
+    };
+
+    let secrets = match nonsecret_allowlist {
+      Some(path) => {
+        let content = std::fs::read_to_string(path).map_err(EnvSyncError::AllowlistIo)?;
+        SecretPolicy::from_allowlist(&content)
+      }
+      None => SecretPolicy::default(),
+    };
+
+    let defaults_str = match &defaults_file {
+      Some(path) => Some(Self::read_env_file(path, EnvSyncError::DefaultsIo)?),
+      None => None,
+    };
+
+    let local_exists = local_path.exists();
+    let local_str = if local_exists {
+      Self::read_env_file(&local_path, EnvSyncError::LocalIo)?
+    } else {
+      String::new()
+    };
+
+    let parse_options = ParseOptions {
+      regions: region.is_some(),
+      ..Default::default()
+    };
+    let mut template_content = EnvFile::parse_with_options(&template_str, &parse_options)
+      .map_err(EnvSyncError::TemplateParse)?;
+
+    if let Some(key) = Self::first_duplicate_key(&template_content) {
+      if !allow_duplicate_template_keys {
+        return Err(EnvSyncError::TemplateDuplicateKey { key });
+      }
+      #[cfg(feature = "tracing")]
+      debug!(
+        "Template defines {} more than once, keeping the last value",
+        key
+      );
+      template_content.dedup_keys(DedupKeep::LastKeepFirstPos);
+    }
+
+    if !allow_empty_template && template_content.variables().next().is_none() {
+      return Err(EnvSyncError::EmptyTemplate);
+    }
+
+    if template_str.trim().is_empty() {
+      #[cfg(feature = "tracing")]
+      info!("Template is empty, leaving local file untouched");
+      if !local_exists && !dry_run {
+        #[cfg(feature = "tracing")]
+        debug!("Creating local file: {:?}", local_path);
+        Self::write_file(&local_path, "", file_mode).map_err(EnvSyncError::CreateLocal)?;
+      }
+      return Ok(SyncReport {
+        local_path,
+        synced: local_str.clone(),
+        original: local_str,
+        written: false,
+        changes: Vec::new(),
+        deprecated: Vec::new(),
+        secrets,
+      });
+    }
+
+    let local_content = Self::parse_local(&local_str, &parse_options, on_local_parse_error)?;
+
+    // Lower-priority layers under the local file, which is still the one compared and written
+    let mut layered: Option<EnvFile> = None;
+    for layer_str in &layer_strs {
+      let layer = Self::parse_local(layer_str, &parse_options, on_local_parse_error)?;
+      layered = Some(match layered {
+        Some(lower) => merge(&lower, &layer, MergePolicy::TakeTheirs),
+        None => layer,
+      });
+    }
+    let layered = layered.map(|lower| merge(&lower, &local_content, MergePolicy::TakeTheirs));
+    let effective_local = layered.as_ref().unwrap_or(&local_content);
+
+    let defaults = match &defaults_str {
+      Some(defaults_str) => {
+        Some(EnvFile::try_from(defaults_str.as_str()).map_err(EnvSyncError::DefaultsParse)?)
+      }
+      None => None,
+    };
+
+    let settings = SyncSettings {
+      consolidate_comments,
+      keep_local_header,
+      prune,
+      pruned_comments,
+      prefer_local,
+      keys_only,
+      region,
+      fill_from_env,
+      env_lookup,
+      defaults,
+      overrides,
+      orphan_placement,
+      group_by_prefix,
+      group_headers,
+      deletions,
+      comments,
+      header,
+      tidy,
+      diff_options,
+    };
+    let (synced, merged) = Self::merge_with(effective_local, &template_content, &settings)?;
+
+    let changes = match &layered {
+      Some(_) => EnvDiff::with_options(&local_content, &synced, &settings.diff_options).changes,
+      None => merged.changes,
+    };
+    let synced = if minimal_rewrite {
+      Self::render_minimal(&synced, &local_content, &local_str)
+    } else {
+      synced.to_string()
+    };
+
+    let written = if dry_run {
+      #[cfg(feature = "tracing")]
+      info!("Dry run, not writing {:?}", local_path);
+      false
+    } else {
+      if backup && local_exists && synced != local_str {
+        Self::backup_local(&local_path)?;
+      }
+      Self::update_local(&synced, &local_path, follow_symlinks, file_mode)? == LocalWrite::Written
+    };
+
+    if written && let Some(listener) = &listener {
+      Self::notify(listener.as_ref(), &changes);
+    }
+
+    let report = SyncReport {
+      local_path,
+      original: local_str,
+      synced,
+      written,
+      changes,
+      deprecated: merged.deprecated,
+      secrets,
+    };
+
+    #[cfg(feature = "diff")]
+    if let Some(patch_path) = write_patch
+      && report.written
+    {
+      #[cfg(feature = "tracing")]
+      debug!("Writing patch to {:?}", patch_path);
+      std::fs::write(patch_path, report.patch()).map_err(EnvSyncError::PatchIo)?;
+    }
+
+    Ok(report)
+  }
+
+  /// Merges `template` into `local` in memory, applying every merge and post-processing step
+  /// of [`EnvSync::sync_with_options`] without reading or writing any file.
+  ///
+  /// The report describes the merge as if `local` was the content of the local file: its
+  /// `local_path` is empty, nothing is `written`, and no values are masked.
+  pub fn merge_with<'a>(
+    local: &EnvFile<'a>,
+    template: &EnvFile<'a>,
+    opts: &SyncSettings<'a>,
+  ) -> Result<(EnvFile<'a>, SyncReport), EnvSyncError> {
+    let deprecated = Self::deprecated_in_use(local, template);
+
+    let mut synced = match &opts.region {
+      Some(name) => Self::sync_region(local, template.clone(), name, opts)?,
+      None => Self::sync(local, template.clone(), opts)?,
+    };
+
+    if opts.fill_from_env {
+      Self::fill_from_env(&mut synced, opts.env_lookup);
+    }
+
+    if let Some(defaults) = &opts.defaults {
+      Self::fill_defaults(&mut synced, defaults);
+    }
+
+    for (key, value) in &opts.overrides {
+      #[cfg(feature = "tracing")]
+      debug!("Overriding value for {}", key);
+      Self::apply_override(
+        &mut synced,
+        key.clone(),
+        value.clone(),
+        opts.orphan_placement,
+      );
+    }
+
+    if opts.group_by_prefix {
+      #[cfg(feature = "tracing")]
+      debug!("Grouping variables by prefix");
+      synced = synced.grouped_by_prefix(opts.group_headers);
+    }
 
-    if !template_file.exists() {
-      return Err(EnvSyncError::TemplateNotFound(template_file));
+    if !opts.deletions.is_empty() {
+      #[cfg(feature = "tracing")]
+      debug!("Deleting keys: {:?}", opts.deletions);
+      synced.entries.retain(|entry| match entry {
+        EnvEntry::Variable(var) => !opts.deletions.iter().any(|key| key == var.name()),
+        _ => true,
+      });
+    }
+
+    for (key, text) in &opts.comments {
+      let Some(var) = synced.get_mut(key) else {
+        #[cfg(feature = "tracing")]
+        warn!("Not commenting {}, it is not in the synced output", key);
+        continue;
+      };
+      var.inline_comment = Some(EnvComment::new(format!(" {}", text)));
     }
 
-    if !local_path.exists() {
+    if let Some(header) = &opts.header {
+      Self::apply_header(&mut synced, header);
+    }
+
+    if opts.tidy && synced.squeeze_blank_lines() {
       #[cfg(feature = "tracing")]
-      debug!("Creating local file: {:?}", local_path);
-      std::fs::write(&local_path, "").map_err(EnvSyncError::CreateLocal)?;
+      debug!("Collapsed repeated empty lines");
+    }
+
+    let report = SyncReport {
+      local_path: PathBuf::new(),
+      original: local.to_string(),
+      synced: synced.to_string(),
+      written: false,
+      changes: EnvDiff::with_options(local, &synced, &opts.diff_options).changes,
+      deprecated,
+      secrets: SecretPolicy::default(),
+    };
+    Ok((synced, report))
+  }
+
+  /// Returns the first key defined a second time in `file`.
+  fn first_duplicate_key(file: &EnvFile) -> Option<String> {
+    let mut seen = std::collections::HashSet::new();
+    file
+      .variables()
+      .find(|var| !seen.insert(var.name()))
+      .map(|var| var.name().to_string())
+  }
+
+  /// Parses the local file, handling invalid lines according to the policy.
+  fn parse_local<'a>(
+    local_str: &'a str,
+    options: &ParseOptions,
+    policy: LocalParseErrorPolicy,
+  ) -> Result<EnvFile<'a>, EnvSyncError> {
+    match EnvFile::parse_with_options(local_str, options) {
+      Ok(local) => Ok(local),
+      Err(error) => match policy {
+        LocalParseErrorPolicy::Abort => Err(EnvSyncError::LocalParse(error)),
+        LocalParseErrorPolicy::SkipLine => {
+          let (local, _errors) = EnvFile::parse_lossy(local_str, options);
+          #[cfg(feature = "tracing")]
+          warn!("Skipped {} invalid local lines", _errors.len());
+          Ok(local)
+        }
+        LocalParseErrorPolicy::Recreate => {
+          #[cfg(feature = "tracing")]
+          warn!(
+            "Local file is invalid ({}), recreating it from the template",
+            error
+          );
+          Ok(EnvFile::default())
+        }
+      },
+    }
+  }
+
+  /// Collects the template variables marked `@deprecated` that still have a local value.
+  fn deprecated_in_use(local: &EnvFile, template: &EnvFile) -> Vec<DeprecatedKey> {
+    template
+      .variables()
+      .filter_map(|var| {
+        let message = var.deprecation()?;
+        local
+          .get(var.name())
+          .filter(|local_var| !local_var.value.is_empty())?;
+
+        #[cfg(feature = "tracing")]
+        warn!("Deprecated key {} is still set locally", var.key);
+
+        Some(DeprecatedKey {
+          key: var.name().to_string(),
+          message: message.to_string(),
+        })
+      })
+      .collect()
+  }
+
+  /// Reports the value transitions among `changes`, written to the local file.
+  fn notify(listener: &dyn SyncListener, changes: &[Change]) {
+    for change in changes {
+      match change {
+        Change::Added { key, value } => listener.on_change(key, None, value),
+        Change::Modified { key, old, new } => listener.on_change(key, Some(old), new),
+        Change::Removed { .. } => {}
+      }
     }
+  }
 
-    let local_str = std::fs::read_to_string(&local_path).map_err(EnvSyncError::LocalIo)?;
-    let template_str = std::fs::read_to_string(&template_file).map_err(EnvSyncError::TemplateIo)?;
+  /// Generates a template file from an existing local file.
+  ///
+  /// See [`EnvFile::to_template`] for how values are handled. Fails if the template
+  /// already exists rather than overwriting it.
+  pub fn bootstrap_template(local_path: &Path, template_path: &Path) -> Result<(), EnvSyncError> {
+    if template_path.exists() {
+      return Err(EnvSyncError::TemplateExists(template_path.to_path_buf()));
+    }
 
-    let local_content = local_str
+    let local_str = Self::read_env_file(local_path, EnvSyncError::LocalIo)?;
+    let local: EnvFile = local_str
       .as_str()
       .try_into()
       .map_err(EnvSyncError::LocalParse)?;
 
-    let template_content = template_str
-      .as_str()
-      .try_into()
-      .map_err(EnvSyncError::TemplateParse)?;
+    #[cfg(feature = "tracing")]
+    info!("Writing template {:?} from {:?}", template_path, local_path);
+
+    std::fs::write(template_path, local.to_template().to_string()).map_err(EnvSyncError::Write)
+  }
+
+  /// Reads an env file as UTF-8, rejecting files that carry a UTF-16 or UTF-32 byte order mark.
+  ///
+  /// IO failures are reported with `io_error`, content that isn't valid UTF-8 as
+  /// [`EnvSyncError::Decode`].
+  pub(crate) fn read_env_file(
+    path: &Path,
+    io_error: fn(std::io::Error) -> EnvSyncError,
+  ) -> Result<String, EnvSyncError> {
+    let bytes = std::fs::read(path).map_err(io_error)?;
+
+    if FOREIGN_BOMS.iter().any(|bom| bytes.starts_with(bom)) {
+      #[cfg(feature = "tracing")]
+      debug!("Detected non UTF-8 byte order mark in {:?}", path);
+      return Err(EnvSyncError::EncodingMismatch {
+        path: path.to_path_buf(),
+      });
+    }
 
-    let synced = Self::sync(local_content, template_content)?;
+    String::from_utf8(bytes).map_err(|_error| {
+      #[cfg(feature = "tracing")]
+      debug!("Invalid UTF-8 in {:?}: {}", path, _error);
+      EnvSyncError::Decode {
+        path: path.to_path_buf(),
+      }
+    })
+  }
 
-    Self::update_local(synced, local_path)
+  /// Synchronizes already parsed files in memory, without reading or writing anything.
+  ///
+  /// Every variable of the result records where its value came from, see
+  /// [`EnvVariable::source`].
+  pub fn sync_files<'a>(
+    local: &EnvFile<'a>,
+    template: EnvFile<'a>,
+  ) -> Result<EnvFile<'a>, EnvSyncError> {
+    Self::sync(local, template, &SyncSettings::default())
   }
 
   /// Performs the core synchronization logic between local and template files.
   ///
   /// Takes the template as the base structure and enriches it with local values and comments.
-  fn sync<'a>(local: EnvFile<'a>, mut template: EnvFile<'a>) -> Result<EnvFile<'a>, EnvSyncError> {
+  fn sync<'a>(
+    local: &EnvFile<'a>,
+    mut template: EnvFile<'a>,
+    settings: &SyncSettings,
+  ) -> Result<EnvFile<'a>, EnvSyncError> {
     #[cfg(feature = "tracing")]
     debug!(
       "Starting sync of {} template entries",
@@ -101,9 +516,15 @@ impl EnvSync {
     );
 
     for entry in &mut template.entries {
-      if let EnvEntry::Variable(template_var) = entry
-        && let Some(local_var) = local.get(&template_var.key)
-      {
+      let EnvEntry::Variable(template_var) = entry else {
+        continue;
+      };
+      template_var.source = Some(Source::Template);
+      if settings.keys_only {
+        template_var.value = Cow::Borrowed("");
+      }
+
+      if let Some(local_var) = local.get(template_var.name()) {
         #[cfg(feature = "tracing")]
         trace!("Processing variable: {}", template_var.key);
 
@@ -115,17 +536,36 @@ impl EnvSync {
             template_var.key, local_var.value
           );
           template_var.value = local_var.value.clone();
+          template_var.heredoc = local_var.heredoc.clone();
+          template_var.source = Some(Source::Local);
+        } else if settings
+          .diff_options
+          .values_equal(&template_var.value, &local_var.value)
+        {
+          // Spelled as in the local file so a difference the comparison ignores isn't drift
+          template_var.value = local_var.value.clone();
+          template_var.source = Some(Source::Both);
+        } else if settings.prefer_local && !local_var.value.is_empty() {
+          #[cfg(feature = "tracing")]
+          trace!("Keeping local value for {}", template_var.key);
+          template_var.value = local_var.value.clone();
+          template_var.heredoc = local_var.heredoc.clone();
+          template_var.source = Some(Source::Local);
         }
 
         // Copy inline comment if template doesn't have one
-        if template_var.inline_comment.is_none() && local_var.inline_comment.is_some() {
+        let inline_from_local =
+          template_var.inline_comment.is_none() && local_var.inline_comment.is_some();
+        if inline_from_local {
           #[cfg(feature = "tracing")]
           trace!("Copying inline comment for {}", template_var.key);
           template_var.inline_comment = local_var.inline_comment.clone();
         }
 
         // Copy preceding comments if template doesn't have any
-        if template_var.preceding_comments.is_empty() && !local_var.preceding_comments.is_empty() {
+        let preceding_from_local =
+          template_var.preceding_comments.is_empty() && !local_var.preceding_comments.is_empty();
+        if preceding_from_local {
           #[cfg(feature = "tracing")]
           trace!(
             "Copying {} preceding comments for {}",
@@ -134,88 +574,2380 @@ impl EnvSync {
           );
           template_var.preceding_comments = local_var.preceding_comments.clone();
         }
+
+        // Documentation split across both slots by the merge ends up as preceding comments
+        if settings.consolidate_comments
+          && inline_from_local != preceding_from_local
+          && !template_var.preceding_comments.is_empty()
+          && let Some(inline) = template_var.inline_comment.take()
+        {
+          #[cfg(feature = "tracing")]
+          trace!("Moving inline comment of {} to preceding", template_var.key);
+          template_var.preceding_comments.push(inline);
+        }
       }
     }
 
+    if !settings.prune {
+      Self::append_local_only(local, &mut template, settings.orphan_placement);
+    } else if settings.pruned_comments == CommentOnRemove::Orphan {
+      Self::append_pruned_comments(local, &mut template);
+    }
+
+    if settings.keep_local_header {
+      Self::keep_local_header(local, &mut template);
+    }
+
     Ok(template)
   }
 
-  /// Writes the synchronized content back to the local file.
-  fn update_local<P: AsRef<Path>>(local: EnvFile, local_path: P) -> Result<(), EnvSyncError> {
+  /// Syncs only the entries inside the region `name`, keeping the rest of the local file
+  /// as it is.
+  ///
+  /// When the local file has no such region, the synced region is appended along with the
+  /// template's markers.
+  fn sync_region<'a>(
+    local: &EnvFile<'a>,
+    mut template: EnvFile<'a>,
+    name: &str,
+    settings: &SyncSettings,
+  ) -> Result<EnvFile<'a>, EnvSyncError> {
+    let template_range = template
+      .region_range(name)
+      .ok_or_else(|| EnvSyncError::RegionNotFound(name.to_string()))?;
+    let local_range = local.region_range(name);
+
+    #[cfg(feature = "tracing")]
+    debug!(
+      "Syncing region {} ({} template entries)",
+      name,
+      template_range.len()
+    );
+
+    let local_region = EnvFile {
+      entries: local_range
+        .clone()
+        .map(|range| local.entries[range].to_vec())
+        .unwrap_or_default(),
+    };
+    let end_marker = template
+      .entries
+      .get(template_range.end)
+      .filter(|entry| matches!(entry, EnvEntry::RegionEnd { .. }))
+      .cloned();
+    let start_marker = template.entries[template_range.start - 1].clone();
+    let template_region = EnvFile {
+      entries: template.entries.drain(template_range).collect(),
+    };
+    let region = Self::sync(&local_region, template_region, settings)?;
+
+    let mut synced = local.clone();
+    match local_range {
+      Some(range) => {
+        synced.entries.splice(range, region.entries);
+      }
+      None => {
+        if synced
+          .entries
+          .last()
+          .is_some_and(|entry| *entry != EnvEntry::EmptyLine)
+        {
+          synced.entries.push(EnvEntry::EmptyLine);
+        }
+        synced.entries.push(start_marker);
+        synced.entries.extend(region.entries);
+        synced.entries.extend(end_marker);
+      }
+    }
+    Ok(synced)
+  }
+
+  /// Adds the local variables the template doesn't define to the synced file, as a block
+  /// separated by empty lines, at the end or above the footer depending on `placement`.
+  fn append_local_only<'a>(
+    local: &EnvFile<'a>,
+    synced: &mut EnvFile<'a>,
+    placement: OrphanPlacement,
+  ) {
+    let local_only = Self::local_only(local, synced);
+    if local_only.is_empty() {
+      return;
+    }
     #[cfg(feature = "tracing")]
-    debug!("Writing synced content to {:?}", local_path.as_ref());
+    debug!("Keeping {} local-only variables", local_only.len());
 
-    let content = local.to_string();
-    std::fs::write(local_path, content).map_err(EnvSyncError::Write)?;
+    let index = match placement {
+      OrphanPlacement::End => synced.entries.len(),
+      OrphanPlacement::BeforeFooter => synced.footer_start(),
+    };
+    let mut block = Vec::with_capacity(local_only.len() + 2);
+    if index > 0 && synced.entries[index - 1] != EnvEntry::EmptyLine {
+      block.push(EnvEntry::EmptyLine);
+    }
+    for var in local_only {
+      let mut var = var.clone();
+      var.source = Some(Source::Local);
+      block.push(EnvEntry::Variable(var));
+    }
+    if synced
+      .entries
+      .get(index)
+      .is_some_and(|entry| *entry != EnvEntry::EmptyLine)
+    {
+      block.push(EnvEntry::EmptyLine);
+    }
+    synced.entries.splice(index..index, block);
+  }
 
+  /// Appends the preceding comments of the local variables left out by pruning to the end
+  /// of the synced file as orphan comments, separated by an empty line.
+  fn append_pruned_comments<'a>(local: &EnvFile<'a>, synced: &mut EnvFile<'a>) {
+    let comments: Vec<&EnvComment<'a>> = Self::local_only(local, synced)
+      .into_iter()
+      .flat_map(|var| &var.preceding_comments)
+      .collect();
+    if comments.is_empty() {
+      return;
+    }
     #[cfg(feature = "tracing")]
-    info!("Sync completed successfully");
+    debug!("Keeping {} comments of pruned variables", comments.len());
 
-    Ok(())
+    if Self::needs_separator(synced) {
+      synced.entries.push(EnvEntry::EmptyLine);
+    }
+    synced
+      .entries
+      .extend(comments.into_iter().cloned().map(EnvEntry::OrphanComment));
   }
-}
 
-/// Errors that can occur during environment file synchronization.
-#[derive(Debug, thiserror::Error)]
-pub enum EnvSyncError {
-  /// Error reading the local environment file
-  #[error("Local file IO error: {0}")]
-  LocalIo(std::io::Error),
-  /// Error parsing the local environment file
-  #[error("Local file parse error: {0}")]
-  LocalParse(ParseError),
-  /// Error reading the template file
-  #[error("Template file IO error: {0}")]
-  TemplateIo(std::io::Error),
-  /// Error parsing the template file
-  #[error("Template file parse error: {0}")]
-  TemplateParse(ParseError),
-  /// Error writing the synchronized content
-  #[error("Write error: {0}")]
-  Write(std::io::Error),
-  /// Error creating the local file
-  #[error("Failed to create local file: {0}")]
-  CreateLocal(std::io::Error),
-  /// Template file does not exist
-  #[error("Template file not found: {0}")]
-  TemplateNotFound(PathBuf),
-}
+  /// Returns the first definition of every local variable the template doesn't define.
+  fn local_only<'l, 'a>(local: &'l EnvFile<'a>, template: &EnvFile) -> Vec<&'l EnvVariable<'a>> {
+    let mut local_only: Vec<&EnvVariable> = Vec::new();
+    for var in local.variables() {
+      if template.get(var.name()).is_none()
+        && !local_only.iter().any(|kept| kept.name() == var.name())
+      {
+        local_only.push(var);
+      }
+    }
+    local_only
+  }
 
-/// Configuration options for environment file synchronization.
-pub struct EnvSyncOptions {
-  /// Path to the local environment file. If None, defaults to `.env` in current directory.
-  pub local_file: Option<PathBuf>,
-  /// Path to the template file that defines the desired structure.
-  pub template_file: PathBuf,
-}
+  /// Returns `true` if entries appended to `file` need an empty line to stand apart.
+  fn needs_separator(file: &EnvFile) -> bool {
+    file
+      .entries
+      .last()
+      .is_some_and(|entry| *entry != EnvEntry::EmptyLine)
+  }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
+  /// Copies the orphan comments at the top of the local file, dropped along with the
+  /// local-only variables otherwise, to the top of the synced file.
+  fn keep_local_header<'a>(local: &EnvFile<'a>, synced: &mut EnvFile<'a>) {
+    let Some((header, needs_separator)) = Self::local_header(local, synced) else {
+      return;
+    };
 
-  #[test]
-  fn test_sync() {
-    let local_content = "# Comment for KEY1\nKEY1=value1\nKEY2=value2 # inline comment\nKEY3=";
-    let template_content = "KEY1=\nKEY2=template_value\nKEY3=template_value3\nKEY4=new_key";
+    #[cfg(feature = "tracing")]
+    debug!("Keeping {} local header entries", header.len());
 
-    let local: EnvFile = local_content.try_into().unwrap();
-    let template: EnvFile = template_content.try_into().unwrap();
+    let separator = needs_separator.then_some(EnvEntry::EmptyLine);
+    let header: Vec<EnvEntry<'a>> = header.iter().cloned().chain(separator).collect();
+    synced.entries.splice(0..0, header);
+  }
 
-    let synced = EnvSync::sync(local, template).unwrap();
+  /// Returns the local header to keep above `synced`, and whether an empty line must
+  /// separate them.
+  ///
+  /// Only kept when the synced file has no header of its own, so re-syncing is idempotent.
+  fn local_header<'p, 'a>(
+    local: &'p EnvFile<'a>,
+    synced: &EnvFile<'a>,
+  ) -> Option<(&'p [EnvEntry<'a>], bool)> {
+    fn leading<'p, 'a>(file: &'p EnvFile<'a>) -> &'p [EnvEntry<'a>] {
+      let end = file
+        .entries
+        .iter()
+        .position(|entry| matches!(entry, EnvEntry::Variable(_)))
+        .unwrap_or(file.entries.len());
+      &file.entries[..end]
+    }
+    let has_comment = |entries: &[EnvEntry]| {
+      entries
+        .iter()
+        .any(|entry| matches!(entry, EnvEntry::OrphanComment(_)))
+    };
 
-    let key1 = synced.get("KEY1").unwrap();
-    assert_eq!(key1.value, "value1");
-    assert_eq!(key1.preceding_comments.len(), 1);
+    let header = leading(local);
+    if !has_comment(header) || has_comment(leading(synced)) {
+      return None;
+    }
 
-    let key2 = synced.get("KEY2").unwrap();
-    assert_eq!(key2.value, "template_value");
-    assert_eq!(
-      key2.inline_comment.as_ref().unwrap().to_string(),
-      "# inline comment"
+    let needs_separator = header.last() != Some(&EnvEntry::EmptyLine)
+      && synced
+        .entries
+        .first()
+        .is_some_and(|entry| *entry != EnvEntry::EmptyLine);
+    Some((header, needs_separator))
+  }
+
+  /// Describes how [`EnvSync::sync_files`] would merge the files, borrowing from them
+  /// instead of building the synced file.
+  ///
+  /// Rendering the plan gives the same content as the synced file, which makes it a
+  /// cheaper way to preview or check a sync.
+  pub fn plan<'p, 'a>(local: &'p EnvFile<'a>, template: &'p EnvFile<'a>) -> SyncPlan<'p, 'a> {
+    static EMPTY_LINE: EnvEntry<'static> = EnvEntry::EmptyLine;
+
+    let mut entries = Vec::with_capacity(template.entries.len());
+    if let Some((header, needs_separator)) = Self::local_header(local, template) {
+      entries.extend(header.iter().map(PlannedEntry::Keep));
+      if needs_separator {
+        entries.push(PlannedEntry::Keep(&EMPTY_LINE));
+      }
+    }
+
+    for entry in &template.entries {
+      let EnvEntry::Variable(template_var) = entry else {
+        entries.push(PlannedEntry::Keep(entry));
+        continue;
+      };
+
+      let mut planned = PlannedVariable {
+        template: template_var,
+        value: &template_var.value,
+        heredoc: template_var.heredoc.as_deref(),
+        source: Source::Template,
+        preceding_comments: &template_var.preceding_comments,
+        inline_comment: template_var.inline_comment.as_ref(),
+      };
+      if let Some(local_var) = local.get(template_var.name()) {
+        if template_var.value.is_empty() && !local_var.value.is_empty() {
+          planned.value = &local_var.value;
+          planned.heredoc = local_var.heredoc.as_deref();
+          planned.source = Source::Local;
+        } else if template_var.value == local_var.value {
+          planned.source = Source::Both;
+        }
+        if planned.inline_comment.is_none() {
+          planned.inline_comment = local_var.inline_comment.as_ref();
+        }
+        if planned.preceding_comments.is_empty() {
+          planned.preceding_comments = &local_var.preceding_comments;
+        }
+      }
+      entries.push(PlannedEntry::Variable(planned));
+    }
+
+    let local_only = Self::local_only(local, template);
+    if !local_only.is_empty() && Self::needs_separator(template) {
+      entries.push(PlannedEntry::Keep(&EMPTY_LINE));
+    }
+    entries.extend(local_only.into_iter().map(|var| {
+      PlannedEntry::Variable(PlannedVariable {
+        template: var,
+        value: &var.value,
+        heredoc: var.heredoc.as_deref(),
+        source: Source::Local,
+        preceding_comments: &var.preceding_comments,
+        inline_comment: var.inline_comment.as_ref(),
+      })
+    }));
+
+    SyncPlan { entries }
+  }
+
+  /// Renders the synced file, writing variables that are unchanged from the local file
+  /// exactly as their original line.
+  ///
+  /// `local` must be parsed from `local_str`. Values spanning several lines are always
+  /// re-rendered.
+  fn render_minimal(synced: &EnvFile, local: &EnvFile, local_str: &str) -> String {
+    let mut out = String::new();
+    for entry in &synced.entries {
+      let raw = match entry {
+        EnvEntry::Variable(var) => local
+          .get(var.name())
+          .filter(|local_var| {
+            local_var.value == var.value
+              && local_var.inline_comment == var.inline_comment
+              && local_var.bare == var.bare
+              && local_var.exported == var.exported
+              && var.heredoc.is_none()
+              && local_var.heredoc.is_none()
+              && !local_var.value.contains('\n')
+          })
+          .and_then(|local_var| Self::source_line(local_str, &local_var.key))
+          .map(|raw| (var, raw)),
+        _ => None,
+      };
+
+      // Writing to a `String` cannot fail
+      let _ = match raw {
+        Some((var, raw)) => {
+          #[cfg(feature = "tracing")]
+          trace!("Keeping original line for {}", var.key);
+          var
+            .preceding_comments
+            .iter()
+            .try_for_each(|comment| writeln!(out, "{}", comment))
+            .and_then(|_| writeln!(out, "{}", raw))
+        }
+        None => write!(out, "{}", entry),
+      };
+    }
+    out
+  }
+
+  /// Returns the line of `source` that contains `part`, or `None` if `part` doesn't borrow
+  /// from `source`.
+  fn source_line<'s>(source: &'s str, part: &str) -> Option<&'s str> {
+    let offset = (part.as_ptr() as usize).checked_sub(source.as_ptr() as usize)?;
+    if offset + part.len() > source.len() {
+      return None;
+    }
+    let start = source[..offset].rfind('\n').map_or(0, |index| index + 1);
+    let end = source[offset..]
+      .find('\n')
+      .map_or(source.len(), |index| offset + index);
+    Some(source[start..end].trim_end_matches('\r'))
+  }
+
+  /// Fills values that are still empty after the merge from same-named environment
+  /// variables, read with `lookup`.
+  fn fill_from_env(file: &mut EnvFile, lookup: fn(&str) -> Option<String>) {
+    for entry in &mut file.entries {
+      if let EnvEntry::Variable(var) = entry
+        && var.value.is_empty()
+        && let Some(value) = lookup(var.name())
+        && !value.is_empty()
+      {
+        #[cfg(feature = "tracing")]
+        trace!("Using environment value for {}", var.key);
+        var.value = value.into();
+        var.source = Some(Source::Environment);
+      }
+    }
+  }
+
+  /// Fills values that are still empty after the merge from the defaults file.
+  fn fill_defaults<'a>(file: &mut EnvFile<'a>, defaults: &EnvFile<'a>) {
+    for entry in &mut file.entries {
+      if let EnvEntry::Variable(var) = entry
+        && var.value.is_empty()
+        && let Some(default) = defaults.get(var.name())
+        && !default.value.is_empty()
+      {
+        #[cfg(feature = "tracing")]
+        trace!("Using default value for {}", var.key);
+        var.value = default.value.clone();
+        var.source = Some(Source::Defaults);
+      }
+    }
+  }
+
+  /// Sets a forced value, placing keys missing from the file according to `placement`.
+  fn apply_override<'a>(
+    file: &mut EnvFile<'a>,
+    key: String,
+    value: String,
+    placement: OrphanPlacement,
+  ) {
+    if let Some(var) = file.get_mut(&key) {
+      var.value = value.into();
+      var.source = Some(Source::Override);
+      return;
+    }
+
+    let index = match placement {
+      OrphanPlacement::End => file.entries.len(),
+      OrphanPlacement::BeforeFooter => file.footer_start(),
+    };
+    #[cfg(feature = "tracing")]
+    trace!("Inserting {} at entry {}", key, index);
+    file.entries.insert(
+      index,
+      EnvEntry::Variable(EnvVariable {
+        key: key.into(),
+        value: value.into(),
+        preceding_comments: Vec::new(),
+        inline_comment: None,
+        bare: false,
+        heredoc: None,
+        exported: false,
+        source: Some(Source::Override),
+      }),
     );
+  }
 
-    assert_eq!(synced.get("KEY3").unwrap().value, "template_value3");
-    assert_eq!(synced.get("KEY4").unwrap().value, "new_key");
+  /// Ensures the header comment lines are present at the top of the file.
+  ///
+  /// Lines that don't start with `#` are turned into comments. The header is followed by an
+  /// empty line so it is not attached to the first variable when the file is parsed again.
+  fn apply_header(file: &mut EnvFile, header: &str) {
+    let comments: Vec<EnvEntry> = header
+      .lines()
+      .map(|line| match line.strip_prefix(COMMENT_PREFIX) {
+        Some(content) => EnvComment::new(content.to_string()),
+        None => EnvComment::new(format!(" {}", line)),
+      })
+      .map(EnvEntry::OrphanComment)
+      .collect();
+
+    if file.entries.starts_with(&comments) {
+      #[cfg(feature = "tracing")]
+      trace!("Header already present");
+      return;
+    }
+
+    #[cfg(feature = "tracing")]
+    debug!("Inserting {} header lines", comments.len());
+
+    let needs_separator = file
+      .entries
+      .first()
+      .is_some_and(|entry| *entry != EnvEntry::EmptyLine);
+    let separator = needs_separator.then_some(EnvEntry::EmptyLine);
+
+    file
+      .entries
+      .splice(0..0, comments.into_iter().chain(separator));
+  }
+
+  /// Writes the synchronized content back to the local file.
+  ///
+  /// Writing through a symlink updates its target. Without `follow_symlinks`, a symlinked
+  /// local file is replaced by a regular file instead.
+  fn update_local<P: AsRef<Path>>(
+    content: &str,
+    local_path: P,
+    follow_symlinks: bool,
+    file_mode: Option<u32>,
+  ) -> Result<LocalWrite, EnvSyncError> {
+    let local_path = local_path.as_ref();
+    let replace_symlink = !follow_symlinks
+      && std::fs::symlink_metadata(local_path).is_ok_and(|metadata| metadata.is_symlink());
+
+    // Leaving an identical file alone keeps its modification time for file watchers
+    if !replace_symlink
+      && std::fs::read(local_path).is_ok_and(|existing| existing == content.as_bytes())
+    {
+      #[cfg(feature = "tracing")]
+      info!("{:?} is already in sync, not writing it", local_path);
+      Self::set_file_mode(local_path, file_mode).map_err(EnvSyncError::Write)?;
+      return Ok(LocalWrite::NoChange);
+    }
+
+    #[cfg(feature = "tracing")]
+    debug!("Writing synced content to {:?}", local_path);
+
+    if replace_symlink {
+      #[cfg(feature = "tracing")]
+      debug!("Replacing symlink {:?} with a regular file", local_path);
+      std::fs::remove_file(local_path).map_err(EnvSyncError::Write)?;
+    }
+
+    Self::write_file(local_path, content, file_mode).map_err(EnvSyncError::Write)?;
+
+    #[cfg(feature = "tracing")]
+    info!("Sync completed successfully");
+
+    Ok(LocalWrite::Written)
+  }
+
+  /// Copies the local file to the same path with a `.bak` suffix, e.g. `.env.bak`.
+  fn backup_local(local_path: &Path) -> Result<(), EnvSyncError> {
+    let mut backup_path = local_path.as_os_str().to_owned();
+    backup_path.push(".bak");
+
+    #[cfg(feature = "tracing")]
+    debug!("Backing up {:?} to {:?}", local_path, backup_path);
+    std::fs::copy(local_path, backup_path).map_err(EnvSyncError::Backup)?;
+    Ok(())
+  }
+
+  /// Writes `content` to `path`, creating it if needed.
+  ///
+  /// On Unix, a given mode is applied before any content is written, so a new file is never
+  /// more permissive than `mode` while it holds the secrets.
+  fn write_file(path: &Path, content: &str, mode: Option<u32>) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+      use std::io::Write as _;
+      use std::os::unix::fs::{OpenOptionsExt, PermissionsExt};
+
+      #[cfg(feature = "tracing")]
+      debug!("Writing {:?} with mode {:o}", path, mode);
+      let mut file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .mode(mode)
+        .open(path)?;
+      // The umask can clear bits of a new file, and an existing file keeps its old mode
+      file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+      return file.write_all(content.as_bytes());
+    }
+    #[cfg(not(unix))]
+    let _ = mode;
+    std::fs::write(path, content)
+  }
+
+  /// Sets the Unix permission bits of `path`, if a mode is given. Does nothing elsewhere.
+  fn set_file_mode(path: &Path, mode: Option<u32>) -> std::io::Result<()> {
+    #[cfg(unix)]
+    if let Some(mode) = mode {
+      use std::os::unix::fs::PermissionsExt;
+
+      #[cfg(feature = "tracing")]
+      debug!("Setting mode {:o} on {:?}", mode, path);
+      std::fs::set_permissions(path, std::fs::Permissions::from_mode(mode))?;
+    }
+    #[cfg(not(unix))]
+    let _ = (path, mode);
+    Ok(())
+  }
+}
+
+/// A borrowed description of a sync, produced by [`EnvSync::plan`].
+///
+/// Its [`Display`](fmt::Display) output is the synced file content.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncPlan<'p, 'a> {
+  /// Entries of the synced file, in order
+  pub entries: Vec<PlannedEntry<'p, 'a>>,
+}
+
+/// An entry of a [`SyncPlan`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum PlannedEntry<'p, 'a> {
+  /// An entry written as is, from the template or the local file's header
+  Keep(&'p EnvEntry<'a>),
+  /// A template variable merged with its local counterpart
+  Variable(PlannedVariable<'p, 'a>),
+}
+
+/// A template variable along with the parts the sync takes from either file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PlannedVariable<'p, 'a> {
+  /// The variable providing the key and form of the line, from the template or, for a
+  /// local-only variable, from the local file
+  pub template: &'p EnvVariable<'a>,
+  /// The synced value
+  pub value: &'p str,
+  /// Terminator of the heredoc the synced value is written as, see [`EnvVariable::heredoc`]
+  pub heredoc: Option<&'p str>,
+  /// Where the synced value comes from
+  pub source: Source,
+  /// The synced preceding comments
+  pub preceding_comments: &'p [EnvComment<'a>],
+  /// The synced inline comment
+  pub inline_comment: Option<&'p EnvComment<'a>>,
+}
+
+impl fmt::Display for SyncPlan<'_, '_> {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    for entry in &self.entries {
+      match entry {
+        PlannedEntry::Keep(entry) => write!(f, "{}", entry)?,
+        PlannedEntry::Variable(planned) => {
+          for comment in planned.preceding_comments {
+            writeln!(f, "{}", comment)?;
+          }
+          let var = EnvVariable {
+            key: Cow::Borrowed(&planned.template.key),
+            value: Cow::Borrowed(planned.value),
+            preceding_comments: Vec::new(),
+            inline_comment: planned.inline_comment.map(EnvComment::borrowed),
+            bare: planned.template.bare,
+            heredoc: planned.heredoc.map(Cow::Borrowed),
+            exported: planned.template.exported,
+            source: None,
+          };
+          writeln!(f, "{}", var)?;
+        }
+      }
+    }
+    Ok(())
+  }
+}
+
+/// Whether [`EnvSync::update_local`] had to write the local file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LocalWrite {
+  Written,
+  /// The file already had the synced content and was left untouched
+  NoChange,
+}
+
+/// Reads `name` from the process environment, the default [`EnvSyncOptions::env_lookup`].
+fn env_var(name: &str) -> Option<String> {
+  std::env::var(name).ok()
+}
+
+/// Options that affect how the local and template files are merged, see
+/// [`EnvSync::merge_with`].
+///
+/// Fields mirror the [`EnvSyncOptions`] of the same name. Defaults match
+/// [`EnvSyncOptions::default`].
+#[derive(Debug, Clone)]
+pub struct SyncSettings<'a> {
+  /// See [`EnvSyncOptions::consolidate_comments`]
+  pub consolidate_comments: bool,
+  /// See [`EnvSyncOptions::keep_local_header`]
+  pub keep_local_header: bool,
+  /// See [`EnvSyncOptions::prune`]
+  pub prune: bool,
+  /// See [`EnvSyncOptions::pruned_comments`]
+  pub pruned_comments: CommentOnRemove,
+  /// See [`EnvSyncOptions::prefer_local`]
+  pub prefer_local: bool,
+  /// See [`EnvSyncOptions::keys_only`]
+  pub keys_only: bool,
+  /// See [`EnvSyncOptions::region`]
+  pub region: Option<String>,
+  /// See [`EnvSyncOptions::fill_from_env`]
+  pub fill_from_env: bool,
+  /// See [`EnvSyncOptions::env_lookup`]
+  pub env_lookup: fn(&str) -> Option<String>,
+  /// Fallback values, see [`EnvSyncOptions::defaults_file`]
+  pub defaults: Option<EnvFile<'a>>,
+  /// See [`EnvSyncOptions::overrides`]
+  pub overrides: Vec<(String, String)>,
+  /// See [`EnvSyncOptions::orphan_placement`]
+  pub orphan_placement: OrphanPlacement,
+  /// See [`EnvSyncOptions::group_by_prefix`]
+  pub group_by_prefix: bool,
+  /// See [`EnvSyncOptions::group_headers`]
+  pub group_headers: bool,
+  /// See [`EnvSyncOptions::deletions`]
+  pub deletions: Vec<String>,
+  /// See [`EnvSyncOptions::comments`]
+  pub comments: Vec<(String, String)>,
+  /// See [`EnvSyncOptions::header`]
+  pub header: Option<String>,
+  /// See [`EnvSyncOptions::tidy`]
+  pub tidy: bool,
+  /// See [`EnvSyncOptions::diff_options`]
+  pub diff_options: DiffOptions,
+}
+
+impl Default for SyncSettings<'_> {
+  fn default() -> Self {
+    Self {
+      consolidate_comments: false,
+      keep_local_header: true,
+      prune: false,
+      pruned_comments: CommentOnRemove::Delete,
+      prefer_local: false,
+      keys_only: false,
+      region: None,
+      fill_from_env: false,
+      env_lookup: env_var,
+      defaults: None,
+      overrides: Vec::new(),
+      orphan_placement: OrphanPlacement::End,
+      group_by_prefix: false,
+      group_headers: false,
+      deletions: Vec::new(),
+      comments: Vec::new(),
+      header: None,
+      tidy: false,
+      diff_options: DiffOptions::default(),
+    }
+  }
+}
+
+/// The outcome of a sync run.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncReport {
+  /// Path of the local file that was synced
+  pub local_path: PathBuf,
+  /// Content of the local file before the sync (empty if it didn't exist)
+  pub original: String,
+  /// Content produced by the sync
+  pub synced: String,
+  /// Whether the synced content was written to the local file. A local file that already
+  /// has the synced content isn't rewritten, so its modification time is kept.
+  pub written: bool,
+  /// Key-level changes from the original local file to the synced content
+  pub changes: Vec<Change>,
+  /// Keys marked `@deprecated` in the template that still have a local value
+  pub deprecated: Vec<DeprecatedKey>,
+  /// Policy used to decide which values are masked by [`SyncReport::masked_changes`]
+  pub secrets: SecretPolicy,
+}
+
+/// A deprecated template key that is still in use locally.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct DeprecatedKey {
+  /// The deprecated key
+  pub key: String,
+  /// The text following the `@deprecated` annotation, possibly empty
+  pub message: String,
+}
+
+/// The keys affected by a sync, without their values.
+///
+/// With the `json` feature it serializes to the object printed by
+/// `env-sync --report-format json`.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(feature = "json", derive(serde::Serialize))]
+pub struct ReportSummary {
+  /// Path of the local file that was synced
+  pub local_path: PathBuf,
+  /// Whether the synced content was written to the local file
+  pub written: bool,
+  /// Keys added to the local file
+  pub added: Vec<String>,
+  /// Keys whose value changed
+  pub changed: Vec<String>,
+  /// Keys kept with their previous value
+  pub unchanged: Vec<String>,
+  /// Local keys dropped because the template doesn't define them
+  pub orphans: Vec<String>,
+  /// Keys marked `@deprecated` in the template that still have a local value
+  pub deprecated: Vec<DeprecatedKey>,
+}
+
+impl SyncReport {
+  /// Summarizes the report by key, leaving out every value.
+  pub fn summary(&self) -> ReportSummary {
+    let mut summary = ReportSummary {
+      local_path: self.local_path.clone(),
+      written: self.written,
+      added: Vec::new(),
+      changed: Vec::new(),
+      unchanged: Vec::new(),
+      orphans: Vec::new(),
+      deprecated: self.deprecated.clone(),
+    };
+    for change in &self.changes {
+      match change {
+        Change::Added { key, .. } => summary.added.push(key.clone()),
+        Change::Modified { key, .. } => summary.changed.push(key.clone()),
+        Change::Removed { key, .. } => summary.orphans.push(key.clone()),
+      }
+    }
+
+    let (synced, _) = EnvFile::parse_lossy(&self.synced, &ParseOptions::default());
+    for var in synced.variables() {
+      let key = var.name().to_string();
+      if !summary.added.contains(&key)
+        && !summary.changed.contains(&key)
+        && !summary.unchanged.contains(&key)
+      {
+        summary.unchanged.push(key);
+      }
+    }
+    summary
+  }
+
+  /// Renders [`SyncReport::summary`] as a JSON object.
+  #[cfg(feature = "json")]
+  pub fn to_json(&self) -> String {
+    serde_json::to_string_pretty(&self.summary()).expect("the summary serializes to JSON")
+  }
+
+  /// Returns `true` if the sync produced content different from the original local file.
+  pub fn has_changes(&self) -> bool {
+    self.original != self.synced
+  }
+
+  /// Returns the key-level changes with the values of secret keys masked.
+  pub fn masked_changes(&self) -> Vec<Change> {
+    let mask = |key: &str, value: &str| self.secrets.mask(key, value).to_string();
+
+    self
+      .changes
+      .iter()
+      .map(|change| match change {
+        Change::Added { key, value } => Change::Added {
+          key: key.clone(),
+          value: mask(key, value),
+        },
+        Change::Removed { key, value } => Change::Removed {
+          key: key.clone(),
+          value: mask(key, value),
+        },
+        Change::Modified { key, old, new } => Change::Modified {
+          key: key.clone(),
+          old: mask(key, old),
+          new: mask(key, new),
+        },
+      })
+      .collect()
+  }
+
+  /// Renders the change from the original to the synced content as a unified diff.
+  ///
+  /// Returns an empty string when nothing changed.
+  #[cfg(feature = "diff")]
+  pub fn unified_diff(&self) -> String {
+    let path = self.local_path.display().to_string();
+    Self::render_diff(&self.original, &self.synced, &path, &path)
+  }
+
+  /// Renders the change as a patch that applies to the original local file.
+  ///
+  /// Paths use the `a/` and `b/` prefixes with the local file name, so the patch applies
+  /// with `git apply` or `patch -p1` from the directory containing the local file.
+  #[cfg(feature = "diff")]
+  pub fn patch(&self) -> String {
+    let name = self
+      .local_path
+      .file_name()
+      .map(|name| name.to_string_lossy())
+      .unwrap_or_else(|| DEFAULT_LOCAL_FILENAME.into());
+    Self::render_diff(
+      &self.original,
+      &self.synced,
+      &format!("a/{}", name),
+      &format!("b/{}", name),
+    )
+  }
+
+  #[cfg(feature = "diff")]
+  fn render_diff(old: &str, new: &str, old_header: &str, new_header: &str) -> String {
+    similar::TextDiff::from_lines(old, new)
+      .unified_diff()
+      .header(old_header, new_header)
+      .to_string()
+  }
+}
+
+/// Receives the value transitions produced by a sync, e.g. to hot-reload configuration.
+pub trait SyncListener {
+  /// Called for each key whose effective value changes.
+  ///
+  /// `old` is `None` when the key didn't exist in the local file.
+  fn on_change(&self, key: &str, old: Option<&str>, new: &str);
+}
+
+/// Errors that can occur during environment file synchronization.
+#[derive(Debug, thiserror::Error)]
+pub enum EnvSyncError {
+  /// Error reading the local environment file
+  #[error("Local file IO error: {0}")]
+  LocalIo(std::io::Error),
+  /// Error parsing the local environment file
+  #[error("Local file parse error: {0}")]
+  LocalParse(ParseError),
+  /// Error reading the template file
+  #[error("Template file IO error: {0}")]
+  TemplateIo(std::io::Error),
+  /// Error parsing the template file
+  #[error("Template file parse error: {0}")]
+  TemplateParse(ParseError),
+  /// Error reading the overlay file passed to [`crate::merge_files`]
+  #[error("Overlay file IO error: {0}")]
+  OverlayIo(std::io::Error),
+  /// Error parsing the overlay file passed to [`crate::merge_files`]
+  #[error("Overlay file parse error: {0}")]
+  OverlayParse(ParseError),
+  /// Error writing the synchronized content
+  #[error("Write error: {0}")]
+  Write(std::io::Error),
+  /// Error creating the local file
+  #[error("Failed to create local file: {0}")]
+  CreateLocal(std::io::Error),
+  /// Template file does not exist
+  #[error("Template file not found: {0}")]
+  TemplateNotFound(PathBuf),
+  /// Template file contains no variables
+  #[error("Template has no variables")]
+  EmptyTemplate,
+  /// File is not UTF-8 encoded (e.g. it starts with a UTF-16 byte order mark)
+  #[error("File is not UTF-8 encoded: {path}")]
+  EncodingMismatch { path: PathBuf },
+  /// File content is not valid UTF-8
+  #[error("File contains invalid UTF-8: {path}")]
+  Decode { path: PathBuf },
+  /// Error reading the non-secret allowlist file
+  #[error("Allowlist file IO error: {0}")]
+  AllowlistIo(std::io::Error),
+  /// Template file already exists and would be overwritten
+  #[error("Template file already exists: {0}")]
+  TemplateExists(PathBuf),
+  /// Error writing the patch file
+  #[error("Patch file IO error: {0}")]
+  PatchIo(std::io::Error),
+  /// Error reading the defaults file
+  #[error("Defaults file IO error: {0}")]
+  DefaultsIo(std::io::Error),
+  /// Error parsing the defaults file
+  #[error("Defaults file parse error: {0}")]
+  DefaultsParse(ParseError),
+  /// [`EnvSyncOptions::region`] names a region the template doesn't have
+  #[error("Template has no region named {0}")]
+  RegionNotFound(String),
+  /// The template defines a key more than once, see
+  /// [`EnvSyncOptions::allow_duplicate_template_keys`]
+  #[error("Template defines {key} more than once")]
+  TemplateDuplicateKey { key: String },
+  /// Error copying the local file to its backup, see [`EnvSyncOptions::backup`]
+  #[error("Backup error: {0}")]
+  Backup(std::io::Error),
+}
+
+/// How to handle a local file that contains lines that cannot be parsed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LocalParseErrorPolicy {
+  /// Fail with [`EnvSyncError::LocalParse`]
+  #[default]
+  Abort,
+  /// Drop the invalid lines and sync the rest of the local file
+  SkipLine,
+  /// Ignore the local file and rebuild it from the template
+  Recreate,
+}
+
+/// Where keys that are missing from the synced output are appended.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OrphanPlacement {
+  /// After the last entry of the file
+  #[default]
+  End,
+  /// After the last variable, keeping a trailing block of comments (a footer) at the end
+  BeforeFooter,
+}
+
+/// Where the template content is read from.
+#[derive(Debug, Clone, PartialEq)]
+pub enum TemplateSource {
+  /// A template file on disk
+  File(PathBuf),
+  /// Template content held in memory, e.g. a default embedded with `include_str!`
+  Inline(String),
+}
+
+impl From<PathBuf> for TemplateSource {
+  fn from(path: PathBuf) -> Self {
+    TemplateSource::File(path)
+  }
+}
+
+impl From<&OsStr> for TemplateSource {
+  fn from(path: &OsStr) -> Self {
+    TemplateSource::File(PathBuf::from(path))
+  }
+}
+
+/// Configuration options for environment file synchronization.
+pub struct EnvSyncOptions {
+  /// Path to the local environment file. If None, defaults to `.env` in current directory.
+  pub local_file: Option<PathBuf>,
+  /// Template that defines the desired structure.
+  pub template_source: TemplateSource,
+  /// Header comment written at the top of the local file, one comment per line.
+  ///
+  /// The header is inserted only if it isn't already present, so re-syncing doesn't duplicate it.
+  pub header: Option<String>,
+  /// Values forced onto specific keys after merging, regardless of the local and template values.
+  ///
+  /// Keys missing from the merged output are appended.
+  pub overrides: Vec<(String, String)>,
+  /// Whether a template without any variables is accepted. When `false`, syncing against
+  /// such a template fails with [`EnvSyncError::EmptyTemplate`]. Defaults to `true`.
+  pub allow_empty_template: bool,
+  /// Whether a template defining a key more than once is accepted, in which case the last
+  /// value wins at the position of the first definition. When `false`, the sync fails with
+  /// [`EnvSyncError::TemplateDuplicateKey`].
+  pub allow_duplicate_template_keys: bool,
+  /// Listener notified of every value change the sync applies to the local file, once the
+  /// file is written. It isn't called for a dry run, a failed write, or a file that already
+  /// has the synced content.
+  pub listener: Option<Box<dyn SyncListener>>,
+  /// Compute the sync without writing or creating the local file.
+  pub dry_run: bool,
+  /// Keep a variable's documentation in a single place: when the merge would combine an
+  /// inline comment from one file with preceding comments from the other, the inline comment
+  /// is moved into the preceding comments.
+  pub consolidate_comments: bool,
+  /// File listing the keys whose values are safe to show, one per line.
+  ///
+  /// When set, every key not on the list is treated as secret in reports. Otherwise secrets
+  /// are detected from key suffixes such as `_TOKEN` (see [`SecretPolicy::Heuristic`]).
+  pub nonsecret_allowlist: Option<PathBuf>,
+  /// Also write the applied change as a unified-diff patch to this path.
+  ///
+  /// No patch is written when the local file isn't, e.g. in dry-run mode or when it is
+  /// already in sync.
+  #[cfg(feature = "diff")]
+  pub write_patch: Option<PathBuf>,
+  /// What to do when the local file contains invalid lines.
+  pub on_local_parse_error: LocalParseErrorPolicy,
+  /// Where keys missing from the template are placed: the keys appended by
+  /// [`EnvSyncOptions::overrides`], and the local-only variables kept unless
+  /// [`EnvSyncOptions::prune`] is set.
+  pub orphan_placement: OrphanPlacement,
+  /// When the local file is a symlink, write to its target and keep the link. When `false`,
+  /// the link is replaced by a regular file. Defaults to `true`.
+  pub follow_symlinks: bool,
+  /// Collapse repeated empty lines in the synced output so sections are separated by at
+  /// most one empty line.
+  pub tidy: bool,
+  /// Env file with fallback values, e.g. organization-wide `.env.defaults`.
+  ///
+  /// Applied after merging local and template, it only fills values that are still empty.
+  /// Keys that are not in the template are not added.
+  pub defaults_file: Option<PathBuf>,
+  /// Write variables that the sync didn't change (same value, inline comment, and form as
+  /// in the local file) exactly as their original local line, including unusual spacing.
+  /// Only changed or new variables are re-rendered.
+  pub minimal_rewrite: bool,
+  /// Sort the output by key and group it by prefix (the part before the first `_`), with an
+  /// empty line between groups. See [`EnvFile::grouped_by_prefix`].
+  pub group_by_prefix: bool,
+  /// With [`EnvSyncOptions::group_by_prefix`], start every group with a `# PREFIX` comment.
+  pub group_headers: bool,
+  /// Inline comments set on specific keys after merging, replacing any existing one.
+  ///
+  /// Keys missing from the merged output are skipped.
+  pub comments: Vec<(String, String)>,
+  /// Fill values that are still empty after merging from process environment variables of
+  /// the same name, e.g. secrets provided by CI. Takes precedence over
+  /// [`EnvSyncOptions::defaults_file`].
+  pub fill_from_env: bool,
+  /// Reads an environment variable for [`EnvSyncOptions::fill_from_env`]. Defaults to the
+  /// process environment.
+  pub env_lookup: fn(&str) -> Option<String>,
+  /// Keys removed from the output after merging and applying overrides, together with
+  /// their preceding and inline comments.
+  pub deletions: Vec<String>,
+  /// Keep the orphan comments at the top of the local file, e.g. project documentation,
+  /// when the template has no header of its own. Defaults to `true`.
+  pub keep_local_header: bool,
+  /// Only sync the variables inside the `# region NAME` section of that name, leaving the
+  /// rest of the local file untouched. See [`ParseOptions::regions`].
+  pub region: Option<String>,
+  /// Unix permission bits, e.g. `0o600`, set on the local file when it is created or
+  /// rewritten, regardless of the umask. Ignored on other platforms.
+  pub file_mode: Option<u32>,
+  /// Leave out the local variables the template doesn't define, so the local file ends up
+  /// with exactly the template's keys. Otherwise they are appended to the end of the output
+  /// with their comments, after an empty line.
+  pub prune: bool,
+  /// With [`EnvSyncOptions::prune`], what happens to the preceding comments of the variables
+  /// left out. [`CommentOnRemove::Orphan`] keeps them at the end of the output as orphan
+  /// comments, which later syncs drop like any other local orphan comment.
+  pub pruned_comments: CommentOnRemove,
+  /// Copy an existing local file to the same path with a `.bak` suffix, e.g. `.env.bak`,
+  /// before overwriting it. The sync fails without writing if the copy fails.
+  pub backup: bool,
+  /// Keep a non-empty local value even when the template sets a different one. Otherwise
+  /// template values win and local values only fill empty template values.
+  pub prefer_local: bool,
+  /// Only take the keys from the template, ignoring its example values: keys added to the
+  /// local file start out empty.
+  pub keys_only: bool,
+  /// Layered local files, from lowest to highest priority, e.g. `.env` then `.env.local`.
+  ///
+  /// The template is synced against the combined view where higher-priority files win, and
+  /// the result is written to the last file. When [`EnvSyncOptions::local_file`] is set too,
+  /// it is the file written instead, above every layer. Missing lower-priority files are
+  /// skipped.
+  pub local_files: Vec<PathBuf>,
+  /// How local and template values are compared. Values that compare equal aren't drift:
+  /// the local spelling is kept and the key isn't reported as changed.
+  pub diff_options: DiffOptions,
+}
+
+impl Default for EnvSyncOptions {
+  fn default() -> Self {
+    Self {
+      local_file: None,
+      template_source: TemplateSource::File(PathBuf::from(DEFAULT_TEMPLATE_FILENAME)),
+      header: None,
+      overrides: Vec::new(),
+      allow_empty_template: true,
+      listener: None,
+      dry_run: false,
+      consolidate_comments: false,
+      nonsecret_allowlist: None,
+      #[cfg(feature = "diff")]
+      write_patch: None,
+      on_local_parse_error: LocalParseErrorPolicy::Abort,
+      orphan_placement: OrphanPlacement::End,
+      follow_symlinks: true,
+      tidy: false,
+      defaults_file: None,
+      minimal_rewrite: false,
+      group_by_prefix: false,
+      group_headers: false,
+      comments: Vec::new(),
+      fill_from_env: false,
+      env_lookup: env_var,
+      deletions: Vec::new(),
+      keep_local_header: true,
+      region: None,
+      file_mode: None,
+      local_files: Vec::new(),
+      prune: false,
+      allow_duplicate_template_keys: false,
+      backup: false,
+      prefer_local: false,
+      keys_only: false,
+      pruned_comments: CommentOnRemove::Delete,
+      diff_options: DiffOptions::default(),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_sync() {
+    let local_content = "# Comment for KEY1\nKEY1=value1\nKEY2=value2 # inline comment\nKEY3=";
+    let template_content = "KEY1=\nKEY2=template_value\nKEY3=template_value3\nKEY4=new_key";
+
+    let local: EnvFile = local_content.try_into().unwrap();
+    let template: EnvFile = template_content.try_into().unwrap();
+
+    let synced = EnvSync::sync(&local, template, &SyncSettings::default()).unwrap();
+
+    let key1 = synced.get("KEY1").unwrap();
+    assert_eq!(key1.value, "value1");
+    assert_eq!(key1.preceding_comments.len(), 1);
+
+    let key2 = synced.get("KEY2").unwrap();
+    assert_eq!(key2.value, "template_value");
+    assert_eq!(
+      key2.inline_comment.as_ref().unwrap().to_string(),
+      "# inline comment"
+    );
+
+    assert_eq!(synced.get("KEY3").unwrap().value, "template_value3");
+    assert_eq!(synced.get("KEY4").unwrap().value, "new_key");
+  }
+
+  #[test]
+  fn test_sync_preserves_preceding_comment_order() {
+    let local_content = "# First\n# Second\n# Third\nKEY=value";
+    let template_content = "KEY=";
+
+    let local: EnvFile = local_content.try_into().unwrap();
+    let template: EnvFile = template_content.try_into().unwrap();
+
+    let synced = EnvSync::sync(&local, template, &SyncSettings::default()).unwrap();
+
+    let key = synced.get("KEY").unwrap();
+    let comments: Vec<String> = key
+      .preceding_comments
+      .iter()
+      .map(|c| c.to_string())
+      .collect();
+    assert_eq!(comments, vec!["# First", "# Second", "# Third"]);
+    assert_eq!(
+      synced.to_string(),
+      "# First\n# Second\n# Third\nKEY=value\n"
+    );
+  }
+
+  #[test]
+  fn test_sync_consolidates_comments_into_preceding() {
+    let local: EnvFile = "# Local docs\nKEY=value".try_into().unwrap();
+    let template: EnvFile = "KEY= # Template docs".try_into().unwrap();
+
+    let settings = SyncSettings {
+      consolidate_comments: true,
+      ..Default::default()
+    };
+    let synced = EnvSync::sync(&local, template.clone(), &settings).unwrap();
+    assert_eq!(
+      synced.to_string(),
+      "# Local docs\n# Template docs\nKEY=value\n"
+    );
+
+    let synced = EnvSync::sync(&local, template, &SyncSettings::default()).unwrap();
+    assert_eq!(
+      synced.to_string(),
+      "# Local docs\nKEY=value # Template docs\n"
+    );
+  }
+
+  #[test]
+  fn test_sync_preserves_unknown_annotations() {
+    let local: EnvFile = "# @custom local note\nKEY=value".try_into().unwrap();
+    let template: EnvFile = "# @deprecated use OTHER\n# @owner infra\nKEY=\nOTHER=\n"
+      .try_into()
+      .unwrap();
+
+    let synced = EnvSync::sync(&local, template, &SyncSettings::default()).unwrap();
+
+    assert_eq!(
+      synced.to_string(),
+      "# @deprecated use OTHER\n# @owner infra\nKEY=value\nOTHER=\n"
+    );
+  }
+
+  #[test]
+  fn test_header_inserted_once() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    std::fs::write(&local_path, "KEY=local").unwrap();
+    std::fs::write(&template_path, "KEY=").unwrap();
+
+    let header = "Generated by env-sync; do not edit above managed region";
+    for _ in 0..2 {
+      let options = EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: template_path.clone().into(),
+        header: Some(header.to_string()),
+        ..Default::default()
+      };
+      EnvSync::sync_with_options(options).unwrap();
+    }
+
+    let content = std::fs::read_to_string(&local_path).unwrap();
+    assert_eq!(
+      content,
+      "# Generated by env-sync; do not edit above managed region\n\nKEY=local\n"
+    );
+  }
+
+  #[test]
+  fn test_empty_template_preserves_local() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    let local_content = "# Local settings\nKEY=value\nOTHER=1\n";
+    std::fs::write(&local_path, local_content).unwrap();
+    std::fs::write(&template_path, "").unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: template_path.into(),
+      ..Default::default()
+    };
+    EnvSync::sync_with_options(options).unwrap();
+
+    assert_eq!(std::fs::read_to_string(&local_path).unwrap(), local_content);
+  }
+
+  #[test]
+  fn test_empty_template_disallowed() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    let local_content = "KEY=value\n";
+    std::fs::write(&local_path, local_content).unwrap();
+    std::fs::write(&template_path, "# Only a comment\n").unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: template_path.clone().into(),
+      allow_empty_template: false,
+      ..Default::default()
+    };
+
+    match EnvSync::sync_with_options(options) {
+      Err(EnvSyncError::EmptyTemplate) => {}
+      other => panic!("Expected EmptyTemplate error, got {:?}", other),
+    }
+    assert_eq!(std::fs::read_to_string(&local_path).unwrap(), local_content);
+
+    let missing_path = temp_dir.path().join(".env.missing");
+    let options = EnvSyncOptions {
+      local_file: Some(missing_path.clone()),
+      template_source: template_path.into(),
+      allow_empty_template: false,
+      ..Default::default()
+    };
+    assert!(matches!(
+      EnvSync::sync_with_options(options),
+      Err(EnvSyncError::EmptyTemplate)
+    ));
+    assert!(!missing_path.exists());
+  }
+
+  #[test]
+  fn test_empty_template_allowed() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    std::fs::write(&local_path, "KEY=value\n").unwrap();
+    std::fs::write(&template_path, "# Only a comment\n").unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: template_path.into(),
+      allow_empty_template: true,
+      ..Default::default()
+    };
+
+    assert!(EnvSync::sync_with_options(options).is_ok());
+  }
+
+  #[test]
+  fn test_sync_inline_template() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+
+    std::fs::write(&local_path, "API_KEY=secret\n").unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline("# Embedded\nAPI_KEY=\nPORT=8080\n".to_string()),
+      ..Default::default()
+    };
+    EnvSync::sync_with_options(options).unwrap();
+
+    assert_eq!(
+      std::fs::read_to_string(&local_path).unwrap(),
+      "# Embedded\nAPI_KEY=secret\nPORT=8080\n"
+    );
+  }
+
+  #[test]
+  fn test_utf16_template_is_rejected() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    std::fs::write(&local_path, "KEY=value\n").unwrap();
+    let mut template_bytes = vec![0xFF, 0xFE];
+    template_bytes.extend("KEY=\n".encode_utf16().flat_map(u16::to_le_bytes));
+    std::fs::write(&template_path, template_bytes).unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path),
+      template_source: template_path.clone().into(),
+      ..Default::default()
+    };
+
+    match EnvSync::sync_with_options(options) {
+      Err(EnvSyncError::EncodingMismatch { path }) => assert_eq!(path, template_path),
+      other => panic!("Expected EncodingMismatch error, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_dry_run_does_not_write() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    std::fs::write(&template_path, "KEY=template\n").unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: template_path.into(),
+      dry_run: true,
+      ..Default::default()
+    };
+    let report = EnvSync::sync_with_options(options).unwrap();
+
+    assert!(!local_path.exists());
+    assert!(!report.written);
+    assert!(report.has_changes());
+    assert_eq!(report.original, "");
+    assert_eq!(report.synced, "KEY=template\n");
+  }
+
+  #[test]
+  fn test_deprecated_key_in_use() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    std::fs::write(&local_path, "OLD_KEY=still-here\nNEW_KEY=\n").unwrap();
+    std::fs::write(
+      &template_path,
+      "# @deprecated use NEW_KEY instead\nOLD_KEY=\nNEW_KEY=\n",
+    )
+    .unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path),
+      template_source: template_path.into(),
+      ..Default::default()
+    };
+    let report = EnvSync::sync_with_options(options).unwrap();
+
+    assert_eq!(
+      report.deprecated,
+      vec![DeprecatedKey {
+        key: "OLD_KEY".to_string(),
+        message: "use NEW_KEY instead".to_string(),
+      }]
+    );
+  }
+
+  #[test]
+  fn test_deprecated_key_not_in_use() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    std::fs::write(&local_path, "OLD_KEY=\nNEW_KEY=value\n").unwrap();
+    std::fs::write(
+      &template_path,
+      "# @deprecated use NEW_KEY instead\nOLD_KEY=\nNEW_KEY=\n",
+    )
+    .unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path),
+      template_source: template_path.into(),
+      ..Default::default()
+    };
+    let report = EnvSync::sync_with_options(options).unwrap();
+
+    assert!(report.deprecated.is_empty());
+  }
+
+  #[test]
+  fn test_nonsecret_allowlist_masks_report() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+    let allowlist_path = temp_dir.path().join("nonsecret.txt");
+
+    std::fs::write(&local_path, "DB_HOST=localhost\nDB_USER=admin\n").unwrap();
+    std::fs::write(
+      &template_path,
+      "DB_HOST=db.internal\nDB_USER=root\nAPI_TOKEN=t0ken\n",
+    )
+    .unwrap();
+    std::fs::write(&allowlist_path, "DB_HOST\nAPI_TOKEN\n").unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path),
+      template_source: template_path.into(),
+      nonsecret_allowlist: Some(allowlist_path),
+      dry_run: true,
+      ..Default::default()
+    };
+    let report = EnvSync::sync_with_options(options).unwrap();
+
+    assert_eq!(
+      report.masked_changes(),
+      vec![
+        Change::Modified {
+          key: "DB_HOST".to_string(),
+          old: "localhost".to_string(),
+          new: "db.internal".to_string(),
+        },
+        Change::Modified {
+          key: "DB_USER".to_string(),
+          old: "****".to_string(),
+          new: "****".to_string(),
+        },
+        Change::Added {
+          key: "API_TOKEN".to_string(),
+          value: "t0ken".to_string(),
+        },
+      ]
+    );
+  }
+
+  #[cfg(feature = "diff")]
+  #[test]
+  fn test_write_patch() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+    let patch_path = temp_dir.path().join("sync.patch");
+
+    std::fs::write(&local_path, "API_KEY=secret\nDB_HOST=localhost\n").unwrap();
+    std::fs::write(&template_path, "API_KEY=\nDB_HOST=db\nNEW=1\n").unwrap();
+
+    let sync = |dry_run| {
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: template_path.clone().into(),
+        write_patch: Some(patch_path.clone()),
+        dry_run,
+        ..Default::default()
+      })
+      .unwrap()
+    };
+
+    sync(true);
+    assert!(!patch_path.exists());
+
+    sync(false);
+    let patch = std::fs::read_to_string(&patch_path).unwrap();
+    assert_eq!(
+      patch,
+      "--- a/.env\n+++ b/.env\n@@ -1,2 +1,3 @@\n API_KEY=secret\n-DB_HOST=localhost\n+DB_HOST=db\n+NEW=1\n"
+    );
+
+    // Nothing left to apply, so the previous patch is kept
+    sync(false);
+    assert_eq!(std::fs::read_to_string(&patch_path).unwrap(), patch);
+  }
+
+  #[test]
+  fn test_bootstrap_template() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    std::fs::write(
+      &local_path,
+      "# Service\nAPI_TOKEN=abc # secret\nPORT=8080\n",
+    )
+    .unwrap();
+
+    EnvSync::bootstrap_template(&local_path, &template_path).unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&template_path).unwrap(),
+      "# Service\nAPI_TOKEN= # secret\nPORT=8080\n"
+    );
+
+    match EnvSync::bootstrap_template(&local_path, &template_path) {
+      Err(EnvSyncError::TemplateExists(path)) => assert_eq!(path, template_path),
+      other => panic!("Expected TemplateExists error, got {:?}", other),
+    }
+  }
+
+  fn sync_invalid_local(
+    policy: LocalParseErrorPolicy,
+  ) -> (Result<SyncReport, EnvSyncError>, String) {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template_path = temp_dir.path().join(".env.template");
+
+    std::fs::write(
+      &local_path,
+      "API_KEY=secret\nthis line is broken\nPORT=9000\n",
+    )
+    .unwrap();
+    std::fs::write(&template_path, "API_KEY=\nPORT=\nHOST=localhost\n").unwrap();
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: template_path.into(),
+      on_local_parse_error: policy,
+      ..Default::default()
+    };
+    let result = EnvSync::sync_with_options(options);
+    (result, std::fs::read_to_string(&local_path).unwrap())
+  }
+
+  #[test]
+  fn test_local_parse_error_abort() {
+    let (result, content) = sync_invalid_local(LocalParseErrorPolicy::Abort);
+
+    assert!(matches!(result, Err(EnvSyncError::LocalParse(_))));
+    assert_eq!(content, "API_KEY=secret\nthis line is broken\nPORT=9000\n");
+  }
+
+  #[test]
+  fn test_local_parse_error_skip_line() {
+    let (result, content) = sync_invalid_local(LocalParseErrorPolicy::SkipLine);
+
+    assert!(result.is_ok());
+    assert_eq!(content, "API_KEY=secret\nPORT=9000\nHOST=localhost\n");
+  }
+
+  #[test]
+  fn test_local_parse_error_recreate() {
+    let (result, content) = sync_invalid_local(LocalParseErrorPolicy::Recreate);
+
+    assert!(result.is_ok());
+    assert_eq!(content, "API_KEY=\nPORT=\nHOST=localhost\n");
+  }
+
+  #[test]
+  fn test_appended_key_placed_above_footer() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(&local_path, "HOST=localhost\nLOCAL_ONLY=1\n").unwrap();
+
+    let template = "HOST=\n\n# Footer: see docs/config.md\n# for every option\n";
+    let sync = |orphan_placement| {
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline(template.to_string()),
+        overrides: vec![("LOCAL_ONLY".to_string(), "1".to_string())],
+        dry_run: true,
+        orphan_placement,
+        ..Default::default()
+      })
+      .unwrap()
+    };
+
+    assert_eq!(
+      sync(OrphanPlacement::BeforeFooter).synced,
+      "HOST=localhost\n\nLOCAL_ONLY=1\n\n# Footer: see docs/config.md\n# for every option\n"
+    );
+    assert_eq!(
+      sync(OrphanPlacement::End).synced,
+      "HOST=localhost\n\n# Footer: see docs/config.md\n# for every option\n\nLOCAL_ONLY=1\n"
+    );
+    let overridden = EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline(template.to_string()),
+      overrides: vec![("NEW_KEY".to_string(), "1".to_string())],
+      dry_run: true,
+      orphan_placement: OrphanPlacement::BeforeFooter,
+      prune: true,
+      ..Default::default()
+    })
+    .unwrap();
+    assert_eq!(
+      overridden.synced,
+      "HOST=localhost\nNEW_KEY=1\n\n# Footer: see docs/config.md\n# for every option\n"
+    );
+  }
+
+  #[test]
+  fn test_override_source() {
+    let mut synced = EnvSync::sync_files(
+      &"HOST=localhost".try_into().unwrap(),
+      "HOST=\nPORT=80".try_into().unwrap(),
+    )
+    .unwrap();
+
+    EnvSync::apply_override(
+      &mut synced,
+      "PORT".to_string(),
+      "8080".to_string(),
+      OrphanPlacement::End,
+    );
+    EnvSync::apply_override(
+      &mut synced,
+      "DEBUG".to_string(),
+      "1".to_string(),
+      OrphanPlacement::End,
+    );
+
+    assert_eq!(synced.get("HOST").unwrap().source(), Some(Source::Local));
+    assert_eq!(synced.get("PORT").unwrap().source(), Some(Source::Override));
+    assert_eq!(
+      synced.get("DEBUG").unwrap().source(),
+      Some(Source::Override)
+    );
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_symlinked_local_file() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let target_path = temp_dir.path().join("shared.env");
+    let local_path = temp_dir.path().join(".env");
+
+    let sync = |follow_symlinks| {
+      std::fs::write(&target_path, "KEY=local\n").unwrap();
+      let _ = std::fs::remove_file(&local_path);
+      std::os::unix::fs::symlink(&target_path, &local_path).unwrap();
+
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline("KEY=\nNEW=1\n".to_string()),
+        follow_symlinks,
+        ..Default::default()
+      })
+      .unwrap();
+    };
+
+    sync(true);
+    assert!(local_path.symlink_metadata().unwrap().is_symlink());
+    assert_eq!(
+      std::fs::read_to_string(&target_path).unwrap(),
+      "KEY=local\nNEW=1\n"
+    );
+
+    sync(false);
+    assert!(!local_path.symlink_metadata().unwrap().is_symlink());
+    assert_eq!(
+      std::fs::read_to_string(&local_path).unwrap(),
+      "KEY=local\nNEW=1\n"
+    );
+    assert_eq!(
+      std::fs::read_to_string(&target_path).unwrap(),
+      "KEY=local\n"
+    );
+  }
+
+  #[test]
+  fn test_tidy_collapses_blank_lines() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let template = "# App\nHOST=\n\n\n\n# Database\nDB_URL=\n\n\n";
+
+    let sync = |tidy| {
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline(template.to_string()),
+        header: Some("Generated file".to_string()),
+        dry_run: true,
+        tidy,
+        ..Default::default()
+      })
+      .unwrap()
+      .synced
+    };
+
+    assert!(sync(false).contains("\n\n\n"));
+
+    let tidy = sync(true);
+    assert!(!tidy.contains("\n\n\n"));
+    assert_eq!(
+      tidy,
+      "# Generated file\n\n# App\nHOST=\n\n# Database\nDB_URL=\n\n"
+    );
+  }
+
+  #[test]
+  fn test_defaults_file_fills_empty_values() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let defaults_path = temp_dir.path().join(".env.defaults");
+    std::fs::write(&local_path, "HOST=localhost\n").unwrap();
+    std::fs::write(
+      &defaults_path,
+      "HOST=default.example.com\nPORT=1234\nREGION=eu-west-1\nUNUSED=1\n",
+    )
+    .unwrap();
+
+    let report = EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline("HOST=\nPORT=80\nREGION=\n".to_string()),
+      defaults_file: Some(defaults_path),
+      ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(report.synced, "HOST=localhost\nPORT=80\nREGION=eu-west-1\n");
+  }
+
+  #[test]
+  fn test_minimal_rewrite_keeps_untouched_lines() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(
+      &local_path,
+      "# Docs\n  HOST =  localhost\t#  primary\nPORT=80\nOLD=1\n",
+    )
+    .unwrap();
+
+    let sync = |minimal_rewrite| {
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline("HOST=\nPORT=8080\nNEW=1\n".to_string()),
+        dry_run: true,
+        minimal_rewrite,
+        prune: true,
+        ..Default::default()
+      })
+      .unwrap()
+      .synced
+    };
+
+    assert_eq!(
+      sync(true),
+      "# Docs\n  HOST =  localhost\t#  primary\nPORT=8080\nNEW=1\n"
+    );
+    assert_eq!(
+      sync(false),
+      "# Docs\nHOST=localhost #  primary\nPORT=8080\nNEW=1\n"
+    );
+  }
+
+  #[test]
+  fn test_minimal_rewrite_multiline_values() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(
+      &local_path,
+      "CERT=\"line1\nline2\nline3\"\nset  HOST = localhost\nPORT=80\n",
+    )
+    .unwrap();
+
+    let report = EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline("CERT=\nHOST=\nPORT=8080\n".to_string()),
+      dry_run: true,
+      minimal_rewrite: true,
+      ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(
+      report.synced,
+      "CERT=\"line1\nline2\nline3\"\nset  HOST = localhost\nPORT=8080\n"
+    );
+  }
+
+  #[test]
+  fn test_minimal_rewrite_export_from_template() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(&local_path, "FOO=1\nBAR = 2\n").unwrap();
+
+    let sync = |minimal_rewrite| {
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline("export FOO=\nBAR=\n".to_string()),
+        dry_run: true,
+        minimal_rewrite,
+        ..Default::default()
+      })
+      .unwrap()
+      .synced
+    };
+
+    assert_eq!(sync(false), "export FOO=1\nBAR=2\n");
+    assert_eq!(sync(true), "export FOO=1\nBAR = 2\n");
+  }
+
+  #[test]
+  fn test_group_by_prefix() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(&local_path, "DB_PASSWORD=hunter2\n").unwrap();
+
+    let report = EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline(
+        "REDIS_URL=redis://cache\nDB_PASSWORD=\nDEBUG=false\nDB_HOST=db\nREDIS_TTL=60\n"
+          .to_string(),
+      ),
+      header: Some("Generated".to_string()),
+      group_by_prefix: true,
+      group_headers: true,
+      dry_run: true,
+      ..Default::default()
+    })
+    .unwrap();
+
+    assert_eq!(
+      report.synced,
+      "# Generated\n\nDEBUG=false\n\n# DB\nDB_HOST=db\nDB_PASSWORD=hunter2\n\n# REDIS\nREDIS_TTL=60\nREDIS_URL=redis://cache\n"
+    );
+  }
+
+  #[test]
+  fn test_invalid_utf8_local_is_decode_error() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(&local_path, b"KEY=caf\xe9\n").unwrap();
+
+    let result = EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline("KEY=\n".to_string()),
+      ..Default::default()
+    });
+
+    match result {
+      Err(EnvSyncError::Decode { path }) => assert_eq!(path, local_path),
+      other => panic!("Expected Decode error, got {:?}", other),
+    }
+  }
+
+  #[test]
+  fn test_fill_from_env() {
+    const KEY: &str = "ENV_SYNC_TEST_FILL_FROM_ENV_TOKEN";
+    // Stands in for the process environment, which tests running in parallel can't mutate
+    fn lookup(name: &str) -> Option<String> {
+      (name == KEY).then(|| "from-ci".to_string())
+    }
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let defaults_path = temp_dir.path().join(".env.defaults");
+    std::fs::write(&defaults_path, format!("{}=from-defaults\n", KEY)).unwrap();
+
+    let sync = |fill_from_env| {
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline(format!("{}=\nOTHER=\n", KEY)),
+        defaults_file: Some(defaults_path.clone()),
+        fill_from_env,
+        env_lookup: lookup,
+        dry_run: true,
+        ..Default::default()
+      })
+      .unwrap()
+      .synced
+    };
+
+    assert_eq!(sync(true), format!("{}=from-ci\nOTHER=\n", KEY));
+    assert_eq!(sync(false), format!("{}=from-defaults\nOTHER=\n", KEY));
+  }
+
+  #[test]
+  fn test_local_header_survives_prune() {
+    let local: EnvFile =
+      "# Project settings\n# See docs/env.md\n\n# Old feature\nLOCAL_ONLY=1\nDB_HOST=localhost\n"
+        .try_into()
+        .unwrap();
+    let template: EnvFile = "DB_HOST=\nDB_PORT=5432\n".try_into().unwrap();
+    let pruning = SyncSettings {
+      prune: true,
+      ..Default::default()
+    };
+
+    let synced = EnvSync::sync(&local, template.clone(), &pruning).unwrap();
+    let expected = "# Project settings\n# See docs/env.md\n\nDB_HOST=localhost\nDB_PORT=5432\n";
+    assert_eq!(synced.to_string(), expected);
+
+    let resynced = EnvSync::sync(&synced, template.clone(), &pruning).unwrap();
+    assert_eq!(resynced.to_string(), expected);
+
+    let with_header: EnvFile = "# Template header\n\nDB_HOST=\n".try_into().unwrap();
+    let synced = EnvSync::sync(&local, with_header, &pruning).unwrap();
+    assert_eq!(
+      synced.to_string(),
+      "# Template header\n\nDB_HOST=localhost\n"
+    );
+
+    let settings = SyncSettings {
+      keep_local_header: false,
+      ..pruning
+    };
+    let synced = EnvSync::sync(&local, template, &settings).unwrap();
+    assert_eq!(synced.to_string(), "DB_HOST=localhost\nDB_PORT=5432\n");
+  }
+
+  #[test]
+  fn test_merge_with() {
+    let local: EnvFile = "DB_HOST=db.local\nDB_PORT=6543\nLOCAL_ONLY=1\n"
+      .try_into()
+      .unwrap();
+    let template: EnvFile = "DB_HOST=\nDB_PORT=5432\nDB_NAME=app\n".try_into().unwrap();
+
+    let (synced, report) =
+      EnvSync::merge_with(&local, &template, &SyncSettings::default()).unwrap();
+    assert_eq!(
+      synced.to_string(),
+      "DB_HOST=db.local\nDB_PORT=5432\nDB_NAME=app\n\nLOCAL_ONLY=1\n"
+    );
+    assert_eq!(report.synced, synced.to_string());
+    assert_eq!(report.original, local.to_string());
+    assert!(!report.written);
+    assert_eq!(report.summary().added, ["DB_NAME"]);
+    assert_eq!(report.summary().changed, ["DB_PORT"]);
+
+    let settings = SyncSettings {
+      prune: true,
+      prefer_local: true,
+      ..Default::default()
+    };
+    let (synced, _) = EnvSync::merge_with(&local, &template, &settings).unwrap();
+    assert_eq!(
+      synced.to_string(),
+      "DB_HOST=db.local\nDB_PORT=6543\nDB_NAME=app\n"
+    );
+    assert_eq!(synced.get("DB_PORT").unwrap().source(), Some(Source::Local));
+
+    let settings = SyncSettings {
+      prune: true,
+      keys_only: true,
+      overrides: vec![("DB_HOST".to_string(), "db.test".to_string())],
+      ..Default::default()
+    };
+    let (synced, report) = EnvSync::merge_with(&local, &template, &settings).unwrap();
+    assert_eq!(
+      synced.to_string(),
+      "DB_HOST=db.test\nDB_PORT=6543\nDB_NAME=\n"
+    );
+    assert_eq!(report.summary().orphans, ["LOCAL_ONLY"]);
+
+    let settings = SyncSettings {
+      region: Some("missing".to_string()),
+      ..Default::default()
+    };
+    assert!(matches!(
+      EnvSync::merge_with(&local, &template, &settings),
+      Err(EnvSyncError::RegionNotFound(_))
+    ));
+  }
+
+  #[test]
+  fn test_sync_region() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(
+      &local_path,
+      "# region App\nAPP_NAME=mine\nAPP_OLD=1\n# endregion\n\n# region Database\nDB_HOST=localhost\nDB_LOCAL=1\n# endregion\nOUTSIDE=1\n",
+    )
+    .unwrap();
+    let template = "# region App\nAPP_NAME=\nAPP_PORT=80\n# endregion\n\n# region Database\nDB_HOST=db.example.com\nDB_PORT=5432\n# endregion\n";
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline(template.to_string()),
+      region: Some("Database".to_string()),
+      prune: true,
+      ..Default::default()
+    };
+    EnvSync::sync_with_options(options).unwrap();
+
+    assert_eq!(
+      std::fs::read_to_string(&local_path).unwrap(),
+      "# region App\nAPP_NAME=mine\nAPP_OLD=1\n# endregion\n\n# region Database\nDB_HOST=db.example.com\nDB_PORT=5432\n# endregion\nOUTSIDE=1\n"
+    );
+
+    std::fs::write(&local_path, "OUTSIDE=1\n").unwrap();
+    let options = EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline(template.to_string()),
+      region: Some("App".to_string()),
+      ..Default::default()
+    };
+    EnvSync::sync_with_options(options).unwrap();
+    assert_eq!(
+      std::fs::read_to_string(&local_path).unwrap(),
+      "OUTSIDE=1\n\n# region App\nAPP_NAME=\nAPP_PORT=80\n# endregion\n"
+    );
+
+    let options = EnvSyncOptions {
+      local_file: Some(local_path),
+      template_source: TemplateSource::Inline(template.to_string()),
+      region: Some("Missing".to_string()),
+      ..Default::default()
+    };
+    assert!(matches!(
+      EnvSync::sync_with_options(options),
+      Err(EnvSyncError::RegionNotFound(name)) if name == "Missing"
+    ));
+  }
+
+  #[test]
+  fn test_plan_matches_sync() {
+    let local: EnvFile = "# Project notes\nAPI_KEY=secret # local note\n# Local docs\nDB_HOST=localhost\nSAME=1\nLOCAL_ONLY=x\nKEY<<END\na\nb\nEND\n".try_into().unwrap();
+    let template: EnvFile = "API_KEY=\nDB_HOST=db.example.com # template note\nSAME=1\nKEY=\n\n# Signing key\nCERT<<EOF\nline\nEOF\n"
+      .try_into()
+      .unwrap();
+
+    let plan = EnvSync::plan(&local, &template);
+    let synced = EnvSync::sync_files(&local, template.clone()).unwrap();
+    assert_eq!(plan.to_string(), synced.to_string());
+
+    let sources: Vec<(&str, Source)> = plan
+      .entries
+      .iter()
+      .filter_map(|entry| match entry {
+        PlannedEntry::Variable(planned) => Some((planned.template.key.as_ref(), planned.source)),
+        PlannedEntry::Keep(_) => None,
+      })
+      .collect();
+    let expected: Vec<(&str, Source)> = synced
+      .variables()
+      .map(|var| (var.key.as_ref(), var.source().unwrap()))
+      .collect();
+    assert_eq!(sources, expected);
+    assert_eq!(sources[0], ("API_KEY", Source::Local));
+    assert_eq!(
+      synced.get("KEY").unwrap().to_string(),
+      "KEY<<END\na\nb\nEND"
+    );
+  }
+
+  #[cfg(unix)]
+  #[test]
+  fn test_file_mode() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let mode = || std::fs::metadata(&local_path).unwrap().permissions().mode() & 0o777;
+    let options = || EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline("API_KEY=\n".to_string()),
+      file_mode: Some(0o600),
+      ..Default::default()
+    };
+
+    EnvSync::sync_with_options(options()).unwrap();
+    assert_eq!(mode(), 0o600);
+
+    std::fs::set_permissions(&local_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    EnvSync::sync_with_options(options()).unwrap();
+    assert_eq!(mode(), 0o600);
+
+    // Rewriting an existing file with new content
+    std::fs::write(&local_path, "API_KEY=secret\nOLD=1\n").unwrap();
+    std::fs::set_permissions(&local_path, std::fs::Permissions::from_mode(0o644)).unwrap();
+    let report = EnvSync::sync_with_options(EnvSyncOptions {
+      prune: true,
+      ..options()
+    })
+    .unwrap();
+    assert!(report.written);
+    assert_eq!(mode(), 0o600);
+    assert_eq!(
+      std::fs::read_to_string(&local_path).unwrap(),
+      "API_KEY=secret\n"
+    );
+  }
+
+  #[test]
+  fn test_local_files_priority() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let base_path = temp_dir.path().join(".env");
+    let local_path = temp_dir.path().join(".env.local");
+    let base_content = "API_KEY=base_key\nDB_HOST=localhost\nSHARED=low\n";
+    std::fs::write(&base_path, base_content).unwrap();
+    std::fs::write(&local_path, "SHARED=high\n").unwrap();
+
+    let options = EnvSyncOptions {
+      local_files: vec![
+        temp_dir.path().join(".env.missing"),
+        base_path.clone(),
+        local_path.clone(),
+      ],
+      template_source: TemplateSource::Inline("API_KEY=\nDB_HOST=\nSHARED=\nNEW=1\n".to_string()),
+      ..Default::default()
+    };
+    let report = EnvSync::sync_with_options(options).unwrap();
+
+    assert_eq!(report.local_path, local_path);
+    assert_eq!(
+      std::fs::read_to_string(&local_path).unwrap(),
+      "API_KEY=base_key\nDB_HOST=localhost\nSHARED=high\nNEW=1\n"
+    );
+    assert_eq!(std::fs::read_to_string(&base_path).unwrap(), base_content);
+
+    // An explicit local file is written on top of all the layers
+    let override_path = temp_dir.path().join(".env.override");
+    std::fs::write(&override_path, "DB_HOST=override\n").unwrap();
+    let options = EnvSyncOptions {
+      local_file: Some(override_path.clone()),
+      local_files: vec![base_path.clone(), local_path.clone()],
+      template_source: TemplateSource::Inline("API_KEY=\nDB_HOST=\nSHARED=\nNEW=\n".to_string()),
+      ..Default::default()
+    };
+    let report = EnvSync::sync_with_options(options).unwrap();
+
+    assert_eq!(report.local_path, override_path);
+    assert_eq!(
+      std::fs::read_to_string(&override_path).unwrap(),
+      "API_KEY=base_key\nDB_HOST=override\nSHARED=high\nNEW=1\n"
+    );
+  }
+
+  #[test]
+  fn test_local_only_appended() {
+    let local: EnvFile = "API_KEY=secret\n# Personal token\nDEBUG_TOKEN=xyz # expires monthly\n"
+      .try_into()
+      .unwrap();
+    let template: EnvFile = "# Api\nAPI_KEY=\nDB_HOST=localhost\n".try_into().unwrap();
+
+    let synced = EnvSync::sync_files(&local, template.clone()).unwrap();
+    assert_eq!(
+      synced.to_string(),
+      "# Api\nAPI_KEY=secret\nDB_HOST=localhost\n\n# Personal token\nDEBUG_TOKEN=xyz # expires monthly\n"
+    );
+    assert_eq!(
+      synced.get("DEBUG_TOKEN").unwrap().source(),
+      Some(Source::Local)
+    );
+    let resynced = EnvSync::sync_files(&synced, template).unwrap();
+    assert_eq!(resynced.to_string(), synced.to_string());
+  }
+
+  #[test]
+  fn test_prune() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let local_content = "API_KEY=secret\n# Removed last release\nOLD_KEY=1\n";
+    let sync = |prune| {
+      std::fs::write(&local_path, local_content).unwrap();
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline("API_KEY=\nDB_HOST=localhost\n".to_string()),
+        prune,
+        ..Default::default()
+      })
+      .unwrap()
+      .synced
+    };
+
+    assert_eq!(sync(true), "API_KEY=secret\nDB_HOST=localhost\n");
+    let kept = sync(false);
+    assert_eq!(
+      kept,
+      "API_KEY=secret\nDB_HOST=localhost\n\n# Removed last release\nOLD_KEY=1\n"
+    );
+
+    std::fs::write(&local_path, &kept).unwrap();
+    let report = EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline("API_KEY=\nDB_HOST=localhost\n".to_string()),
+      prune: false,
+      ..Default::default()
+    })
+    .unwrap();
+    assert!(!report.has_changes());
+  }
+
+  #[test]
+  fn test_prune_keeps_comments_as_orphans() {
+    let local: EnvFile = "API_KEY=secret\n# Removed last release\nOLD_KEY=1\nNO_DOCS=1\n"
+      .try_into()
+      .unwrap();
+    let template: EnvFile = "API_KEY=\nDB_HOST=localhost\n".try_into().unwrap();
+    let settings = |pruned_comments| SyncSettings {
+      prune: true,
+      pruned_comments,
+      ..Default::default()
+    };
+
+    let synced = EnvSync::sync(&local, template.clone(), &settings(CommentOnRemove::Delete));
+    assert_eq!(
+      synced.unwrap().to_string(),
+      "API_KEY=secret\nDB_HOST=localhost\n"
+    );
+
+    let synced = EnvSync::sync(&local, template, &settings(CommentOnRemove::Orphan)).unwrap();
+    assert_eq!(
+      synced.to_string(),
+      "API_KEY=secret\nDB_HOST=localhost\n\n# Removed last release\n"
+    );
+    assert!(synced.get("OLD_KEY").is_none());
+  }
+
+  #[test]
+  fn test_template_duplicate_key() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(&local_path, "API_KEY=secret\n").unwrap();
+    let sync = |allow_duplicate_template_keys| {
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline("PORT=80\nAPI_KEY=\nPORT=8080\n".to_string()),
+        dry_run: true,
+        allow_duplicate_template_keys,
+        ..Default::default()
+      })
+    };
+
+    assert!(matches!(
+      sync(false),
+      Err(EnvSyncError::TemplateDuplicateKey { key }) if key == "PORT"
+    ));
+    assert_eq!(sync(true).unwrap().synced, "PORT=8080\nAPI_KEY=secret\n");
+
+    // The failed sync doesn't create a missing local file
+    let missing_path = temp_dir.path().join(".env.missing");
+    let result = EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(missing_path.clone()),
+      template_source: TemplateSource::Inline("A=1\nA=2\n".to_string()),
+      ..Default::default()
+    });
+    assert!(matches!(
+      result,
+      Err(EnvSyncError::TemplateDuplicateKey { key }) if key == "A"
+    ));
+    assert!(!missing_path.exists());
+
+    // A `set ` prefix names the same key, for the duplicate check and the dedup alike
+    let template: EnvFile = "set PORT=80\nAPI_KEY=\nPORT=8080\n".try_into().unwrap();
+    assert_eq!(
+      EnvSync::first_duplicate_key(&template).as_deref(),
+      Some("PORT")
+    );
+    let mut deduped = template.clone();
+    deduped.dedup_keys(DedupKeep::LastKeepFirstPos);
+    assert_eq!(deduped.to_string(), "PORT=8080\nAPI_KEY=\n");
+
+    let settings = SyncSettings {
+      deletions: vec!["PORT".to_string()],
+      ..Default::default()
+    };
+    let template: EnvFile = "set PORT=80\nAPI_KEY=\n".try_into().unwrap();
+    let (synced, _) = EnvSync::merge_with(&EnvFile::default(), &template, &settings).unwrap();
+    assert_eq!(synced.to_string(), "API_KEY=\n");
+  }
+
+  #[test]
+  fn test_ignore_case_values() {
+    let local: EnvFile = "FLAG=true\nPORT=80\n".try_into().unwrap();
+    let template: EnvFile = "FLAG=TRUE\nPORT=8080\n".try_into().unwrap();
+
+    let (_, report) = EnvSync::merge_with(&local, &template, &SyncSettings::default()).unwrap();
+    assert_eq!(report.synced, "FLAG=TRUE\nPORT=8080\n");
+    assert_eq!(report.changes.len(), 2);
+
+    let settings = SyncSettings {
+      diff_options: DiffOptions {
+        ignore_case_values: true,
+      },
+      ..Default::default()
+    };
+    let (_, report) = EnvSync::merge_with(&local, &template, &settings).unwrap();
+    assert_eq!(report.synced, "FLAG=true\nPORT=8080\n");
+    assert_eq!(
+      report.changes,
+      vec![Change::Modified {
+        key: "PORT".to_string(),
+        old: "80".to_string(),
+        new: "8080".to_string(),
+      }]
+    );
+  }
+
+  #[test]
+  fn test_unchanged_file_not_rewritten() {
+    use std::time::{Duration, SystemTime};
+
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    std::fs::write(&local_path, "API_KEY=secret\nDB_HOST=localhost\n").unwrap();
+    let modified = SystemTime::now() - Duration::from_secs(3600);
+    std::fs::File::options()
+      .write(true)
+      .open(&local_path)
+      .unwrap()
+      .set_modified(modified)
+      .unwrap();
+
+    let report = EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline("API_KEY=\nDB_HOST=localhost\n".to_string()),
+      backup: true,
+      ..Default::default()
+    })
+    .unwrap();
+
+    assert!(!report.written);
+    let metadata = std::fs::metadata(&local_path).unwrap();
+    assert_eq!(metadata.modified().unwrap(), modified);
+    assert!(!temp_dir.path().join(".env.bak").exists());
+  }
+
+  #[test]
+  fn test_backup() {
+    let temp_dir = tempfile::TempDir::new().unwrap();
+    let local_path = temp_dir.path().join(".env");
+    let backup_path = temp_dir.path().join(".env.bak");
+    let sync = || {
+      EnvSync::sync_with_options(EnvSyncOptions {
+        local_file: Some(local_path.clone()),
+        template_source: TemplateSource::Inline("API_KEY=\nDB_HOST=localhost\n".to_string()),
+        backup: true,
+        ..Default::default()
+      })
+      .unwrap()
+    };
+
+    sync();
+    assert!(!backup_path.exists());
+
+    std::fs::write(&local_path, "API_KEY=secret\n").unwrap();
+    sync();
+    assert_eq!(
+      std::fs::read_to_string(&backup_path).unwrap(),
+      "API_KEY=secret\n"
+    );
+    assert_eq!(
+      std::fs::read_to_string(&local_path).unwrap(),
+      "API_KEY=secret\nDB_HOST=localhost\n"
+    );
   }
 
   #[test]
@@ -224,7 +2956,8 @@ mod tests {
 
     let options = EnvSyncOptions {
       local_file: None,
-      template_file: PathBuf::from("nonexistent.env.template"),
+      template_source: PathBuf::from("nonexistent.env.template").into(),
+      ..Default::default()
     };
 
     let result = EnvSync::sync_with_options(options);
@@ -1,28 +1,119 @@
-use clap::Parser;
-use env_sync::sync::{EnvSync, EnvSyncOptions};
-use std::path::PathBuf;
+use clap::{Parser, Subcommand, ValueEnum};
+#[cfg(feature = "config")]
+use env_sync::config::Config;
+use env_sync::parse::{EnvFile, ParseOptions};
+use env_sync::sync::{EnvSync, EnvSyncOptions, SyncReport, TemplateSource};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
 
 #[derive(Parser)]
 #[command(
   name = "env-sync",
   about = "Easily update your local env file with a git-trackable file",
   version,
-  author
+  author,
+  args_conflicts_with_subcommands = true
 )]
 struct Cli {
+  #[command(subcommand)]
+  command: Option<Command>,
+
   /// Path to the local .env file
   #[arg(short, long)]
   local: Option<PathBuf>,
 
-  /// Path to the template file
-  #[arg(short, long, default_value = ".env.template")]
-  template: PathBuf,
+  /// Path to the template file [default: .env.template]
+  #[arg(short, long)]
+  template: Option<PathBuf>,
+
+  /// Force a variable to a value in the output (repeatable)
+  #[arg(long = "set", alias = "force-value", value_name = "KEY=VALUE", value_parser = parse_key_value)]
+  overrides: Vec<(String, String)>,
+
+  /// Set the inline comment of a variable in the output (repeatable)
+  #[arg(long = "comment", value_name = "KEY=TEXT", value_parser = parse_key_value)]
+  comments: Vec<(String, String)>,
+
+  /// Remove a variable from the output (repeatable)
+  #[arg(long = "delete", value_name = "KEY")]
+  deletions: Vec<String>,
+
+  /// Remove local variables that aren't in the template instead of keeping them at the end
+  #[arg(long)]
+  prune: bool,
+
+  /// Fail instead of syncing when the template contains no variables
+  #[arg(long)]
+  deny_empty_template: bool,
+
+  /// Copy the local file to <LOCAL>.bak before overwriting it
+  #[arg(long)]
+  backup: bool,
+
+  /// Accept a template that defines a key more than once, using its last value
+  #[arg(long)]
+  allow_duplicate_template_keys: bool,
+
+  /// Compute the sync without writing the local file, printing the changes it would make
+  #[arg(long)]
+  dry_run: bool,
+
+  /// Exit with a failure status if the local file is out of sync, without writing it
+  #[arg(long)]
+  check: bool,
+
+  /// Treat values that differ only by letter case (e.g. `true` vs `TRUE`) as in sync
+  #[arg(long)]
+  ignore_case_values: bool,
+
+  /// Print the changes made to the local file as a unified diff
+  #[cfg(feature = "diff")]
+  #[arg(long)]
+  diff: bool,
+
+  /// Write the applied changes as a patch file
+  #[cfg(feature = "diff")]
+  #[arg(long, value_name = "FILE", conflicts_with_all = ["check", "dry_run"])]
+  write_patch: Option<PathBuf>,
+
+  /// How to print the sync results
+  #[cfg(feature = "json")]
+  #[arg(long, value_enum, default_value_t = ReportFormat::Human)]
+  report_format: ReportFormat,
+
+  /// Generate the template from the local file instead of syncing
+  #[arg(long)]
+  template_from_local: bool,
 
   /// Verbose output (-v for verbose, -vv for very verbose)
   #[arg(short, long, action = clap::ArgAction::Count)]
   verbose: u8,
 }
 
+#[derive(Subcommand)]
+enum Command {
+  /// Check a single env file for mistakes, without a template
+  Validate {
+    /// Path to the env file to check
+    file: PathBuf,
+  },
+}
+
+#[derive(Clone, Copy, PartialEq, ValueEnum)]
+enum ReportFormat {
+  /// Warnings on stderr and the optional diff
+  Human,
+  /// A JSON summary of the affected keys on stdout, without values
+  Json,
+}
+
+fn parse_key_value(s: &str) -> Result<(String, String), String> {
+  s.split_once('=')
+    .map(|(key, value)| (key.trim().to_string(), value.to_string()))
+    .filter(|(key, _)| !key.is_empty())
+    .ok_or_else(|| format!("expected KEY=VALUE, got `{}`", s))
+}
+
 fn setup_tracing(verbose: u8) {
   use tracing_subscriber::fmt;
   use tracing_subscriber::prelude::*;
@@ -34,24 +125,120 @@ fn setup_tracing(verbose: u8) {
   };
 
   tracing_subscriber::registry()
-    .with(fmt::layer())
+    .with(fmt::layer().with_writer(std::io::stderr))
     .with(tracing_subscriber::EnvFilter::new(
       std::env::var("RUST_LOG").unwrap_or_else(|_| log_level.into()),
     ))
     .init();
 }
 
-fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// Prints every diagnostic for `file`, returning whether there were none.
+fn validate(file: &Path) -> Result<bool, Box<dyn std::error::Error>> {
+  let content = std::fs::read_to_string(file)?;
+  let diagnostics = EnvFile::lint(&content, &ParseOptions::default());
+
+  for diagnostic in &diagnostics {
+    println!("{}:{}: {}", file.display(), diagnostic.line(), diagnostic);
+  }
+  Ok(diagnostics.is_empty())
+}
+
+fn main() -> Result<ExitCode, Box<dyn std::error::Error>> {
   let cli = Cli::parse();
 
   setup_tracing(cli.verbose);
 
-  let options = EnvSyncOptions {
-    local_file: cli.local,
-    template_file: cli.template,
-  };
+  if let Some(Command::Validate { file }) = &cli.command {
+    return Ok(if validate(file)? {
+      ExitCode::SUCCESS
+    } else {
+      ExitCode::FAILURE
+    });
+  }
+
+  let mut options = EnvSyncOptions::default();
+  // Defaults from the project config, overridden by the flags below
+  #[cfg(feature = "config")]
+  if let Some(config) = Config::discover(std::path::Path::new("."))? {
+    config.apply(&mut options);
+  }
 
-  EnvSync::sync_with_options(options)?;
+  if let Some(local) = cli.local {
+    options.local_file = Some(local);
+  }
+  if let Some(template) = cli.template {
+    options.template_source = TemplateSource::File(template);
+  }
+  options.overrides.extend(cli.overrides);
+  options.comments.extend(cli.comments);
+  options.deletions.extend(cli.deletions);
+  options.prune |= cli.prune;
+  options.backup |= cli.backup;
+  options.allow_duplicate_template_keys |= cli.allow_duplicate_template_keys;
+  options.allow_empty_template &= !cli.deny_empty_template;
+  options.dry_run = cli.dry_run || cli.check;
+  options.diff_options.ignore_case_values |= cli.ignore_case_values;
+  #[cfg(feature = "diff")]
+  if let Some(path) = cli.write_patch {
+    options.write_patch = Some(path);
+  }
+
+  if cli.template_from_local {
+    let local = options.local_file.unwrap_or_else(|| PathBuf::from(".env"));
+    if let TemplateSource::File(template) = &options.template_source {
+      EnvSync::bootstrap_template(&local, template)?;
+    }
+    return Ok(ExitCode::SUCCESS);
+  }
+
+  let report = EnvSync::sync_with_options(options)?;
+
+  #[cfg(feature = "json")]
+  if cli.report_format == ReportFormat::Json {
+    println!("{}", report.to_json());
+    return Ok(check_status(cli.check, &report));
+  }
+
+  for deprecated in &report.deprecated {
+    if deprecated.message.is_empty() {
+      eprintln!("warning: {} is deprecated", deprecated.key);
+    } else {
+      eprintln!(
+        "warning: {} is deprecated: {}",
+        deprecated.key, deprecated.message
+      );
+    }
+  }
+
+  if cli.dry_run {
+    for change in report.masked_changes() {
+      println!("{}", change);
+    }
+  } else if cli.check && report.has_changes() {
+    if report.changes.is_empty() {
+      println!(
+        "{} differs from the synced output",
+        report.local_path.display()
+      );
+    }
+    for change in &report.changes {
+      println!("{} is out of sync", change.key());
+    }
+  }
+
+  #[cfg(feature = "diff")]
+  if cli.diff {
+    print!("{}", report.unified_diff());
+  }
+
+  Ok(check_status(cli.check, &report))
+}
 
-  Ok(())
+/// Returns the exit status of a sync, a failure when `--check` finds the file out of sync.
+fn check_status(check: bool, report: &SyncReport) -> ExitCode {
+  if check && report.has_changes() {
+    ExitCode::FAILURE
+  } else {
+    ExitCode::SUCCESS
+  }
 }
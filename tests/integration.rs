@@ -1,5 +1,10 @@
-use env_sync::sync::{EnvSync, EnvSyncOptions};
+use env_sync::diff::Change;
+use env_sync::parse::{EnvFile, Source};
+use env_sync::sync::{EnvSync, EnvSyncError, EnvSyncOptions, SyncListener, TemplateSource};
+use env_sync::{MergePolicy, merge_files};
+use std::cell::RefCell;
 use std::fs;
+use std::rc::Rc;
 use tempfile::TempDir;
 
 #[test]
@@ -26,7 +31,8 @@ NEW_VAR=default # Feature flag";
 
   let options = EnvSyncOptions {
     local_file: Some(local_path.clone()),
-    template_file: template_path,
+    template_source: template_path.into(),
+    ..Default::default()
   };
 
   EnvSync::sync_with_options(options).unwrap();
@@ -43,3 +49,550 @@ NEW_VAR=default # Feature flag
 
   assert_eq!(synced_content, expected);
 }
+
+#[test]
+fn test_sync_with_overrides() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  let template_path = temp_dir.path().join(".env.template");
+
+  fs::write(&local_path, "API_KEY=secret123\nDB_HOST=localhost\n").unwrap();
+  fs::write(&template_path, "API_KEY=\nDB_HOST=production.example.com\n").unwrap();
+
+  let options = EnvSyncOptions {
+    local_file: Some(local_path.clone()),
+    template_source: template_path.into(),
+    overrides: vec![
+      ("DB_HOST".to_string(), "staging.example.com".to_string()),
+      ("EXTRA".to_string(), "1".to_string()),
+    ],
+    ..Default::default()
+  };
+
+  EnvSync::sync_with_options(options).unwrap();
+
+  let synced_content = fs::read_to_string(&local_path).unwrap();
+  assert_eq!(
+    synced_content,
+    "API_KEY=secret123\nDB_HOST=staging.example.com\nEXTRA=1\n"
+  );
+}
+
+type Transition = (String, Option<String>, String);
+
+struct RecordingListener(Rc<RefCell<Vec<Transition>>>);
+
+impl SyncListener for RecordingListener {
+  fn on_change(&self, key: &str, old: Option<&str>, new: &str) {
+    self
+      .0
+      .borrow_mut()
+      .push((key.to_string(), old.map(str::to_string), new.to_string()));
+  }
+}
+
+#[test]
+fn test_sync_listener_transitions() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  let template_path = temp_dir.path().join(".env.template");
+
+  let local_content = "# Database configuration
+API_KEY=secret123 # Keep this secret!
+DB_HOST=localhost
+DB_PORT=";
+  let template_content = "# Database configuration
+API_KEY=
+DB_HOST=production.example.com
+DB_PORT=5432 # Default postgres port
+
+# New feature
+NEW_VAR=default # Feature flag";
+
+  fs::write(&local_path, local_content).unwrap();
+  fs::write(&template_path, template_content).unwrap();
+
+  let transitions = Rc::new(RefCell::new(Vec::new()));
+  let options = EnvSyncOptions {
+    local_file: Some(local_path),
+    template_source: template_path.into(),
+    listener: Some(Box::new(RecordingListener(transitions.clone()))),
+    ..Default::default()
+  };
+
+  EnvSync::sync_with_options(options).unwrap();
+
+  assert_eq!(
+    *transitions.borrow(),
+    vec![
+      (
+        "DB_HOST".to_string(),
+        Some("localhost".to_string()),
+        "production.example.com".to_string()
+      ),
+      (
+        "DB_PORT".to_string(),
+        Some(String::new()),
+        "5432".to_string()
+      ),
+      ("NEW_VAR".to_string(), None, "default".to_string()),
+    ]
+  );
+}
+
+#[test]
+fn test_sync_listener_skips_failed_write() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  fs::write(&local_path, "DB_HOST=localhost\n").unwrap();
+  // A directory where the backup goes makes the sync fail before writing
+  fs::create_dir(temp_dir.path().join(".env.bak")).unwrap();
+
+  let transitions = Rc::new(RefCell::new(Vec::new()));
+  let sync = |backup| {
+    EnvSync::sync_with_options(EnvSyncOptions {
+      local_file: Some(local_path.clone()),
+      template_source: TemplateSource::Inline("DB_HOST=\nDB_PORT=5432\n".to_string()),
+      listener: Some(Box::new(RecordingListener(transitions.clone()))),
+      backup,
+      ..Default::default()
+    })
+  };
+
+  assert!(sync(true).is_err());
+  assert!(transitions.borrow().is_empty());
+  assert_eq!(
+    fs::read_to_string(&local_path).unwrap(),
+    "DB_HOST=localhost\n"
+  );
+
+  assert!(sync(false).unwrap().written);
+  assert_eq!(transitions.borrow().len(), 1);
+  assert!(!sync(false).unwrap().written);
+  assert_eq!(transitions.borrow().len(), 1);
+}
+
+#[cfg(feature = "diff")]
+#[test]
+fn test_dry_run_diff_preview() {
+  use std::process::Command;
+
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  let template_path = temp_dir.path().join(".env.template");
+
+  let local_content = "API_KEY=secret123\nDB_HOST=localhost\n";
+  fs::write(&local_path, local_content).unwrap();
+  fs::write(
+    &template_path,
+    "API_KEY=\nDB_HOST=production.example.com\nNEW_VAR=default\n",
+  )
+  .unwrap();
+
+  let output = Command::new(env!("CARGO_BIN_EXE_env-sync"))
+    .arg("--local")
+    .arg(&local_path)
+    .arg("--template")
+    .arg(&template_path)
+    .args(["--dry-run", "--diff"])
+    .output()
+    .unwrap();
+
+  assert!(output.status.success());
+  let stdout = String::from_utf8(output.stdout).unwrap();
+  assert!(stdout.contains("-DB_HOST=localhost\n"));
+  assert!(stdout.contains("+DB_HOST=production.example.com\n"));
+  assert!(stdout.contains("+NEW_VAR=default\n"));
+  assert!(stdout.contains(" API_KEY=secret123\n"));
+
+  assert_eq!(fs::read_to_string(&local_path).unwrap(), local_content);
+  assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+}
+
+#[test]
+fn test_dry_run_check() {
+  use std::process::Command;
+
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  let template_path = temp_dir.path().join(".env.template");
+
+  let local_content = "API_KEY=secret123\nDB_HOST=localhost\n";
+  fs::write(&local_path, local_content).unwrap();
+  fs::write(
+    &template_path,
+    "API_KEY=\nDB_HOST=production.example.com\nNEW_VAR=default\n",
+  )
+  .unwrap();
+
+  let run = |check: bool| {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_env-sync"));
+    command
+      .arg("--local")
+      .arg(&local_path)
+      .arg("--template")
+      .arg(&template_path)
+      .arg("--dry-run");
+    if check {
+      command.arg("--check");
+    }
+    command.output().unwrap()
+  };
+
+  let output = run(false);
+  assert!(output.status.success());
+  assert_eq!(
+    String::from_utf8(output.stdout).unwrap(),
+    "~ DB_HOST: localhost -> production.example.com\n+ NEW_VAR=default\n"
+  );
+  assert!(!run(true).status.success());
+  assert_eq!(fs::read_to_string(&local_path).unwrap(), local_content);
+
+  fs::write(
+    &local_path,
+    "API_KEY=secret123\nDB_HOST=production.example.com\nNEW_VAR=default\n",
+  )
+  .unwrap();
+  let output = run(true);
+  assert!(output.status.success());
+  assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_check_flag() {
+  use std::process::Command;
+
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  let template_path = temp_dir.path().join(".env.template");
+
+  let local_content = "API_KEY=secret123\nDB_HOST=localhost\n";
+  fs::write(&local_path, local_content).unwrap();
+  fs::write(
+    &template_path,
+    "API_KEY=\nDB_HOST=production.example.com\nNEW_VAR=default\n",
+  )
+  .unwrap();
+
+  let check_with = |args: &[&str]| {
+    Command::new(env!("CARGO_BIN_EXE_env-sync"))
+      .arg("--local")
+      .arg(&local_path)
+      .arg("--template")
+      .arg(&template_path)
+      .arg("--check")
+      .args(args)
+      .output()
+      .unwrap()
+  };
+  let check = || check_with(&[]);
+
+  let output = check();
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(
+    String::from_utf8(output.stdout).unwrap(),
+    "DB_HOST is out of sync\nNEW_VAR is out of sync\n"
+  );
+  assert_eq!(fs::read_to_string(&local_path).unwrap(), local_content);
+
+  let patch_path = temp_dir.path().join("changes.patch");
+  let output = check_with(&["--write-patch", patch_path.to_str().unwrap()]);
+  assert_eq!(output.status.code(), Some(2));
+  assert!(!patch_path.exists());
+
+  let output = check_with(&["--report-format", "json"]);
+  assert_eq!(output.status.code(), Some(1));
+  let summary: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+  assert_eq!(summary["added"], serde_json::json!(["NEW_VAR"]));
+  assert_eq!(fs::read_to_string(&local_path).unwrap(), local_content);
+
+  fs::write(&local_path, "DB_HOST=localhost\nAPI_KEY=secret123\n").unwrap();
+  fs::write(&template_path, "API_KEY=\nDB_HOST=\n").unwrap();
+  let output = check();
+  assert_eq!(output.status.code(), Some(1));
+  assert!(
+    String::from_utf8(output.stdout)
+      .unwrap()
+      .ends_with("differs from the synced output\n")
+  );
+
+  fs::write(&local_path, "API_KEY=secret123\nDB_HOST=localhost\n").unwrap();
+  let output = check();
+  assert!(output.status.success());
+  assert!(output.stdout.is_empty());
+
+  fs::write(&local_path, "FLAG=true\n").unwrap();
+  fs::write(&template_path, "FLAG=TRUE\n").unwrap();
+  let output = check();
+  assert_eq!(output.status.code(), Some(1));
+  assert_eq!(
+    String::from_utf8(output.stdout).unwrap(),
+    "FLAG is out of sync\n"
+  );
+
+  let output = check_with(&["--ignore-case-values"]);
+  assert!(output.status.success());
+  assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn test_comment_flag_sets_inline_comment() {
+  use std::process::Command;
+
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  let template_path = temp_dir.path().join(".env.template");
+
+  fs::write(&local_path, "DB_HOST=localhost # old note\n").unwrap();
+  fs::write(&template_path, "DB_HOST=\nDB_PORT=5432\n").unwrap();
+
+  let status = Command::new(env!("CARGO_BIN_EXE_env-sync"))
+    .arg("--local")
+    .arg(&local_path)
+    .arg("--template")
+    .arg(&template_path)
+    .args(["--comment", "DB_HOST=Primary database host"])
+    .args(["--comment", "DB_PORT=Default postgres port"])
+    .status()
+    .unwrap();
+
+  assert!(status.success());
+  assert_eq!(
+    fs::read_to_string(&local_path).unwrap(),
+    "DB_HOST=localhost # Primary database host\nDB_PORT=5432 # Default postgres port\n"
+  );
+}
+
+#[test]
+fn test_delete_flag_removes_template_key() {
+  use std::process::Command;
+
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  let template_path = temp_dir.path().join(".env.template");
+
+  fs::write(&local_path, "DB_HOST=localhost\nLEGACY_URL=http://old\n").unwrap();
+  fs::write(
+    &template_path,
+    "DB_HOST=\n# Deprecated endpoint\nLEGACY_URL=\nDB_PORT=5432\n",
+  )
+  .unwrap();
+
+  let status = Command::new(env!("CARGO_BIN_EXE_env-sync"))
+    .arg("--local")
+    .arg(&local_path)
+    .arg("--template")
+    .arg(&template_path)
+    .args(["--delete", "LEGACY_URL", "--set", "DB_PORT=6543"])
+    .status()
+    .unwrap();
+
+  assert!(status.success());
+  assert_eq!(
+    fs::read_to_string(&local_path).unwrap(),
+    "DB_HOST=localhost\nDB_PORT=6543\n"
+  );
+}
+
+#[test]
+fn test_merge_files_take_theirs() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let base_path = temp_dir.path().join(".env");
+  let overlay_path = temp_dir.path().join(".env.ci");
+  let out_path = temp_dir.path().join(".env.merged");
+
+  fs::write(&base_path, "# Database\nDB_HOST=localhost\nDB_PORT=5432\n").unwrap();
+  fs::write(&overlay_path, "DB_HOST=ci.example.com\nCI=true\n").unwrap();
+
+  let report = merge_files(
+    &base_path,
+    &overlay_path,
+    &out_path,
+    MergePolicy::TakeTheirs,
+  )
+  .unwrap();
+
+  let expected = "# Database\nDB_HOST=ci.example.com\nDB_PORT=5432\nCI=true\n";
+  assert_eq!(fs::read_to_string(&out_path).unwrap(), expected);
+  assert_eq!(report.synced, expected);
+  assert_eq!(report.local_path, out_path);
+  assert_eq!(
+    report.changes,
+    vec![
+      Change::Modified {
+        key: "DB_HOST".to_string(),
+        old: "localhost".to_string(),
+        new: "ci.example.com".to_string(),
+      },
+      Change::Added {
+        key: "CI".to_string(),
+        value: "true".to_string(),
+      },
+    ]
+  );
+  assert_eq!(
+    fs::read_to_string(&base_path).unwrap(),
+    "# Database\nDB_HOST=localhost\nDB_PORT=5432\n"
+  );
+}
+
+#[test]
+fn test_merge_files_overlay_errors() {
+  let temp_dir = TempDir::new().unwrap();
+
+  let base_path = temp_dir.path().join(".env");
+  let overlay_path = temp_dir.path().join(".env.ci");
+  let out_path = temp_dir.path().join(".env.merged");
+
+  fs::write(&base_path, "DB_HOST=localhost\n").unwrap();
+
+  let result = merge_files(&base_path, &overlay_path, &out_path, MergePolicy::TakeOurs);
+  assert!(matches!(result, Err(EnvSyncError::OverlayIo(_))));
+
+  fs::write(
+    &overlay_path,
+    "DB_HOST=ci.example.com\nthis line is broken\n",
+  )
+  .unwrap();
+  let result = merge_files(&base_path, &overlay_path, &out_path, MergePolicy::TakeOurs);
+  assert!(matches!(result, Err(EnvSyncError::OverlayParse(_))));
+  assert!(!out_path.exists());
+}
+
+#[test]
+fn test_sync_provenance() {
+  let local: EnvFile = "# Database configuration
+API_KEY=secret123 # Keep this secret!
+DB_HOST=localhost
+DB_PORT=
+LOCAL_ONLY=1"
+    .try_into()
+    .unwrap();
+  let template: EnvFile = "# Database configuration
+API_KEY=
+DB_HOST=production.example.com
+DB_PORT=5432 # Default postgres port
+LOG_LEVEL=info
+
+# New feature
+NEW_VAR=default # Feature flag"
+    .try_into()
+    .unwrap();
+  let both: EnvFile = "LOG_LEVEL=info".try_into().unwrap();
+
+  let synced = EnvSync::sync_files(&local, template.clone()).unwrap();
+  let source = |key: &str| synced.get(key).unwrap().source();
+  let rendered = synced.to_string();
+  let reparsed = EnvFile::try_from(rendered.as_str()).unwrap();
+  assert_eq!(reparsed, synced);
+  assert!(reparsed.edit_script(&synced).is_empty());
+
+  assert_eq!(source("API_KEY"), Some(Source::Local));
+  assert_eq!(source("DB_HOST"), Some(Source::Template));
+  assert_eq!(source("DB_PORT"), Some(Source::Template));
+  assert_eq!(source("NEW_VAR"), Some(Source::Template));
+  assert_eq!(source("LOCAL_ONLY"), Some(Source::Local));
+
+  let synced = EnvSync::sync_files(&both, template).unwrap();
+  assert_eq!(
+    synced.get("LOG_LEVEL").unwrap().source(),
+    Some(Source::Both)
+  );
+  assert_eq!(local.get("API_KEY").unwrap().source(), None);
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn test_report_format_json() {
+  use std::process::Command;
+
+  let temp_dir = TempDir::new().unwrap();
+
+  let local_path = temp_dir.path().join(".env");
+  let template_path = temp_dir.path().join(".env.template");
+
+  fs::write(
+    &local_path,
+    "API_KEY=secret123\nDB_HOST=localhost\nOLD_KEY=1\n",
+  )
+  .unwrap();
+  fs::write(
+    &template_path,
+    "API_KEY=\nDB_HOST=production.example.com\nNEW_VAR=default\n",
+  )
+  .unwrap();
+
+  let output = Command::new(env!("CARGO_BIN_EXE_env-sync"))
+    .arg("--local")
+    .arg(&local_path)
+    .arg("--template")
+    .arg(&template_path)
+    .args(["--report-format", "json", "--prune"])
+    .output()
+    .unwrap();
+
+  assert!(output.status.success());
+  let report: serde_json::Value = serde_json::from_slice(&output.stdout).unwrap();
+  assert_eq!(report["local_path"], local_path.display().to_string());
+  assert_eq!(report["written"], true);
+  assert_eq!(report["added"], serde_json::json!(["NEW_VAR"]));
+  assert_eq!(report["changed"], serde_json::json!(["DB_HOST"]));
+  assert_eq!(report["unchanged"], serde_json::json!(["API_KEY"]));
+  assert_eq!(report["orphans"], serde_json::json!(["OLD_KEY"]));
+  assert_eq!(report["deprecated"], serde_json::json!([]));
+  assert!(
+    !String::from_utf8(output.stdout)
+      .unwrap()
+      .contains("secret123")
+  );
+}
+
+#[test]
+fn test_validate_subcommand() {
+  use std::process::Command;
+
+  let temp_dir = TempDir::new().unwrap();
+  let path = temp_dir.path().join(".env");
+
+  fs::write(
+    &path,
+    "# Database\nDB_HOST=localhost\nDB-PORT=5432\n DB_HOST =db\nDB_HOST=other\nnot valid\n",
+  )
+  .unwrap();
+
+  let output = Command::new(env!("CARGO_BIN_EXE_env-sync"))
+    .arg("validate")
+    .arg(&path)
+    .output()
+    .unwrap();
+
+  assert!(!output.status.success());
+  let stdout = String::from_utf8(output.stdout).unwrap();
+  let file = path.display();
+  assert_eq!(
+    stdout,
+    format!(
+      "{file}:3: DB-PORT is not a valid variable name\n\
+       {file}:4: DB_HOST is already defined on line 2 with different whitespace\n\
+       {file}:5: DB_HOST is already defined on line 2\n\
+       {file}:6: not a variable, comment, or empty line\n"
+    )
+  );
+
+  fs::write(&path, "# Database\nDB_HOST=localhost\n").unwrap();
+  let status = Command::new(env!("CARGO_BIN_EXE_env-sync"))
+    .arg("validate")
+    .arg(&path)
+    .status()
+    .unwrap();
+  assert!(status.success());
+}
@@ -0,0 +1,81 @@
+//! Property tests checking that rendering a parsed file and parsing it again gives the same
+//! structure.
+
+use env_sync::parse::EnvFile;
+use proptest::prelude::*;
+
+/// Keys, sometimes written with an `export ` prefix.
+fn key() -> impl Strategy<Value = String> {
+  (prop::bool::weighted(0.2), "[A-Z_][A-Z0-9_]{0,8}").prop_map(|(exported, key)| {
+    if exported {
+      format!("export {}", key)
+    } else {
+      key
+    }
+  })
+}
+
+/// Unquoted, quoted and empty values.
+fn value() -> impl Strategy<Value = String> {
+  prop_oneof![
+    Just(String::new()),
+    "[a-z0-9:/._-]{1,12}",
+    "[a-z0-9 ._-]{0,12}".prop_map(|value| format!("\"{}\"", value)),
+    "[a-z0-9 ._-]{0,12}".prop_map(|value| format!("'{}'", value)),
+    "[a-z0-9 #=$\\\\{}\"'-]{1,12}",
+  ]
+}
+
+/// Spacing that can surround markers and operators.
+fn spacing() -> impl Strategy<Value = &'static str> {
+  prop_oneof![Just(""), Just(" "), Just("  "), Just("\t")]
+}
+
+/// A single line or multiline value, biased toward comments, blank lines and inline comments.
+fn line() -> impl Strategy<Value = String> {
+  prop_oneof![
+    2 => spacing().prop_map(str::to_string),
+    3 => (spacing(), spacing(), "[a-zA-Z0-9 #=@-]{0,16}")
+      .prop_map(|(indent, space, text)| format!("{}#{}{}", indent, space, text)),
+    1 => "#{3,12}",
+    4 => (key(), spacing(), spacing(), value(), spacing())
+      .prop_map(|(key, before, after, value, trailing)| {
+        format!("{}{}={}{}{}", key, before, after, value, trailing)
+      }),
+    3 => (key(), value(), spacing(), spacing(), "[a-z0-9 ]{0,10}")
+      .prop_map(|(key, value, gap, space, comment)| {
+        format!("{}={}{} #{}{}", key, value, gap, space, comment)
+      }),
+    1 => (key(), prop::collection::vec("[a-z0-9 #=]{0,10}", 1..4))
+      .prop_map(|(key, lines)| format!("{}=\"{}\"", key, lines.join("\n"))),
+    1 => (key(), prop::collection::vec("[a-z0-9 #=\"]{0,10}", 0..4))
+      .prop_map(|(key, lines)| format!("{}<<EOF\n{}\nEOF", key, lines.join("\n"))),
+  ]
+}
+
+fn env_file() -> impl Strategy<Value = String> {
+  (prop::collection::vec(line(), 0..24), prop::bool::ANY).prop_map(|(lines, newline)| {
+    let mut content = lines.join("\n");
+    if newline {
+      content.push('\n');
+    }
+    content
+  })
+}
+
+proptest! {
+  #![proptest_config(ProptestConfig::with_cases(512))]
+
+  #[test]
+  fn test_render_roundtrip(content in env_file()) {
+    let Ok(parsed) = EnvFile::try_from(content.as_str()) else {
+      return Ok(());
+    };
+    let rendered = parsed.to_string();
+    let reparsed = EnvFile::try_from(rendered.as_str())
+      .unwrap_or_else(|error| panic!("rendered file doesn't parse ({}):\n{}", error, rendered));
+
+    prop_assert_eq!(&reparsed, &parsed, "rendered as:\n{}", rendered);
+    prop_assert_eq!(reparsed.to_string(), rendered);
+  }
+}